@@ -3,7 +3,7 @@ mod cli;
 use std::{
     fs::{File},
     io::{BufReader, Read},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::Command,
     time::{Duration},
 };
@@ -11,8 +11,14 @@ use std::{
 use clap::Parser;
 
 use libaflstar::{
+    child_output::{ChildOutput, ChildOutputFeedback, ChildOutputObserver},
+    crash_dedup::CrashDedupFeedback,
+    crash_record::{Conversation, ConversationObserver, CrashRecordFeedback},
     event_manager:: LibAFLStarManager,
     executor::{forkserver::ForkserverExecutor, ResettableForkserver, StatefulPersistentExecutor},
+    feedbacks::{OomFeedback, SeparateSolutionsFeedback},
+    port,
+    replay::TraceReader,
     state::{LibAFLStarState, Prefix, PrefixMetadata},
 };
 use libafl::{
@@ -36,6 +42,42 @@ use libafl_bolts::{
     AsMutSlice, Error, Truncate,
 };
 
+/// Read the messages of a trace, with the time (in seconds since the UNIX epoch) each was sent, if known.
+///
+/// LibAFLstar traces (`.cbor`, `.cbor.gz`) record when each message was sent, AFLNet traces (`<len><bytes>`) do not.
+fn read_messages(path: &Path) -> Result<Vec<(Vec<u8>, Option<u64>)>, Error> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if name.ends_with(".cbor") || name.ends_with(".cbor.gz") {
+        let reader = TraceReader::open(path)?;
+        let start_time = reader.header().map_or(0, |header| header.start_time);
+        let mut messages = Vec::new();
+        for message in reader {
+            let message = message?;
+            let time = if message.time != 0 {
+                message.time
+            } else {
+                start_time
+            };
+            messages.push((message.req, (time != 0).then_some(time / 1000)));
+        }
+        return Ok(messages);
+    }
+
+    let mut trace_file = BufReader::new(File::open(path)?);
+    let mut messages = Vec::new();
+    loop {
+        let mut size = [0u8; 4];
+        match trace_file.read_exact(&mut size) {
+            Ok(()) => {}     // more messages
+            Err(_) => break, // end of file?
+        }
+        let mut buf = vec![0u8; u32::from_le_bytes(size) as usize];
+        trace_file.read_exact(&mut buf)?;
+        messages.push((buf, None));
+    }
+    Ok(messages)
+}
+
 #[allow(clippy::similar_names)]
 fn main() -> Result<(), Error> {
     env_logger::init();
@@ -79,6 +121,14 @@ fn main() -> Result<(), Error> {
     // Create an observation channel to keep track of the execution time
     let time_observer = TimeObserver::new("time");
 
+    // Create an observation channel to capture the output of the target, it is stored next to the solutions
+    let child_output = ChildOutput::default();
+    let child_output_observer = ChildOutputObserver::new("child_output", child_output.clone());
+
+    // Create an observation channel that keeps the conversation with the target, it is stored with the solutions
+    let conversation = Conversation::default();
+    let conversation_observer = ConversationObserver::new("conversation", conversation.clone());
+
     // Feedback to rate the interestingness of an input
     // This one is composed by two Feedbacks in OR
     let mut feedback = feedback_or!(
@@ -90,12 +140,35 @@ fn main() -> Result<(), Error> {
 
     // A feedback to choose if an input is a solution or not
     // We want to do the same crash deduplication that AFL does
-    let mut objective = feedback_and_fast!(
-        // Must be a crash
-        CrashFeedback::new(),
-        // Take it only if trigger new coverage over crashes
-        // Uses `with_name` to create a different history from the `MaxMapFeedback` in `feedback` above
-        MaxMapFeedback::with_name("mapfeedback_metadata_objective", &edges_observer)
+    let mut objective = feedback_or!(
+        feedback_and_fast!(
+            // Must be a crash
+            CrashFeedback::new(),
+            // Take it only if it is a new bug, over the crashes of all target states.
+            // Crashes are bucketed by the stack trace of the sanitizer report, without one they must reach new coverage
+            CrashDedupFeedback::new(
+                &edges_observer,
+                Some(&child_output_observer),
+                out_dir.join("crash_buckets.txt"),
+            )
+        ),
+        // Never interesting by itself, but stores the output of the target next to the solution
+        ChildOutputFeedback::new(&child_output_observer, out_dir.join("crashes")),
+        // Same, but stores the target state and the conversation that led to the solution
+        CrashRecordFeedback::new(&conversation_observer, out_dir.join("crashes")),
+        // Inputs that make the target run out of memory are no crashes, they are stored apart in `ooms/`
+        SeparateSolutionsFeedback::new(
+            "oom_solutions",
+            feedback_or!(
+                feedback_and_fast!(
+                    OomFeedback::new(),
+                    MaxMapFeedback::with_name("mapfeedback_metadata_oom", &edges_observer)
+                ),
+                ChildOutputFeedback::new(&child_output_observer, out_dir.join("ooms")),
+                CrashRecordFeedback::new(&conversation_observer, out_dir.join("ooms"))
+            ),
+            out_dir.join("ooms"),
+        )?
     );
 
     let monitor = OnDiskJSONMonitor::new(
@@ -114,8 +187,23 @@ fn main() -> Result<(), Error> {
     // If we should debug the child
     let debug_child = cli.debug_child;
 
+    // Pick the port of the target and fill it in wherever the placeholder is used
+    let target_port = match cli.target_port {
+        Some(port) => port,
+        None => port::find_free_port()?,
+    };
+    port::record_port(&out_dir, target_port)?;
+    log::info!("Target port: {target_port}");
+    for (template, destination) in cli.config_templates.iter().flatten() {
+        port::render_template(template, destination, target_port)?;
+    }
+
     // Create the executor for the forkserver
-    let args = cli.arguments;
+    let args: Vec<String> = cli
+        .arguments
+        .iter()
+        .map(|arg| port::substitute_port(arg, target_port))
+        .collect();
 
     // Kill signal to kill the target:
     let kill_signal = cli.signal;
@@ -124,20 +212,33 @@ fn main() -> Result<(), Error> {
 
     let mut frsv_builder = ForkserverExecutor::builder();
     if let Some(env_vars) = cli.environment_variables {
-        frsv_builder = frsv_builder.envs(env_vars);
+        frsv_builder = frsv_builder.envs(
+            env_vars
+                .into_iter()
+                .map(|(key, val)| (key, port::substitute_port(&val, target_port))),
+        );
     }
 
     let mut fsrv_executor = frsv_builder
         .program(cli.executable)
         .debug_child(debug_child)
-        .socket_client_port(cli.target_port)
+        .socket_client_port(target_port)
+        .network_namespace(cli.netns)
         .autotokens(&mut tokens)
         .is_persistent(true)
         .timeout(timeout_duration)
         .parse_afl_cmdline(args)
         .coverage_map_size(MAP_SIZE)
         .kill_signal(kill_signal)
-        .build(tuple_list!(time_observer, edges_observer))
+        .capture_child_output(child_output)
+        .record_conversation(conversation)
+        .memlimit(cli.mem_limit, cli.mem_limit_mode)
+        .build(tuple_list!(
+            time_observer,
+            edges_observer,
+            child_output_observer,
+            conversation_observer
+        ))
         .expect("Building forkserver");
 
     if let Some(dynamic_map_size) = fsrv_executor.coverage_map_size() {
@@ -158,7 +259,12 @@ fn main() -> Result<(), Error> {
 
     let prefixes = vec![Prefix {
         prefix: Vec::new(),
-        metadata: PrefixMetadata { outgoing_edges: 0 },
+        metadata: PrefixMetadata {
+            outgoing_edges: 0,
+            name: "empty".to_string(),
+            prefix_hash: 0,
+            tags: Default::default(),
+        },
     }];
 
     // create the LibAFLStarState
@@ -189,7 +295,7 @@ fn main() -> Result<(), Error> {
     let num_files = files.len();
     for (i, file) in files.into_iter().enumerate() {
         let file = file.path();
-        if file.is_dir() || file.ends_with("trace_0.cbor") {
+        if file.is_dir() || file.ends_with("trace_0.cbor") || file.ends_with("trace_0.cbor.gz") {
             continue;
         }
         println!(
@@ -198,16 +304,14 @@ fn main() -> Result<(), Error> {
             num_files,
             &file.file_name()
         );
-        let mut trace_file = BufReader::new(File::open(file)?);
+        // Messages without a timestamp of their own get the modification time of the trace file
+        let modified = file
+            .metadata()?
+            .modified()
+            .ok()
+            .map(|modified| modified.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs());
         let mut new_cov = false;
-        loop {
-            let mut size = [0u8; 4];
-            match trace_file.read_exact(&mut size) {
-                Ok(()) => {}     // more messages
-                Err(_) => break, // end of file?
-            }
-            let mut buf = vec![0u8; u32::from_le_bytes(size) as usize];
-            trace_file.read_exact(&mut buf)?;
+        for (buf, sent_at) in read_messages(&file)? {
             {
                 let input = BytesInput::new(buf);
 
@@ -231,14 +335,12 @@ fn main() -> Result<(), Error> {
                     //Calculate the percentage
                     let percentage = (current_edges as f64 / total_edges as f64) * 100.0;
                     let cov_str = format!("{:.2}%", percentage);
-                    let file_metadata = trace_file.get_ref().metadata()?;
-                    if let Ok(created) = file_metadata.modified() {
-                        let unix_timestamp = created.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+                    if let Some(unix_timestamp) = sent_at.or(modified) {
                         //Append the timestamp, coverage and the current and total edges
                         csv_buf.push_str(&format!("{},{},{},{}\n", unix_timestamp, cov_str, current_edges, total_edges));
                     } else {
-                        // If the creation time is not available, print a warning
-                        println!("Warn: Could not get the creation time of the trace file");
+                        // If the time is not available, print a warning
+                        println!("Warn: Could not get the time of the message or the trace file");
                     }
                     
                 }
@@ -0,0 +1,149 @@
+//! The command line interface of the crash verifier
+
+use std::{error::Error, path::PathBuf};
+
+use clap::Parser;
+use libaflstar::executor::memlimit::MemLimitMode;
+use nix::sys::signal::Signal;
+
+#[derive(Debug, Parser)]
+#[command(about = "Checks if the crashes found by the fuzzer can be reproduced")]
+pub struct Cli {
+    #[arg(
+        help = "The instrumented binary we want to fuzz",
+        name = "EXEC",
+        required = true
+    )]
+    pub executable: String,
+
+    #[arg(
+        help = "Arguments passed to the target",
+        name = "arguments",
+        num_args(1..),
+        allow_hyphen_values = true,
+    )]
+    pub arguments: Vec<String>,
+
+    #[arg(
+        help = "A crash record (<solution>.record.cbor) or a directory of solutions (e.g. crashes/), in which case all crash records in it are verified",
+        short = 'i',
+        long = "in",
+        required = true
+    )]
+    pub input: PathBuf,
+
+    #[arg(
+        help = "The directory to store all outputs in",
+        short = 'o',
+        long = "out-dir",
+        required = true
+    )]
+    pub out_dir: PathBuf,
+
+    #[arg(
+        help = "How many times each crash is replayed, both with and without its history",
+        short = 'n',
+        long = "attempts",
+        default_value = "10"
+    )]
+    pub attempts: usize,
+
+    #[arg(
+        help = "Timeout for each individual execution, in milliseconds",
+        short = 't',
+        long = "timeout",
+        default_value = "1200"
+    )]
+    pub timeout: u64,
+
+    #[arg(
+        help = "If not set, the child's stdout and stderror will be redirected to /dev/null",
+        short = 'd',
+        long = "debug-child",
+        default_value = "false"
+    )]
+    pub debug_child: bool,
+
+    #[arg(
+        help = "Environment variables passed to the target",
+        short = 'e',
+        long = "target-env",
+        value_parser = parse_key_val_pairs::<String, String>,
+    )]
+    pub environment_variables: Option<std::vec::Vec<(String, String)>>,
+
+    #[arg(
+        help = "Port the target uses. If not given, a free port is picked, which is filled in wherever @@PORT@@ is used in the target's arguments, environment variables and config templates",
+        short = 'p',
+        long = "target-port"
+    )]
+    pub target_port: Option<u16>,
+
+    #[arg(
+        help = "Config file templates in which @@PORT@@ is replaced by the target port, as TEMPLATE=DESTINATION pairs",
+        long = "config-template",
+        value_parser = parse_key_val_pairs::<PathBuf, PathBuf>,
+    )]
+    pub config_templates: Option<std::vec::Vec<(PathBuf, PathBuf)>>,
+
+    #[arg(
+        help = "Run the target in its own network namespace with only loopback up, so instances can share a port and the target cannot reach the network (requires root)",
+        long = "netns",
+        default_value = "false"
+    )]
+    pub netns: bool,
+
+    #[arg(
+        help = "Memory limit of the target in MB, 0 means no limit",
+        long = "mem-limit",
+        default_value = "0"
+    )]
+    pub mem_limit: u64,
+
+    #[arg(
+        help = "How the memory limit is enforced: rlimit limits the address space (does not go well with ASAN), cgroup limits the used memory (requires root)",
+        long = "mem-limit-mode",
+        value_enum,
+        default_value = "rlimit"
+    )]
+    pub mem_limit_mode: MemLimitMode,
+
+    #[arg(
+        help = "Signal used to stop child",
+        short = 's',
+        long = "signal",
+        value_parser = str::parse::<Signal>,
+        default_value = "SIGKILL"
+    )]
+    pub signal: Signal,
+}
+
+/// Parse a list of key-value pairs
+fn parse_key_val_pairs<T, U>(
+    strs: &str,
+) -> Result<Vec<(T, U)>, Box<dyn Error + Send + Sync + 'static>>
+where
+    T: std::str::FromStr,
+    T::Err: Error + Send + Sync + 'static,
+    U: std::str::FromStr,
+    U::Err: Error + Send + Sync + 'static,
+{
+    let mut v = Vec::new();
+    for s in strs.split(',') {
+        v.push(parse_key_val(s)?);
+    }
+    Ok(v)
+}
+/// Parse a single key-value pair
+fn parse_key_val<T, U>(s: &str) -> Result<(T, U), Box<dyn Error + Send + Sync + 'static>>
+where
+    T: std::str::FromStr,
+    T::Err: Error + Send + Sync + 'static,
+    U: std::str::FromStr,
+    U::Err: Error + Send + Sync + 'static,
+{
+    let pos = s
+        .find('=')
+        .ok_or_else(|| format!("invalid KEY=value: no `=` found in `{s}`"))?;
+    Ok((s[..pos].parse()?, s[pos + 1..].parse()?))
+}
@@ -0,0 +1,219 @@
+mod cli;
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::Duration,
+};
+
+use clap::Parser;
+
+use libaflstar::{
+    child_output::ChildOutput,
+    crash_record::{is_crash_record, CrashRecord},
+    event_manager::LibAFLStarManager,
+    executor::{forkserver::ForkserverExecutor, StatefulPersistentExecutor},
+    port,
+    replay::input_files,
+    state::{LibAFLStarState, Prefix, PrefixMetadata},
+    verify::{verify_record, Reproducibility},
+};
+use libafl::{
+    corpus::InMemoryCorpus,
+    executors::HasObservers,
+    fuzzer::StdFuzzer,
+    inputs::BytesInput,
+    monitors::MultiMonitor,
+    observers::{HitcountsMapObserver, StdMapObserver, TimeObserver},
+    schedulers::QueueScheduler,
+};
+use libafl_bolts::{
+    current_nanos,
+    rands::StdRand,
+    shmem::{ShMem, ShMemProvider, UnixShMemProvider},
+    tuples::{tuple_list, MatchName},
+    AsMutSlice, Error, Truncate,
+};
+
+#[allow(clippy::similar_names)]
+fn main() -> Result<(), Error> {
+    env_logger::init();
+
+    const MAP_SIZE: usize = 65536;
+
+    let cli = cli::Cli::parse();
+
+    // Get out dir ready
+    let out_dir = cli.out_dir;
+    if out_dir.exists() {
+        if out_dir.read_dir()?.next().is_some() {
+            return Err(Error::illegal_argument(format!(
+                "OUT_DIR [{}] must be empty or not exist.",
+                out_dir.display()
+            )));
+        }
+    } else {
+        std::fs::create_dir(&out_dir)?;
+    }
+
+    // the given file, or all crash records in the given directory
+    let records = input_files(cli.input, is_crash_record)?;
+    if records.is_empty() {
+        println!("No crash records found");
+        return Ok(());
+    }
+
+    let timeout_duration = Duration::from_millis(cli.timeout);
+
+    // The unix shmem provider supported by AFL++ for shared memory
+    let mut shmem_provider = UnixShMemProvider::new().unwrap();
+
+    // The coverage map shared between observer and executor
+    let mut shmem = shmem_provider.new_shmem(MAP_SIZE).unwrap();
+    // let the forkserver know the shmid
+    shmem.write_to_env("__AFL_SHM_ID").unwrap();
+    let shmem_buf = shmem.as_mut_slice();
+
+    // Create an observation channel using the signals map
+    let edges_observer =
+        unsafe { HitcountsMapObserver::new(StdMapObserver::new("shared_mem", shmem_buf)) };
+
+    // Create an observation channel to keep track of the execution time
+    let time_observer = TimeObserver::new("time");
+
+    // The output of the target is captured to recognize sanitizer reports of failed allocations as out-of-memory
+    let child_output = ChildOutput::default();
+
+    // Nothing is stored, we only look at how each execution ends
+    let mut feedback = ();
+    let mut objective = ();
+
+    let monitor = MultiMonitor::new(|s| log::info!("{s}"));
+
+    // The event manager handle the various events generated during the fuzzing loop
+    let mut mgr = LibAFLStarManager::new(monitor);
+
+    // Pick the port of the target and fill it in wherever the placeholder is used
+    let target_port = match cli.target_port {
+        Some(port) => port,
+        None => port::find_free_port()?,
+    };
+    port::record_port(&out_dir, target_port)?;
+    log::info!("Target port: {target_port}");
+    for (template, destination) in cli.config_templates.iter().flatten() {
+        port::render_template(template, destination, target_port)?;
+    }
+
+    // Create the executor for the forkserver
+    let args: Vec<String> = cli
+        .arguments
+        .iter()
+        .map(|arg| port::substitute_port(arg, target_port))
+        .collect();
+
+    let mut frsv_builder = ForkserverExecutor::builder();
+    if let Some(env_vars) = cli.environment_variables {
+        frsv_builder = frsv_builder.envs(
+            env_vars
+                .into_iter()
+                .map(|(key, val)| (key, port::substitute_port(&val, target_port))),
+        );
+    }
+
+    let mut fsrv_executor = frsv_builder
+        .program(cli.executable)
+        .debug_child(cli.debug_child)
+        .socket_client_port(target_port)
+        .network_namespace(cli.netns)
+        .is_persistent(true)
+        .timeout(timeout_duration)
+        .parse_afl_cmdline(args)
+        .coverage_map_size(MAP_SIZE)
+        .kill_signal(cli.signal)
+        .capture_child_output(child_output)
+        .memlimit(cli.mem_limit, cli.mem_limit_mode)
+        .build(tuple_list!(time_observer, edges_observer))
+        .expect("Building forkserver");
+
+    if let Some(dynamic_map_size) = fsrv_executor.coverage_map_size() {
+        fsrv_executor
+            .observers_mut()
+            .match_name_mut::<HitcountsMapObserver<StdMapObserver<'_, u8, false>>>("shared_mem")
+            .unwrap()
+            .truncate(dynamic_map_size);
+    }
+
+    let mut executor = StatefulPersistentExecutor::new(fsrv_executor);
+
+    // The messages of the records are sent as they are, so the state needs no prefixes of its own
+    let prefixes = vec![Prefix {
+        prefix: Vec::new(),
+        metadata: PrefixMetadata {
+            outgoing_edges: 0,
+            name: "empty".to_string(),
+            prefix_hash: 0,
+            tags: Default::default(),
+        },
+    }];
+
+    let mut state = LibAFLStarState::new(
+        StdRand::with_seed(current_nanos()),
+        vec![InMemoryCorpus::<BytesInput>::new()],
+        InMemoryCorpus::new(),
+        &mut feedback,
+        &mut objective,
+        prefixes,
+    )?;
+
+    let mut fuzzer = StdFuzzer::new(QueueScheduler::new(), feedback, objective);
+
+    let mut report = BufWriter::new(File::create(out_dir.join("verification.txt"))?);
+    let (mut reproducible, mut flaky, mut history_needed) = (0, 0, 0);
+    for path in &records {
+        let record = CrashRecord::from_file(path)?;
+        if record.history_truncated {
+            log::warn!(
+                "The history of {} is truncated, it may not reproduce",
+                path.display()
+            );
+        }
+        let verification = verify_record(
+            &mut executor,
+            &mut fuzzer,
+            &mut state,
+            &mut mgr,
+            &record,
+            cli.attempts,
+        )?;
+
+        let line = format!(
+            "{} state={} ({}) exit_kind={} with_history={} bare={} history_needed={}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            record.state_idx,
+            record.state_name,
+            record.exit_kind,
+            verification.with_history,
+            verification.bare,
+            verification.history_needed()
+        );
+        println!("{line}");
+        writeln!(report, "{line}")?;
+
+        match verification.with_history.reproducibility() {
+            Reproducibility::Reproducible => reproducible += 1,
+            Reproducibility::Flaky => flaky += 1,
+            Reproducibility::NonReproducible => {}
+        }
+        if verification.history_needed() {
+            history_needed += 1;
+        }
+    }
+    report.flush()?;
+
+    println!(
+        "Verified {} crashes: {reproducible} reproducible, {flaky} flaky, {} non-reproducible, {history_needed} need their history",
+        records.len(),
+        records.len() - reproducible - flaky
+    );
+    Ok(())
+}
@@ -0,0 +1,168 @@
+//! The command line interface of the differential replayer
+
+use std::{error::Error, path::PathBuf};
+
+use clap::Parser;
+use libaflstar::executor::memlimit::MemLimitMode;
+use nix::sys::signal::Signal;
+
+#[derive(Debug, Parser)]
+#[command(about = "Replays traces against two builds of a target and reports where they behave differently")]
+pub struct Cli {
+    #[arg(
+        help = "The instrumented binary of build A",
+        name = "EXEC",
+        required = true
+    )]
+    pub executable: String,
+
+    #[arg(
+        help = "Arguments passed to build A",
+        name = "arguments",
+        num_args(1..),
+        allow_hyphen_values = true,
+    )]
+    pub arguments: Vec<String>,
+
+    #[arg(
+        help = "The instrumented binary of build B",
+        long = "exec-b",
+        required = true
+    )]
+    pub executable_b: String,
+
+    #[arg(
+        help = "Arguments passed to build B, separated by spaces. Defaults to the arguments of build A",
+        long = "args-b",
+        allow_hyphen_values = true
+    )]
+    pub arguments_b: Option<String>,
+
+    #[arg(
+        help = "A replay trace (trace_N.cbor) or a directory of traces (e.g. replay_traces/)",
+        short = 'i',
+        long = "in",
+        required = true
+    )]
+    pub input: PathBuf,
+
+    #[arg(
+        help = "The directory to store all outputs in",
+        short = 'o',
+        long = "out-dir",
+        required = true
+    )]
+    pub out_dir: PathBuf,
+
+    #[arg(
+        help = "Timeout for each individual execution, in milliseconds",
+        short = 't',
+        long = "timeout",
+        default_value = "1200"
+    )]
+    pub timeout: u64,
+
+    #[arg(
+        help = "If not set, the child's stdout and stderror will be redirected to /dev/null",
+        short = 'd',
+        long = "debug-child",
+        default_value = "false"
+    )]
+    pub debug_child: bool,
+
+    #[arg(
+        help = "Environment variables passed to both builds",
+        short = 'e',
+        long = "target-env",
+        value_parser = parse_key_val_pairs::<String, String>,
+    )]
+    pub environment_variables: Option<std::vec::Vec<(String, String)>>,
+
+    #[arg(
+        help = "Port build A uses. If not given, a free port is picked, which is filled in wherever @@PORT@@ is used in its arguments, environment variables and config templates",
+        short = 'p',
+        long = "target-port"
+    )]
+    pub target_port: Option<u16>,
+
+    #[arg(
+        help = "Port build B uses, like --target-port",
+        long = "target-port-b"
+    )]
+    pub target_port_b: Option<u16>,
+
+    #[arg(
+        help = "Config file templates of build A in which @@PORT@@ is replaced by its port, as TEMPLATE=DESTINATION pairs",
+        long = "config-template",
+        value_parser = parse_key_val_pairs::<PathBuf, PathBuf>,
+    )]
+    pub config_templates: Option<std::vec::Vec<(PathBuf, PathBuf)>>,
+
+    #[arg(
+        help = "Config file templates of build B, like --config-template",
+        long = "config-template-b",
+        value_parser = parse_key_val_pairs::<PathBuf, PathBuf>,
+    )]
+    pub config_templates_b: Option<std::vec::Vec<(PathBuf, PathBuf)>>,
+
+    #[arg(
+        help = "Run the target in its own network namespace with only loopback up, so instances can share a port and the target cannot reach the network (requires root)",
+        long = "netns",
+        default_value = "false"
+    )]
+    pub netns: bool,
+
+    #[arg(
+        help = "Memory limit of the target in MB, 0 means no limit",
+        long = "mem-limit",
+        default_value = "0"
+    )]
+    pub mem_limit: u64,
+
+    #[arg(
+        help = "How the memory limit is enforced: rlimit limits the address space (does not go well with ASAN), cgroup limits the used memory (requires root)",
+        long = "mem-limit-mode",
+        value_enum,
+        default_value = "rlimit"
+    )]
+    pub mem_limit_mode: MemLimitMode,
+
+    #[arg(
+        help = "Signal used to stop child",
+        short = 's',
+        long = "signal",
+        value_parser = str::parse::<Signal>,
+        default_value = "SIGKILL"
+    )]
+    pub signal: Signal,
+}
+
+/// Parse a list of key-value pairs
+fn parse_key_val_pairs<T, U>(
+    strs: &str,
+) -> Result<Vec<(T, U)>, Box<dyn Error + Send + Sync + 'static>>
+where
+    T: std::str::FromStr,
+    T::Err: Error + Send + Sync + 'static,
+    U: std::str::FromStr,
+    U::Err: Error + Send + Sync + 'static,
+{
+    let mut v = Vec::new();
+    for s in strs.split(',') {
+        v.push(parse_key_val(s)?);
+    }
+    Ok(v)
+}
+/// Parse a single key-value pair
+fn parse_key_val<T, U>(s: &str) -> Result<(T, U), Box<dyn Error + Send + Sync + 'static>>
+where
+    T: std::str::FromStr,
+    T::Err: Error + Send + Sync + 'static,
+    U: std::str::FromStr,
+    U::Err: Error + Send + Sync + 'static,
+{
+    let pos = s
+        .find('=')
+        .ok_or_else(|| format!("invalid KEY=value: no `=` found in `{s}`"))?;
+    Ok((s[..pos].parse()?, s[pos + 1..].parse()?))
+}
@@ -0,0 +1,283 @@
+mod cli;
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::Duration,
+};
+
+use clap::Parser;
+
+use libaflstar::{
+    differential::{compare, run_message, CoverageTracker, Difference},
+    event_manager::LibAFLStarManager,
+    executor::{forkserver::ForkserverExecutor, ResettableForkserver, StatefulPersistentExecutor},
+    port,
+    replay::{input_files, is_trace, TraceReader},
+    state::{LibAFLStarState, Prefix, PrefixMetadata},
+};
+use libafl::{
+    corpus::InMemoryCorpus,
+    executors::{ExitKind, HasObservers},
+    fuzzer::StdFuzzer,
+    inputs::BytesInput,
+    monitors::MultiMonitor,
+    observers::{HitcountsMapObserver, StdMapObserver, TimeObserver},
+    schedulers::QueueScheduler,
+};
+use libafl_bolts::{
+    current_nanos,
+    rands::StdRand,
+    shmem::{ShMem, ShMemProvider, UnixShMemProvider},
+    tuples::{tuple_list, MatchName},
+    AsMutSlice, Error, Truncate,
+};
+
+type EdgesObserver<'a> = HitcountsMapObserver<StdMapObserver<'a, u8, false>>;
+
+#[allow(clippy::similar_names)]
+fn main() -> Result<(), Error> {
+    env_logger::init();
+
+    const MAP_SIZE: usize = 65536;
+
+    let cli = cli::Cli::parse();
+
+    // Get out dir ready
+    let out_dir = cli.out_dir;
+    if out_dir.exists() {
+        if out_dir.read_dir()?.next().is_some() {
+            return Err(Error::illegal_argument(format!(
+                "OUT_DIR [{}] must be empty or not exist.",
+                out_dir.display()
+            )));
+        }
+    } else {
+        std::fs::create_dir(&out_dir)?;
+    }
+
+    // the given file, or all traces in the given directory
+    let traces = input_files(cli.input, is_trace)?;
+    if traces.is_empty() {
+        println!("No traces found");
+        return Ok(());
+    }
+
+    let timeout_duration = Duration::from_millis(cli.timeout);
+
+    // Pick the ports of both builds and fill them in wherever the placeholder is used
+    let target_port = match cli.target_port {
+        Some(port) => port,
+        None => port::find_free_port()?,
+    };
+    let target_port_b = match cli.target_port_b {
+        Some(port) => port,
+        None => port::find_free_port()?,
+    };
+    if target_port == target_port_b {
+        return Err(Error::illegal_argument(
+            "Both builds would use the same port, pick different ports",
+        ));
+    }
+    port::record_port(&out_dir, target_port)?;
+    log::info!("Target ports: {target_port} (A), {target_port_b} (B)");
+    for (template, destination) in cli.config_templates.iter().flatten() {
+        port::render_template(template, destination, target_port)?;
+    }
+    for (template, destination) in cli.config_templates_b.iter().flatten() {
+        port::render_template(template, destination, target_port_b)?;
+    }
+
+    let args: Vec<String> = cli
+        .arguments
+        .iter()
+        .map(|arg| port::substitute_port(arg, target_port))
+        .collect();
+    let args_b: Vec<String> = match &cli.arguments_b {
+        Some(arguments) => arguments.split_whitespace().map(str::to_string).collect(),
+        None => cli.arguments.clone(),
+    }
+    .iter()
+    .map(|arg| port::substitute_port(arg, target_port_b))
+    .collect();
+
+    // The unix shmem provider supported by AFL++ for shared memory
+    let mut shmem_provider = UnixShMemProvider::new().unwrap();
+
+    // Each build gets its own coverage map, the forkserver picks up the shmid when it is started
+    let mut shmem = shmem_provider.new_shmem(MAP_SIZE).unwrap();
+    shmem.write_to_env("__AFL_SHM_ID").unwrap();
+    let shmem_buf = shmem.as_mut_slice();
+    let edges_observer =
+        unsafe { HitcountsMapObserver::new(StdMapObserver::new("shared_mem", shmem_buf)) };
+
+    let mut frsv_builder = ForkserverExecutor::builder();
+    if let Some(env_vars) = cli.environment_variables.clone() {
+        frsv_builder = frsv_builder.envs(
+            env_vars
+                .into_iter()
+                .map(|(key, val)| (key, port::substitute_port(&val, target_port))),
+        );
+    }
+    let mut fsrv_executor = frsv_builder
+        .program(cli.executable)
+        .debug_child(cli.debug_child)
+        .socket_client_port(target_port)
+        .network_namespace(cli.netns)
+        .is_persistent(true)
+        .timeout(timeout_duration)
+        .parse_afl_cmdline(args)
+        .coverage_map_size(MAP_SIZE)
+        .kill_signal(cli.signal)
+        .capture_responses(true)
+        .memlimit(cli.mem_limit, cli.mem_limit_mode)
+        .build(tuple_list!(TimeObserver::new("time"), edges_observer))
+        .expect("Building forkserver of build A");
+
+    let mut shmem_b = shmem_provider.new_shmem(MAP_SIZE).unwrap();
+    shmem_b.write_to_env("__AFL_SHM_ID").unwrap();
+    let shmem_buf_b = shmem_b.as_mut_slice();
+    let edges_observer_b =
+        unsafe { HitcountsMapObserver::new(StdMapObserver::new("shared_mem", shmem_buf_b)) };
+
+    let mut frsv_builder_b = ForkserverExecutor::builder();
+    if let Some(env_vars) = cli.environment_variables {
+        frsv_builder_b = frsv_builder_b.envs(
+            env_vars
+                .into_iter()
+                .map(|(key, val)| (key, port::substitute_port(&val, target_port_b))),
+        );
+    }
+    let mut fsrv_executor_b = frsv_builder_b
+        .program(cli.executable_b)
+        .debug_child(cli.debug_child)
+        .socket_client_port(target_port_b)
+        .network_namespace(cli.netns)
+        .is_persistent(true)
+        .timeout(timeout_duration)
+        .parse_afl_cmdline(args_b)
+        .coverage_map_size(MAP_SIZE)
+        .kill_signal(cli.signal)
+        .capture_responses(true)
+        .memlimit(cli.mem_limit, cli.mem_limit_mode)
+        .build(tuple_list!(TimeObserver::new("time"), edges_observer_b))
+        .expect("Building forkserver of build B");
+
+    for executor in [&mut fsrv_executor, &mut fsrv_executor_b] {
+        if let Some(dynamic_map_size) = executor.coverage_map_size() {
+            executor
+                .observers_mut()
+                .match_name_mut::<EdgesObserver<'_>>("shared_mem")
+                .unwrap()
+                .truncate(dynamic_map_size);
+        }
+    }
+
+    let mut executor = StatefulPersistentExecutor::new(fsrv_executor);
+    let mut executor_b = StatefulPersistentExecutor::new(fsrv_executor_b);
+
+    // Nothing is stored, we only compare how each execution ends
+    let mut feedback = ();
+    let mut objective = ();
+
+    let monitor = MultiMonitor::new(|s| log::info!("{s}"));
+    let mut mgr = LibAFLStarManager::new(monitor);
+
+    // The messages of the traces are sent as they are, so the state needs no prefixes of its own
+    let prefixes = vec![Prefix {
+        prefix: Vec::new(),
+        metadata: PrefixMetadata {
+            outgoing_edges: 0,
+            name: "empty".to_string(),
+            prefix_hash: 0,
+            tags: Default::default(),
+        },
+    }];
+
+    let mut state = LibAFLStarState::new(
+        StdRand::with_seed(current_nanos()),
+        vec![InMemoryCorpus::<BytesInput>::new()],
+        InMemoryCorpus::new(),
+        &mut feedback,
+        &mut objective,
+        prefixes,
+    )?;
+
+    let mut fuzzer = StdFuzzer::new(QueueScheduler::new(), feedback, objective);
+
+    let mut coverage = CoverageTracker::default();
+    let mut coverage_b = CoverageTracker::default();
+
+    let mut report = BufWriter::new(File::create(out_dir.join("differences.txt"))?);
+    let (mut messages_replayed, mut messages_differing) = (0, 0);
+    let (mut exit_kind_diffs, mut response_code_diffs, mut coverage_diffs) = (0, 0, 0);
+    for path in &traces {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let messages = TraceReader::open(path)?
+            .map(|message| message.map(|message| message.req))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Both builds start the trace from a fresh process
+        executor.reset_target_state()?;
+        executor_b.reset_target_state()?;
+        coverage.clear();
+        coverage_b.clear();
+
+        for (idx, message) in messages.iter().enumerate() {
+            let outcome = run_message::<_, _, _, EdgesObserver<'_>>(
+                &mut executor,
+                &mut fuzzer,
+                &mut state,
+                &mut mgr,
+                "shared_mem",
+                &mut coverage,
+                message,
+            )?;
+            let outcome_b = run_message::<_, _, _, EdgesObserver<'_>>(
+                &mut executor_b,
+                &mut fuzzer,
+                &mut state,
+                &mut mgr,
+                "shared_mem",
+                &mut coverage_b,
+                message,
+            )?;
+            messages_replayed += 1;
+
+            let differences = compare(&outcome, &outcome_b);
+            if !differences.is_empty() {
+                messages_differing += 1;
+                for difference in &differences {
+                    match difference {
+                        Difference::ExitKind { .. } => exit_kind_diffs += 1,
+                        Difference::ResponseCode { .. } => response_code_diffs += 1,
+                        Difference::Coverage { .. } => coverage_diffs += 1,
+                    }
+                }
+                let line = format!(
+                    "{name} [{idx}] {} | request b\"{}\"",
+                    differences
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join("; "),
+                    message.escape_ascii()
+                );
+                println!("{line}");
+                writeln!(report, "{line}")?;
+            }
+
+            // After a crash or timeout the conversations are no longer the same
+            if outcome.exit_kind != ExitKind::Ok || outcome_b.exit_kind != ExitKind::Ok {
+                break;
+            }
+        }
+    }
+    report.flush()?;
+
+    println!(
+        "Replayed {} traces, {messages_replayed} messages: {messages_differing} differ ({exit_kind_diffs} in exit kind, {response_code_diffs} in response code, {coverage_diffs} in coverage)",
+        traces.len()
+    );
+    Ok(())
+}
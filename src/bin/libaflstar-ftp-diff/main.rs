@@ -0,0 +1,619 @@
+mod cli;
+
+use std::{fs::OpenOptions, io::Write, path::PathBuf, time::Duration};
+
+use clap::Parser;
+
+use libaflstar::{
+    calibration::{self, TimeoutCalibration}, child_output::{ChildOutput, ChildOutputFeedback, ChildOutputObserver}, crash_dedup::CrashDedupFeedback, crash_record::{Conversation, ConversationObserver, CrashRecordFeedback}, differential::{DifferentialExecutor, DisagreementFeedback, LastDisagreement, NormalizationRules}, event_manager::LibAFLStarManager, log_monitor::LogMonitorArgs, executor::{forkserver::ForkserverExecutor, memlimit::MemLimitMode, RestartPolicy, StatefulPersistentExecutor}, feedbacks::{HangFeedback, OomFeedback, SeparateSolutionsFeedback}, fuzzer, mutator::FtpLightMutator, drift::{DriftRules, DriftStage}, oracle::{AuthBypassFeedback, AuthBypassRules, ResponseOracleFeedback, ResponseRules}, replay::{RequestResponseCollector, TraceStoragePolicy}, response::{ResponseObserver, SharedResponse}, stability::{ChildRestartStage, StabilityStage}, state::{self, LibAFLStarState, MultipleStates}, port, state_scheduler, workdir::WorkdirSnapshot
+};
+use libafl::{
+    corpus::{CachedOnDiskCorpus, OnDiskCorpus}, executors::HasObservers, feedback_and_fast, feedback_or, feedbacks::{CrashFeedback, MaxMapFeedback, TimeFeedback}, fuzzer::StdFuzzer, inputs::{BytesInput, HasTargetBytes}, monitors::{MultiMonitor, OnDiskJSONMonitor}, mutators::{scheduled::havoc_mutations, tokens_mutations, StdScheduledMutator, Tokens}, observers::{HitcountsMapObserver, ObserversTuple, StdMapObserver, TimeObserver}, schedulers::QueueScheduler, stages::mutational::StdMutationalStage, state::{HasMetadata, State}
+};
+use libafl_bolts::{
+    current_nanos,
+    rands::StdRand,
+    shmem::{ShMem, ShMemProvider, UnixShMemProvider},
+    tuples::{tuple_list, Merge},
+    AsMutSlice, Error, Truncate,
+};
+use nix::sys::signal::Signal;
+
+const MAP_SIZE: usize = 65536;
+/// The timeout that confirms hangs is this many times the timeout, unless `--hang-timeout` is given
+const HANG_TIMEOUT_FACTOR: u64 = 4;
+
+#[allow(clippy::similar_names)]
+fn main() -> Result<(), Error> {
+    env_logger::init();
+
+    let cli = cli::Cli::parse();
+
+    // Get out dir ready
+    let out_dir = cli.out_dir;
+    if out_dir.exists() {
+        if out_dir.read_dir()?.next().is_some() {
+            return Err(Error::illegal_argument(format!(
+                "OUT_DIR [{}] must be empty or not exist.",
+                out_dir.display()
+            )));
+        }
+    } else {
+        std::fs::create_dir(&out_dir)?;
+    }
+
+    let timeout_duration = Duration::from_millis(cli.timeout);
+
+    // When long-running children are restarted
+    let restart_policy = RestartPolicy {
+        max_executions: cli.restart_after_execs,
+        max_age: cli.restart_after_secs.map(Duration::from_secs),
+        max_rss: cli.restart_max_rss.map(|mb| mb * 1024 * 1024),
+    };
+
+    // Timeouts are confirmed with a longer timeout before they are saved as hangs, if requested
+    let hang_timeout = cli.save_hangs.then(|| {
+        Duration::from_millis(cli.hang_timeout.unwrap_or(cli.timeout * HANG_TIMEOUT_FACTOR))
+    });
+
+    let corpus_dir: PathBuf = cli.in_dir;
+
+    // The unix shmem provider supported by AFL++ for shared memory
+    let mut shmem_provider = UnixShMemProvider::new().unwrap();
+
+    // The coverage map shared between observer and executor
+    let mut shmem = shmem_provider.new_shmem(MAP_SIZE).unwrap();
+    // let the forkserver know the shmid
+    shmem.write_to_env("__AFL_SHM_ID").unwrap();
+    let shm_id = shmem.id().to_string();
+    let shmem_buf = shmem.as_mut_slice();
+
+    // The secondary implementation gets its own coverage map, it is not used for feedback
+    let mut shmem_b = shmem_provider.new_shmem(MAP_SIZE).unwrap();
+    let shm_id_b = shmem_b.id().to_string();
+    let shmem_buf_b = shmem_b.as_mut_slice();
+    let edges_observer_b =
+        unsafe { HitcountsMapObserver::new(StdMapObserver::new("shared_mem", shmem_buf_b)) };
+
+    // Create an observation channel using the signals map
+    let edges_observer =
+        unsafe { HitcountsMapObserver::new(StdMapObserver::new("shared_mem", shmem_buf)) };
+
+    // Measures the stability of new corpus entries and masks unstable edges out of the coverage feedback
+    let stability_stage = StabilityStage::new(&edges_observer, cli.stability_runs);
+    // Restarts long-running children, see `--restart-after-execs`, `--restart-after-secs` and `--restart-max-rss`
+    let restart_stage = ChildRestartStage::new(&edges_observer);
+
+    // Create an observation channel to keep track of the execution time
+    let time_observer = TimeObserver::new("time");
+
+    // Create an observation channel to capture the output of the target, it is stored next to the solutions
+    let child_output = ChildOutput::default();
+    let child_output_observer = ChildOutputObserver::new("child_output", child_output.clone());
+
+    // Create an observation channel that keeps the conversation with the target, it is stored with the solutions
+    let conversation = Conversation::default();
+    let conversation_observer = ConversationObserver::new("conversation", conversation.clone());
+
+    // Create an observation channel that keeps the response of the target, it is checked by the response oracle
+    let shared_response = SharedResponse::default();
+    let response_observer = ResponseObserver::new("response", shared_response.clone());
+
+    // Create an observation channel that tails the log files of the target, and the feedback that flags anomalous log
+    // lines, see `--log-file` and `--log-patterns`
+    let (log_observer, log_pattern_feedback) = cli
+        .log_monitor
+        .create(conversation.clone(), out_dir.join("findings"))?;
+
+    // Rules for responses that reveal logic bugs, see `--response-rules`
+    let response_rules = match &cli.response_rules {
+        Some(path) => ResponseRules::from_file(path)?,
+        None => ResponseRules::default(),
+    };
+    // Privileged commands that must not succeed in unauthenticated target states, see `--auth-rules`
+    let auth_rules = match &cli.auth_rules {
+        Some(path) => AuthBypassRules::from_file(path)?,
+        None => AuthBypassRules::default(),
+    };
+    // Detects inputs that move the target out of the fuzzed target state, see `--drift-rules`
+    let drift_rules = match &cli.drift_rules {
+        Some(path) => DriftRules::from_file(path)?,
+        None => DriftRules::default(),
+    };
+    let drift_stage = DriftStage::new(
+        &response_observer,
+        drift_rules,
+        conversation.clone(),
+        out_dir.join("drift"),
+    );
+
+    // The disagreements between the implementations, they are stored next to the solutions
+    let rules = match &cli.normalization_rules {
+        Some(path) => NormalizationRules::from_file(path)?,
+        None => NormalizationRules::default(),
+    };
+    let last_disagreement = LastDisagreement::default();
+
+    // Feedback to rate the interestingness of an input
+    // This one is composed by two Feedbacks in OR
+    let mut feedback = feedback_or!(
+        // New maximization map feedback linked to the edges observer and the feedback state
+        MaxMapFeedback::tracking(&edges_observer, true, false),
+        // Time feedback, this one does not need a feedback state
+        TimeFeedback::with_observer(&time_observer)
+    );
+
+    // A feedback to choose if an input is a solution or not
+    // We want to do the same crash deduplication that AFL does
+    let mut objective = feedback_or!(
+        feedback_and_fast!(
+            // Must be a crash
+            CrashFeedback::new(),
+            // Take it only if it is a new bug, over the crashes of all target states.
+            // Crashes are bucketed by the stack trace of the sanitizer report, without one they must reach new coverage
+            CrashDedupFeedback::new(
+                &edges_observer,
+                Some(&child_output_observer),
+                out_dir.join("crash_buckets.txt"),
+            )
+        ),
+        // The implementations handled the input differently, take it only if it reaches new coverage of the primary
+        feedback_and_fast!(
+            DisagreementFeedback::new(last_disagreement.clone(), out_dir.join("crashes")),
+            MaxMapFeedback::with_name("mapfeedback_metadata_diff", &edges_observer)
+        ),
+        // Never interesting by itself, but stores the output of the target next to the solution
+        ChildOutputFeedback::new(&child_output_observer, out_dir.join("crashes")),
+        // Same, but stores the target state and the conversation that led to the solution
+        CrashRecordFeedback::new(&conversation_observer, out_dir.join("crashes")),
+        // Inputs that make the target run out of memory are no crashes, they are stored apart in `ooms/`
+        SeparateSolutionsFeedback::new(
+            "oom_solutions",
+            feedback_or!(
+                feedback_and_fast!(
+                    OomFeedback::new(),
+                    MaxMapFeedback::with_name("mapfeedback_metadata_oom", &edges_observer)
+                ),
+                ChildOutputFeedback::new(&child_output_observer, out_dir.join("ooms")),
+                CrashRecordFeedback::new(&conversation_observer, out_dir.join("ooms"))
+            ),
+            out_dir.join("ooms"),
+        )?,
+        // Responses that match a rule reveal logic bugs, they are stored apart in `findings/`
+        SeparateSolutionsFeedback::new(
+            "findings",
+            feedback_and_fast!(
+                feedback_or!(
+                    ResponseOracleFeedback::new(
+                        &response_observer,
+                        response_rules,
+                        conversation.clone(),
+                        out_dir.join("findings"),
+                    ),
+                    AuthBypassFeedback::new(
+                        &response_observer,
+                        auth_rules,
+                        conversation.clone(),
+                        out_dir.join("findings"),
+                    ),
+                    log_pattern_feedback
+                ),
+                MaxMapFeedback::with_name("mapfeedback_metadata_findings", &edges_observer)
+            ),
+            out_dir.join("findings"),
+        )?,
+        // Inputs that make the target hang (with `--save-hangs`) are stored apart in `hangs/`
+        SeparateSolutionsFeedback::new(
+            "hang_solutions",
+            feedback_or!(
+                feedback_and_fast!(
+                    HangFeedback::new(cli.save_hangs),
+                    MaxMapFeedback::with_name("mapfeedback_metadata_hang", &edges_observer)
+                ),
+                ChildOutputFeedback::new(&child_output_observer, out_dir.join("hangs")),
+                CrashRecordFeedback::new(&conversation_observer, out_dir.join("hangs"))
+            ),
+            out_dir.join("hangs"),
+        )?
+    );
+
+    let monitor = OnDiskJSONMonitor::new(
+        out_dir.join("stats.json"),
+        MultiMonitor::new(|s| println!("{s}")),
+        |_| true,
+    );
+
+    // The event manager handle the various events generated during the fuzzing loop
+    // such as the notification of the addition of a new item to the corpus
+    let mut mgr = LibAFLStarManager::new(monitor);
+
+    // A queue policy to get testcasess from the corpus
+    let seed_scheduler = QueueScheduler::new();
+
+    // If we should debug the child
+    let debug_child = cli.debug_child;
+
+    // Pick the ports of both implementations and fill them in wherever the placeholder is used
+    let target_port = match cli.target_port {
+        Some(port) => port,
+        None => port::find_free_port()?,
+    };
+    let target_port_b = match cli.target_port_b {
+        Some(port) => port,
+        None => port::find_free_port()?,
+    };
+    if target_port == target_port_b && !cli.netns {
+        return Err(Error::illegal_argument(
+            "Both implementations would use the same port, pick different ports or use --netns",
+        ));
+    }
+    port::record_port(&out_dir, target_port)?;
+    log::info!("Target ports: {target_port} (primary), {target_port_b} (secondary)");
+    for (template, destination) in cli.config_templates.iter().flatten() {
+        port::render_template(template, destination, target_port)?;
+    }
+    for (template, destination) in cli.config_templates_b.iter().flatten() {
+        port::render_template(template, destination, target_port_b)?;
+    }
+    let substitute_env_vars = |port: u16| {
+        cli.environment_variables.clone().map(|vars| {
+            vars.into_iter()
+                .map(|(key, val)| (key, port::substitute_port(&val, port)))
+                .collect::<Vec<_>>()
+        })
+    };
+    let env_vars = substitute_env_vars(target_port);
+    let env_vars_b = substitute_env_vars(target_port_b);
+
+    // Create the executors for the forkservers
+    let args: Vec<String> = cli
+        .arguments
+        .iter()
+        .map(|arg| port::substitute_port(arg, target_port))
+        .collect();
+    let args_b: Vec<String> = match &cli.arguments_b {
+        Some(arguments) => arguments.split_whitespace().map(str::to_string).collect(),
+        None => cli.arguments.clone(),
+    }
+    .iter()
+    .map(|arg| port::substitute_port(arg, target_port_b))
+    .collect();
+
+    // Kill signal to kill the target:
+    let kill_signal = cli.signal;
+
+    let mut tokens = Tokens::new();
+
+    let collector = Some(RequestResponseCollector::with_policy(
+        &out_dir.join("replay_traces"),
+        TraceStoragePolicy {
+            keep_recent: cli.replay_traces_keep,
+            max_total_size: cli.replay_traces_max_size.map(|mib| mib * 1024 * 1024),
+            compress: cli.replay_traces_compress,
+        },
+    )?);
+
+    // Restore the working directory of the target on resets, if requested
+    let workdir = match (&cli.workdir_template, &cli.workdir) {
+        (Some(template), Some(workdir)) => Some(WorkdirSnapshot::new(
+            template,
+            workdir,
+            cli.workdir_restore,
+            cli.workdir_tmpfs,
+        )?),
+        _ => None,
+    };
+
+    // Each forkserver picks up the shmid of its coverage map when it is started
+    std::env::set_var("__AFL_SHM_ID", &shm_id);
+    let executor_a = create_forkserver_executor(
+        env_vars.clone(),
+        cli.executable.clone(),
+        debug_child,
+        target_port,
+        cli.netns,
+        timeout_duration.clone(),
+        args.clone(),
+        collector,
+        kill_signal.clone(),
+        tuple_list!(
+            time_observer,
+            edges_observer,
+            child_output_observer,
+            conversation_observer,
+            response_observer,
+            log_observer
+        ),
+        Some(&mut tokens),
+        workdir,
+        child_output.clone(),
+        conversation.clone(),
+        shared_response.clone(),
+        cli.mem_limit,
+        cli.mem_limit_mode,
+        hang_timeout,
+        restart_policy,
+    );
+    std::env::set_var("__AFL_SHM_ID", &shm_id_b);
+    let executor_b = create_forkserver_executor(
+        env_vars_b.clone(),
+        cli.executable_b.clone(),
+        debug_child,
+        target_port_b,
+        cli.netns,
+        timeout_duration.clone(),
+        args_b.clone(),
+        None,
+        kill_signal.clone(),
+        tuple_list!(TimeObserver::new("time"), edges_observer_b),
+        None,
+        None,
+        ChildOutput::default(),
+        Conversation::default(),
+        SharedResponse::default(),
+        cli.mem_limit,
+        cli.mem_limit_mode,
+        hang_timeout,
+        restart_policy,
+    );
+    let mut executor =
+        DifferentialExecutor::new(executor_a, executor_b, rules.clone(), last_disagreement.clone());
+
+    let prefixes = state::load_prefixes(&corpus_dir).unwrap();
+
+    let corpus =
+        CachedOnDiskCorpus::<BytesInput>::new(out_dir.join(format!(".states/state")), 300).unwrap();
+
+    // create the LibAFLStarState
+    let mut state = LibAFLStarState::new_single_corpus(
+        // RNG
+        StdRand::with_seed(current_nanos()),
+        // Corpus that will be evolved, we keep it in memory for performance
+        corpus,
+        OnDiskCorpus::new(out_dir.join("crashes")).unwrap(),
+        // States of the feedbacks.
+        // The feedbacks can report the data that should persist in the State.
+        &mut feedback,
+        // Same for objective feedbacks
+        &mut objective,
+        prefixes,
+    )
+    .unwrap();
+
+    let mut state_scheduler = state_scheduler::OutgoingEdges;
+
+    // A fuzzer with feedbacks and a corpus scheduler.
+    let mut fuzzer = StdFuzzer::new(seed_scheduler, feedback, objective);
+
+    // Load testcases
+    state::load_testcases(
+        &mut state,
+        &mut fuzzer,
+        &mut executor,
+        &mut mgr,
+        &corpus_dir,
+    )
+    .unwrap();
+
+    state.for_each(|state| {
+        state.add_metadata(tokens.clone());
+        Ok(())
+    })?;
+
+    // Derive the timeout of each target state from the latencies of its prefix and seeds, if requested
+    if cli.calibrate_timeouts {
+        calibration::calibrate_timeouts(
+            &mut fuzzer,
+            &mut executor,
+            &mut state,
+            &mut mgr,
+            &TimeoutCalibration::new(timeout_duration).with_multiplier(cli.timeout_multiplier),
+        )?;
+    }
+
+    // Setup a mutational stage with a basic bytes mutator
+    let mutator =
+        StdScheduledMutator::with_max_stack_pow(havoc_mutations().merge(tokens_mutations()), 6);
+    let mut stages = tuple_list!(
+        restart_stage,
+        stability_stage,
+        StdMutationalStage::with_max_iterations(
+            FtpLightMutator::new(mutator),
+            // we set the max stage iterations to 1, and control the number of times a test case gets
+            // executed in a target state by the number of `loops` in `fuzz_loop_with_signal_handling`
+            // this way we have full control.
+            1
+        ),
+        drift_stage
+    );
+
+    log::debug!("Writing README.stats");
+    // Before we start, write the README to the out_dir
+    let stats_readme = include_str!("../../resources/README.stats");
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(out_dir.join("README.md"))?
+        .write_all(stats_readme.as_bytes())?;
+
+    // Fuzzing loop.
+    //
+    // Recreate the forkserver if TimeOut error occur
+
+    // keep track of the number of forkserver recreations for debugging
+    let mut recreations = 0;
+    loop {
+        match fuzzer::fuzz_loop_with_signal_handling(
+            &mut fuzzer,
+            &mut stages,
+            &mut executor,
+            &mut state,
+            &mut mgr,
+            &mut state_scheduler,
+            cli.loops,
+        ) {
+            // ShuttingDown is code for recreating the forkserver
+            Err(Error::ShuttingDown) => {}
+            Ok(_) => break,
+            Err(e) => {
+                log::error!("Quitting due to error: {}", e);
+                println!("Quitting due to error: {}", e);
+                break;
+            }
+        };
+        let (mut executor_a, executor_b) = executor.into_inner();
+        let workdir = executor_a.take_workdir_snapshot();
+        let (collector, observers) = executor_a.into_inner().shutdown();
+        let (_, observers_b) = executor_b.into_inner().shutdown();
+
+        println!("Recreating forkserver executors due to TimeOut error");
+        log::error!("Recreating forkserver executors due to TimeOut error");
+        recreations += 1;
+
+        std::env::set_var("__AFL_SHM_ID", &shm_id);
+        let executor_a = create_forkserver_executor(
+            env_vars.clone(),
+            cli.executable.clone(),
+            debug_child,
+            target_port,
+            cli.netns,
+            timeout_duration.clone(),
+            args.clone(),
+            collector,
+            kill_signal.clone(),
+            observers,
+            Some(&mut tokens),
+            workdir,
+            child_output.clone(),
+            conversation.clone(),
+            shared_response.clone(),
+            cli.mem_limit,
+            cli.mem_limit_mode,
+            hang_timeout,
+            restart_policy,
+        );
+        std::env::set_var("__AFL_SHM_ID", &shm_id_b);
+        let executor_b = create_forkserver_executor(
+            env_vars_b.clone(),
+            cli.executable_b.clone(),
+            debug_child,
+            target_port_b,
+            cli.netns,
+            timeout_duration.clone(),
+            args_b.clone(),
+            None,
+            kill_signal.clone(),
+            observers_b,
+            None,
+            None,
+            ChildOutput::default(),
+            Conversation::default(),
+            SharedResponse::default(),
+            cli.mem_limit,
+            cli.mem_limit_mode,
+            hang_timeout,
+            restart_policy,
+        );
+        executor = DifferentialExecutor::new(
+            executor_a,
+            executor_b,
+            rules.clone(),
+            last_disagreement.clone(),
+        );
+    }
+
+    let type_names = vec![
+        std::any::type_name_of_val(&fuzzer),
+        std::any::type_name_of_val(&stages),
+        std::any::type_name_of_val(&executor),
+        std::any::type_name_of_val(&state),
+        std::any::type_name_of_val(&mgr),
+        std::any::type_name_of_val(&state_scheduler),
+    ];
+
+    state.store_fuzzer_info(
+        out_dir.join("total_stats_info.txt"),
+        format!("{:?}", cli::Cli::parse()),
+        type_names,
+    )?;
+
+    println!("Quitting! Recreated forkserver {recreations} times");
+    Ok(())
+}
+
+fn create_forkserver_executor<OT, S>(
+    env_vars: Option<Vec<(String, String)>>,
+    program: String,
+    debug_child: bool,
+    target_port: u16,
+    netns: bool,
+    timeout: Duration,
+    args: Vec<String>,
+    collector: Option<RequestResponseCollector>,
+    signal: Signal,
+    observers: OT,
+    tokens: Option<&mut Tokens>,
+    workdir: Option<WorkdirSnapshot>,
+    child_output: ChildOutput,
+    conversation: Conversation,
+    shared_response: SharedResponse,
+    mem_limit: u64,
+    mem_limit_mode: MemLimitMode,
+    hang_timeout: Option<Duration>,
+    restart_policy: RestartPolicy,
+) -> StatefulPersistentExecutor<OT, S, UnixShMemProvider>
+where
+    OT: ObserversTuple<S>,
+    S: State,
+    S::Input: HasTargetBytes,
+{
+    let mut builder = ForkserverExecutor::builder();
+    if let Some(env_vars) = env_vars {
+        builder = builder.envs(env_vars)
+    }
+    if let Some(tokens) = tokens {
+        builder = builder.autotokens(tokens);
+    }
+    
+    if let Some(collector) = collector { 
+        builder =builder.collect_request_response_pairs(collector);
+    }
+
+    let mut fsrv_executor = builder
+        .program(program)
+        .debug_child(debug_child)
+        .socket_client_port(target_port)
+        .network_namespace(netns)
+        .is_persistent(true)
+        .timeout(timeout)
+        .parse_afl_cmdline(args)
+        .coverage_map_size(MAP_SIZE)
+        .kill_signal(signal)
+        .capture_child_output(child_output)
+        .record_conversation(conversation)
+        .share_responses(shared_response)
+        // the responses of both implementations are compared
+        .capture_responses(true)
+        .memlimit(mem_limit, mem_limit_mode)
+        .build(observers)
+        .expect("Building forkserver");
+
+    if let Some(dynamic_map_size) = fsrv_executor.coverage_map_size() {
+        fsrv_executor
+            .observers_mut()
+            .match_name_mut::<HitcountsMapObserver<StdMapObserver<'_, u8, false>>>("shared_mem")
+            .unwrap()
+            .truncate(dynamic_map_size);
+    }
+
+    let mut executor =
+        StatefulPersistentExecutor::new(fsrv_executor).with_restart_policy(restart_policy);
+    if let Some(hang_timeout) = hang_timeout {
+        executor = executor.with_hang_confirmation(hang_timeout);
+    }
+    match workdir {
+        Some(workdir) => executor.with_workdir_snapshot(workdir),
+        None => executor,
+    }
+}
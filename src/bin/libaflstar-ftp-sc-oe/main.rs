@@ -5,7 +5,7 @@ use std::{fs::OpenOptions, io::Write, path::PathBuf, time::Duration};
 use clap::Parser;
 
 use libaflstar::{
-    event_manager::LibAFLStarManager, executor::{forkserver::ForkserverExecutor, StatefulPersistentExecutor}, fuzzer, mutator::FtpLightMutator, replay::RequestResponseCollector, state::{self, LibAFLStarState, MultipleStates}, state_scheduler
+    calibration::{self, TimeoutCalibration}, child_output::{ChildOutput, ChildOutputFeedback, ChildOutputObserver}, crash_dedup::CrashDedupFeedback, crash_record::{Conversation, ConversationObserver, CrashRecordFeedback}, event_manager::LibAFLStarManager, log_monitor::LogMonitorArgs, executor::{forkserver::ForkserverExecutor, memlimit::MemLimitMode, RestartPolicy, StatefulPersistentExecutor}, feedbacks::{HangFeedback, OomFeedback, SeparateSolutionsFeedback}, fuzzer, mutator::FtpLightMutator, drift::{DriftRules, DriftStage}, oracle::{AuthBypassFeedback, AuthBypassRules, ResponseOracleFeedback, ResponseRules}, replay::{RequestResponseCollector, TraceStoragePolicy}, response::{ResponseObserver, SharedResponse}, stability::{ChildRestartStage, StabilityStage}, state::{self, LibAFLStarState, MultipleStates}, port, state_scheduler, workdir::WorkdirSnapshot
 };
 use libafl::{
     corpus::{CachedOnDiskCorpus, OnDiskCorpus}, executors::HasObservers, feedback_and_fast, feedback_or, feedbacks::{CrashFeedback, MaxMapFeedback, TimeFeedback}, fuzzer::StdFuzzer, inputs::{BytesInput, HasTargetBytes}, monitors::{MultiMonitor, OnDiskJSONMonitor}, mutators::{scheduled::havoc_mutations, tokens_mutations, StdScheduledMutator, Tokens}, observers::{HitcountsMapObserver, ObserversTuple, StdMapObserver, TimeObserver}, schedulers::QueueScheduler, stages::mutational::StdMutationalStage, state::{HasMetadata, State}
@@ -20,6 +20,8 @@ use libafl_bolts::{
 use nix::sys::signal::Signal;
 
 const MAP_SIZE: usize = 65536;
+/// The timeout that confirms hangs is this many times the timeout, unless `--hang-timeout` is given
+const HANG_TIMEOUT_FACTOR: u64 = 4;
 
 #[allow(clippy::similar_names)]
 fn main() -> Result<(), Error> {
@@ -42,6 +44,18 @@ fn main() -> Result<(), Error> {
 
     let timeout_duration = Duration::from_millis(cli.timeout);
 
+    // When long-running children are restarted
+    let restart_policy = RestartPolicy {
+        max_executions: cli.restart_after_execs,
+        max_age: cli.restart_after_secs.map(Duration::from_secs),
+        max_rss: cli.restart_max_rss.map(|mb| mb * 1024 * 1024),
+    };
+
+    // Timeouts are confirmed with a longer timeout before they are saved as hangs, if requested
+    let hang_timeout = cli.save_hangs.then(|| {
+        Duration::from_millis(cli.hang_timeout.unwrap_or(cli.timeout * HANG_TIMEOUT_FACTOR))
+    });
+
     let corpus_dir: PathBuf = cli.in_dir;
 
     // The unix shmem provider supported by AFL++ for shared memory
@@ -57,9 +71,54 @@ fn main() -> Result<(), Error> {
     let edges_observer =
         unsafe { HitcountsMapObserver::new(StdMapObserver::new("shared_mem", shmem_buf)) };
 
+    // Measures the stability of new corpus entries and masks unstable edges out of the coverage feedback
+    let stability_stage = StabilityStage::new(&edges_observer, cli.stability_runs);
+    // Restarts long-running children, see `--restart-after-execs`, `--restart-after-secs` and `--restart-max-rss`
+    let restart_stage = ChildRestartStage::new(&edges_observer);
+
     // Create an observation channel to keep track of the execution time
     let time_observer = TimeObserver::new("time");
 
+    // Create an observation channel to capture the output of the target, it is stored next to the solutions
+    let child_output = ChildOutput::default();
+    let child_output_observer = ChildOutputObserver::new("child_output", child_output.clone());
+
+    // Create an observation channel that keeps the conversation with the target, it is stored with the solutions
+    let conversation = Conversation::default();
+    let conversation_observer = ConversationObserver::new("conversation", conversation.clone());
+
+    // Create an observation channel that keeps the response of the target, it is checked by the response oracle
+    let shared_response = SharedResponse::default();
+    let response_observer = ResponseObserver::new("response", shared_response.clone());
+
+    // Create an observation channel that tails the log files of the target, and the feedback that flags anomalous log
+    // lines, see `--log-file` and `--log-patterns`
+    let (log_observer, log_pattern_feedback) = cli
+        .log_monitor
+        .create(conversation.clone(), out_dir.join("findings"))?;
+
+    // Rules for responses that reveal logic bugs, see `--response-rules`
+    let response_rules = match &cli.response_rules {
+        Some(path) => ResponseRules::from_file(path)?,
+        None => ResponseRules::default(),
+    };
+    // Privileged commands that must not succeed in unauthenticated target states, see `--auth-rules`
+    let auth_rules = match &cli.auth_rules {
+        Some(path) => AuthBypassRules::from_file(path)?,
+        None => AuthBypassRules::default(),
+    };
+    // Detects inputs that move the target out of the fuzzed target state, see `--drift-rules`
+    let drift_rules = match &cli.drift_rules {
+        Some(path) => DriftRules::from_file(path)?,
+        None => DriftRules::default(),
+    };
+    let drift_stage = DriftStage::new(
+        &response_observer,
+        drift_rules,
+        conversation.clone(),
+        out_dir.join("drift"),
+    );
+
     // Feedback to rate the interestingness of an input
     // This one is composed by two Feedbacks in OR
     let mut feedback = feedback_or!(
@@ -71,12 +130,71 @@ fn main() -> Result<(), Error> {
 
     // A feedback to choose if an input is a solution or not
     // We want to do the same crash deduplication that AFL does
-    let mut objective = feedback_and_fast!(
-        // Must be a crash
-        CrashFeedback::new(),
-        // Take it only if trigger new coverage over crashes
-        // Uses `with_name` to create a different history from the `MaxMapFeedback` in `feedback` above
-        MaxMapFeedback::with_name("mapfeedback_metadata_objective", &edges_observer)
+    let mut objective = feedback_or!(
+        feedback_and_fast!(
+            // Must be a crash
+            CrashFeedback::new(),
+            // Take it only if it is a new bug, over the crashes of all target states.
+            // Crashes are bucketed by the stack trace of the sanitizer report, without one they must reach new coverage
+            CrashDedupFeedback::new(
+                &edges_observer,
+                Some(&child_output_observer),
+                out_dir.join("crash_buckets.txt"),
+            )
+        ),
+        // Never interesting by itself, but stores the output of the target next to the solution
+        ChildOutputFeedback::new(&child_output_observer, out_dir.join("crashes")),
+        // Same, but stores the target state and the conversation that led to the solution
+        CrashRecordFeedback::new(&conversation_observer, out_dir.join("crashes")),
+        // Inputs that make the target run out of memory are no crashes, they are stored apart in `ooms/`
+        SeparateSolutionsFeedback::new(
+            "oom_solutions",
+            feedback_or!(
+                feedback_and_fast!(
+                    OomFeedback::new(),
+                    MaxMapFeedback::with_name("mapfeedback_metadata_oom", &edges_observer)
+                ),
+                ChildOutputFeedback::new(&child_output_observer, out_dir.join("ooms")),
+                CrashRecordFeedback::new(&conversation_observer, out_dir.join("ooms"))
+            ),
+            out_dir.join("ooms"),
+        )?,
+        // Responses that match a rule reveal logic bugs, they are stored apart in `findings/`
+        SeparateSolutionsFeedback::new(
+            "findings",
+            feedback_and_fast!(
+                feedback_or!(
+                    ResponseOracleFeedback::new(
+                        &response_observer,
+                        response_rules,
+                        conversation.clone(),
+                        out_dir.join("findings"),
+                    ),
+                    AuthBypassFeedback::new(
+                        &response_observer,
+                        auth_rules,
+                        conversation.clone(),
+                        out_dir.join("findings"),
+                    ),
+                    log_pattern_feedback
+                ),
+                MaxMapFeedback::with_name("mapfeedback_metadata_findings", &edges_observer)
+            ),
+            out_dir.join("findings"),
+        )?,
+        // Inputs that make the target hang (with `--save-hangs`) are stored apart in `hangs/`
+        SeparateSolutionsFeedback::new(
+            "hang_solutions",
+            feedback_or!(
+                feedback_and_fast!(
+                    HangFeedback::new(cli.save_hangs),
+                    MaxMapFeedback::with_name("mapfeedback_metadata_hang", &edges_observer)
+                ),
+                ChildOutputFeedback::new(&child_output_observer, out_dir.join("hangs")),
+                CrashRecordFeedback::new(&conversation_observer, out_dir.join("hangs"))
+            ),
+            out_dir.join("hangs"),
+        )?
     );
 
     let monitor = OnDiskJSONMonitor::new(
@@ -95,27 +213,81 @@ fn main() -> Result<(), Error> {
     // If we should debug the child
     let debug_child = cli.debug_child;
 
+    // Pick the port of the target and fill it in wherever the placeholder is used
+    let target_port = match cli.target_port {
+        Some(port) => port,
+        None => port::find_free_port()?,
+    };
+    port::record_port(&out_dir, target_port)?;
+    log::info!("Target port: {target_port}");
+    for (template, destination) in cli.config_templates.iter().flatten() {
+        port::render_template(template, destination, target_port)?;
+    }
+    let env_vars = cli.environment_variables.map(|vars| {
+        vars.into_iter()
+            .map(|(key, val)| (key, port::substitute_port(&val, target_port)))
+            .collect::<Vec<_>>()
+    });
+
     // Create the executor for the forkserver
-    let args = cli.arguments;
+    let args: Vec<String> = cli
+        .arguments
+        .iter()
+        .map(|arg| port::substitute_port(arg, target_port))
+        .collect();
 
     // Kill signal to kill the target:
     let kill_signal = cli.signal;
 
     let mut tokens = Tokens::new();
 
-    let collector = Some(RequestResponseCollector::new(&out_dir.join("replay_traces"))?);
+    let collector = Some(RequestResponseCollector::with_policy(
+        &out_dir.join("replay_traces"),
+        TraceStoragePolicy {
+            keep_recent: cli.replay_traces_keep,
+            max_total_size: cli.replay_traces_max_size.map(|mib| mib * 1024 * 1024),
+            compress: cli.replay_traces_compress,
+        },
+    )?);
+
+    // Restore the working directory of the target on resets, if requested
+    let workdir = match (&cli.workdir_template, &cli.workdir) {
+        (Some(template), Some(workdir)) => Some(WorkdirSnapshot::new(
+            template,
+            workdir,
+            cli.workdir_restore,
+            cli.workdir_tmpfs,
+        )?),
+        _ => None,
+    };
 
     let mut executor = create_forkserver_executor(
-        cli.environment_variables.clone(),
+        env_vars.clone(),
         cli.executable.clone(),
         debug_child,
-        cli.target_port,
+        target_port,
+        cli.netns,
         timeout_duration.clone(),
         args.clone(),
         collector,
         kill_signal.clone(),
-        tuple_list!(time_observer, edges_observer),
+        tuple_list!(
+            time_observer,
+            edges_observer,
+            child_output_observer,
+            conversation_observer,
+            response_observer,
+            log_observer
+        ),
         Some(&mut tokens),
+        workdir,
+        child_output.clone(),
+        conversation.clone(),
+        shared_response.clone(),
+        cli.mem_limit,
+        cli.mem_limit_mode,
+        hang_timeout,
+        restart_policy,
     );
 
     let prefixes = state::load_prefixes(&corpus_dir).unwrap();
@@ -159,16 +331,32 @@ fn main() -> Result<(), Error> {
         Ok(())
     })?;
 
+    // Derive the timeout of each target state from the latencies of its prefix and seeds, if requested
+    if cli.calibrate_timeouts {
+        calibration::calibrate_timeouts(
+            &mut fuzzer,
+            &mut executor,
+            &mut state,
+            &mut mgr,
+            &TimeoutCalibration::new(timeout_duration).with_multiplier(cli.timeout_multiplier),
+        )?;
+    }
+
     // Setup a mutational stage with a basic bytes mutator
     let mutator =
         StdScheduledMutator::with_max_stack_pow(havoc_mutations().merge(tokens_mutations()), 6);
-    let mut stages = tuple_list!(StdMutationalStage::with_max_iterations(
-        FtpLightMutator::new(mutator),
-        // we set the max stage iterations to 1, and control the number of times a test case gets
-        // executed in a target state by the number of `loops` in `fuzz_loop_with_signal_handling`
-        // this way we have full control.
-        1
-    ));
+    let mut stages = tuple_list!(
+        restart_stage,
+        stability_stage,
+        StdMutationalStage::with_max_iterations(
+            FtpLightMutator::new(mutator),
+            // we set the max stage iterations to 1, and control the number of times a test case gets
+            // executed in a target state by the number of `loops` in `fuzz_loop_with_signal_handling`
+            // this way we have full control.
+            1
+        ),
+        drift_stage
+    );
 
     log::debug!("Writing README.stats");
     // Before we start, write the README to the out_dir
@@ -205,6 +393,7 @@ fn main() -> Result<(), Error> {
                 break;
             }
         };
+        let workdir = executor.take_workdir_snapshot();
         let (collector, observers) = executor.into_inner().shutdown();
 
         println!("Recreating forkserver executor due to TimeOut error");
@@ -212,16 +401,25 @@ fn main() -> Result<(), Error> {
         recreations += 1;
 
         executor = create_forkserver_executor(
-            cli.environment_variables.clone(),
+            env_vars.clone(),
             cli.executable.clone(),
             debug_child,
-            cli.target_port,
+            target_port,
+            cli.netns,
             timeout_duration.clone(),
             args.clone(),
             collector,
             kill_signal.clone(),
             observers,
             Some(&mut tokens),
+            workdir,
+            child_output.clone(),
+            conversation.clone(),
+            shared_response.clone(),
+            cli.mem_limit,
+            cli.mem_limit_mode,
+            hang_timeout,
+            restart_policy,
         );
     }
 
@@ -249,12 +447,21 @@ fn create_forkserver_executor<OT, S>(
     program: String,
     debug_child: bool,
     target_port: u16,
+    netns: bool,
     timeout: Duration,
     args: Vec<String>,
     collector: Option<RequestResponseCollector>,
     signal: Signal,
     observers: OT,
     tokens: Option<&mut Tokens>,
+    workdir: Option<WorkdirSnapshot>,
+    child_output: ChildOutput,
+    conversation: Conversation,
+    shared_response: SharedResponse,
+    mem_limit: u64,
+    mem_limit_mode: MemLimitMode,
+    hang_timeout: Option<Duration>,
+    restart_policy: RestartPolicy,
 ) -> StatefulPersistentExecutor<OT, S, UnixShMemProvider>
 where
     OT: ObserversTuple<S>,
@@ -277,11 +484,16 @@ where
         .program(program)
         .debug_child(debug_child)
         .socket_client_port(target_port)
+        .network_namespace(netns)
         .is_persistent(true)
         .timeout(timeout)
         .parse_afl_cmdline(args)
         .coverage_map_size(MAP_SIZE)
         .kill_signal(signal)
+        .capture_child_output(child_output)
+        .record_conversation(conversation)
+        .share_responses(shared_response)
+        .memlimit(mem_limit, mem_limit_mode)
         .build(observers)
         .expect("Building forkserver");
 
@@ -293,5 +505,13 @@ where
             .truncate(dynamic_map_size);
     }
 
-    StatefulPersistentExecutor::new(fsrv_executor)
+    let mut executor =
+        StatefulPersistentExecutor::new(fsrv_executor).with_restart_policy(restart_policy);
+    if let Some(hang_timeout) = hang_timeout {
+        executor = executor.with_hang_confirmation(hang_timeout);
+    }
+    match workdir {
+        Some(workdir) => executor.with_workdir_snapshot(workdir),
+        None => executor,
+    }
 }
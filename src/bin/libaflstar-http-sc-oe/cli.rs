@@ -3,6 +3,7 @@
 use std::{error::Error, path::PathBuf};
 
 use clap::Parser;
+use libaflstar::{executor::memlimit::MemLimitMode, log_monitor::LogMonitorArgs, workdir::RestorePoint};
 use nix::sys::signal::Signal;
 
 #[derive(Debug, Parser)]
@@ -74,12 +75,40 @@ pub struct Cli {
     pub environment_variables: Option<std::vec::Vec<(String, String)>>,
 
     #[arg(
-        help = "Port the target uses",
+        help = "Port the target uses. If not given, a free port is picked, which is filled in wherever @@PORT@@ is used in the target's arguments, environment variables and config templates",
         short = 'p',
-        long = "target-port",
-        required = true
+        long = "target-port"
+    )]
+    pub target_port: Option<u16>,
+
+    #[arg(
+        help = "Config file templates in which @@PORT@@ is replaced by the target port, as TEMPLATE=DESTINATION pairs",
+        long = "config-template",
+        value_parser = parse_key_val_pairs::<PathBuf, PathBuf>,
+    )]
+    pub config_templates: Option<std::vec::Vec<(PathBuf, PathBuf)>>,
+
+    #[arg(
+        help = "Run the target in its own network namespace with only loopback up, so instances can share a port and the target cannot reach the network (requires root)",
+        long = "netns",
+        default_value = "false"
     )]
-    pub target_port: u16,
+    pub netns: bool,
+
+    #[arg(
+        help = "Memory limit of the target in MB, 0 means no limit",
+        long = "mem-limit",
+        default_value = "0"
+    )]
+    pub mem_limit: u64,
+
+    #[arg(
+        help = "How the memory limit is enforced: rlimit limits the address space (does not go well with ASAN), cgroup limits the used memory (requires root)",
+        long = "mem-limit-mode",
+        value_enum,
+        default_value = "rlimit"
+    )]
+    pub mem_limit_mode: MemLimitMode,
 
     #[arg(
         help = "Signal used to stop child",
@@ -89,6 +118,130 @@ pub struct Cli {
         default_value = "SIGKILL"
     )]
     pub signal: Signal,
+
+    #[arg(
+        help = "Template directory that is copied to the working directory of the target (--workdir) to reset its file system",
+        long = "workdir-template",
+        requires = "workdir"
+    )]
+    pub workdir_template: Option<PathBuf>,
+
+    #[arg(
+        help = "Working directory of the target that is restored from --workdir-template",
+        long = "workdir",
+        requires = "workdir_template"
+    )]
+    pub workdir: Option<PathBuf>,
+
+    #[arg(
+        help = "When to restore the working directory",
+        long = "workdir-restore",
+        value_enum,
+        default_value = "state-switch"
+    )]
+    pub workdir_restore: RestorePoint,
+
+    #[arg(
+        help = "Keep the working directory on a tmpfs (/dev/shm), --workdir becomes a symlink to it",
+        long = "workdir-tmpfs",
+        default_value = "false"
+    )]
+    pub workdir_tmpfs: bool,
+
+    #[arg(
+        help = "Number of replay traces without a crash to keep, besides the crashing ones",
+        long = "replay-traces-keep",
+        default_value = "0"
+    )]
+    pub replay_traces_keep: usize,
+
+    #[arg(
+        help = "Maximum total size of the replay traces in MiB, the oldest traces without a crash are deleted first",
+        long = "replay-traces-max-size"
+    )]
+    pub replay_traces_max_size: Option<u64>,
+
+    #[arg(
+        help = "Compress the replay traces with gzip",
+        long = "replay-traces-compress",
+        default_value = "false"
+    )]
+    pub replay_traces_compress: bool,
+
+    #[arg(
+        help = "JSON file with rules for responses that reveal logic bugs (see src/oracle.rs), matching inputs are stored in findings/",
+        long = "response-rules"
+    )]
+    pub response_rules: Option<PathBuf>,
+
+    #[arg(
+        help = "JSON file with the privileged commands that must not succeed in target states tagged authenticated=false (see src/oracle.rs), matching inputs are stored in findings/",
+        long = "auth-rules"
+    )]
+    pub auth_rules: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub log_monitor: LogMonitorArgs,
+
+    #[arg(
+        help = "Save inputs that make the target hang in hangs/. Timeouts are confirmed by replaying the conversation and running the input again with --hang-timeout",
+        long = "save-hangs",
+        default_value = "false"
+    )]
+    pub save_hangs: bool,
+
+    #[arg(
+        help = "Timeout in ms with which hangs are confirmed, by default 4 times --timeout",
+        long = "hang-timeout",
+        requires = "save_hangs"
+    )]
+    pub hang_timeout: Option<u64>,
+
+    #[arg(
+        help = "Calibrate the timeout of each target state before fuzzing, from the latencies of its prefix and seeds. The timeout is at most 10 times --timeout",
+        long = "calibrate-timeouts",
+        default_value = "false"
+    )]
+    pub calibrate_timeouts: bool,
+
+    #[arg(
+        help = "The calibrated timeout of a target state is this multiple of the 99th percentile of its latencies",
+        long = "timeout-multiplier",
+        default_value = "5",
+        requires = "calibrate_timeouts"
+    )]
+    pub timeout_multiplier: u32,
+
+    #[arg(
+        help = "Execute each new corpus entry this many times in its target state, after a fresh prefix, to find unstable edges and mask them out of the coverage feedback. Fewer than 2 disables it",
+        long = "stability-runs",
+        default_value = "0"
+    )]
+    pub stability_runs: usize,
+
+    #[arg(
+        help = "JSON file with response rules and a probe message that detect inputs moving the target out of the fuzzed target state (see src/drift.rs). The prefix is then sent again, and the inputs are stored in drift/",
+        long = "drift-rules"
+    )]
+    pub drift_rules: Option<PathBuf>,
+
+    #[arg(
+        help = "Restart the child after this many executions, and send the prefix again",
+        long = "restart-after-execs"
+    )]
+    pub restart_after_execs: Option<u64>,
+
+    #[arg(
+        help = "Restart the child after it ran this many seconds, and send the prefix again",
+        long = "restart-after-secs"
+    )]
+    pub restart_after_secs: Option<u64>,
+
+    #[arg(
+        help = "Restart the child once its resident set size exceeds this many MB, and send the prefix again",
+        long = "restart-max-rss"
+    )]
+    pub restart_max_rss: Option<u64>,
 }
 
 /// Parse a list of key-value pairs
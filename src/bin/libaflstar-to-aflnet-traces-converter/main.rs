@@ -1,24 +1,10 @@
 mod cli;
 
-use std::{
-    fs::{File, OpenOptions},
-    io::{BufReader, Write},
-};
+use std::{fs::OpenOptions, io::Write};
 
 use clap::Parser;
 use libafl::Error;
-use serde::{Deserialize, Serialize};
-
-/// Request response pair that just handles bytes (u8) which can be serialized.
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RequestResponsePair {
-    // execution number of this request (test case)
-    ek: String,
-    // request
-    req: Vec<u8>,
-    // response
-    resp: Vec<u8>,
-}
+use libaflstar::replay::TraceReader;
 
 fn main() -> Result<(), Error> {
     let cli = cli::Cli::parse();
@@ -57,16 +43,9 @@ fn main() -> Result<(), Error> {
             .write(true)
             .open(out_dir.join(file_name))?;
 
-        let mut reader = BufReader::new(File::open(file.path())?);
         //      parse cbor file
-        loop {
-            let pair: RequestResponsePair = match ciborium::from_reader(&mut reader) {
-                Ok(a) => a,
-                Err(_) => {
-                    break;
-                }
-            };
-            let request = pair.req;
+        for message in TraceReader::open(&file.path())? {
+            let request = message?.req;
 
             //      write <len><bytes> to file
             let len = request.len() as u32;
@@ -0,0 +1,104 @@
+//! The command line interface of the trace dump
+
+use std::{error::Error, path::PathBuf};
+
+use clap::{Parser, ValueEnum};
+
+use crate::decode::Protocol;
+
+#[derive(Debug, Parser)]
+#[command(about = "Prints replay traces and crash records in a human-readable form")]
+pub struct Cli {
+    #[arg(
+        help = "A replay trace (trace_N.cbor) or crash record (<solution>.record.cbor), or a directory holding them (e.g. replay_traces/ or crashes/)",
+        short = 'i',
+        long = "in",
+        required = true
+    )]
+    pub input: PathBuf,
+
+    #[arg(
+        help = "Print annotated text, or one JSON object per file",
+        long = "format",
+        value_enum,
+        default_value = "text"
+    )]
+    pub format: OutputFormat,
+
+    #[arg(
+        help = "Decode the messages as lines of this protocol, auto detects it from the first request",
+        short = 'p',
+        long = "protocol",
+        value_enum,
+        default_value = "auto"
+    )]
+    pub protocol: Protocol,
+
+    #[arg(
+        help = "Always print a hex dump of the messages, by default only messages with non-printable bytes get one",
+        long = "hex",
+        default_value = "false"
+    )]
+    pub hex: bool,
+
+    #[arg(
+        help = "Only print messages that did not end with Ok, and skip files without them",
+        long = "only-crashes",
+        default_value = "false"
+    )]
+    pub only_crashes: bool,
+
+    #[arg(
+        help = "Only print traces and records of this target state, given by its index or name",
+        short = 's',
+        long = "state"
+    )]
+    pub state: Option<String>,
+
+    #[arg(
+        help = "Only print the messages in this range of indices, e.g. 3, 2..8, ..5 or 4.. (the end is exclusive)",
+        short = 'r',
+        long = "range",
+        value_parser = parse_range
+    )]
+    pub range: Option<MessageRange>,
+}
+
+/// How the files are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// A range of message indices, the end is exclusive
+#[derive(Debug, Clone, Copy)]
+pub struct MessageRange {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl MessageRange {
+    pub fn contains(&self, idx: usize) -> bool {
+        idx >= self.start && self.end.map_or(true, |end| idx < end)
+    }
+}
+
+/// Parse a range of message indices: `3`, `2..8`, `..5` or `4..`
+fn parse_range(s: &str) -> Result<MessageRange, Box<dyn Error + Send + Sync + 'static>> {
+    let Some((start, end)) = s.split_once("..") else {
+        let idx = s.parse()?;
+        return Ok(MessageRange {
+            start: idx,
+            end: Some(idx + 1),
+        });
+    };
+    Ok(MessageRange {
+        start: if start.is_empty() { 0 } else { start.parse()? },
+        end: if end.is_empty() {
+            None
+        } else {
+            Some(end.parse()?)
+        },
+    })
+}
@@ -0,0 +1,156 @@
+//! Decoding of the text based protocols of the case studies (FTP, HTTP and RTSP), line by line
+
+use clap::ValueEnum;
+
+/// The protocol the messages are decoded as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Protocol {
+    /// Detect the protocol from the first request
+    Auto,
+    /// Do not decode the messages
+    Raw,
+    Ftp,
+    Http,
+    Rtsp,
+}
+
+/// Commands of FTP (RFC 959) and its common extensions
+const FTP_COMMANDS: &[&str] = &[
+    "ABOR", "ACCT", "ALLO", "APPE", "AUTH", "CDUP", "CWD", "DELE", "EPRT", "EPSV", "FEAT", "HELP", "LIST", "MDTM",
+    "MKD", "MLSD", "MLST", "MODE", "NLST", "NOOP", "OPTS", "PASS", "PASV", "PBSZ", "PORT", "PROT", "PWD", "QUIT",
+    "REIN", "REST", "RETR", "RMD", "RNFR", "RNTO", "SITE", "SIZE", "SMNT", "STAT", "STOR", "STOU", "STRU", "SYST",
+    "TYPE", "USER", "XCUP", "XCWD", "XMKD", "XPWD", "XRMD",
+];
+
+/// The lines of a message, without line endings.
+fn lines(message: &[u8]) -> Vec<&[u8]> {
+    let mut lines: Vec<&[u8]> = message
+        .split(|&byte| byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .collect();
+    if message.ends_with(b"\n") {
+        lines.pop();
+    }
+    lines
+}
+
+/// Guess the protocol from the first request.
+pub fn detect(request: &[u8]) -> Protocol {
+    let Some(first_line) = lines(request).first().map(|line| String::from_utf8_lossy(line)) else {
+        return Protocol::Raw;
+    };
+    if first_line.contains(" RTSP/") {
+        Protocol::Rtsp
+    } else if first_line.contains(" HTTP/") {
+        Protocol::Http
+    } else if first_line
+        .split(' ')
+        .next()
+        .is_some_and(|command| FTP_COMMANDS.contains(&command.to_uppercase().as_str()))
+    {
+        Protocol::Ftp
+    } else {
+        Protocol::Raw
+    }
+}
+
+/// Decode a message of `protocol`, one description per line or header. Raw messages are not decoded.
+pub fn decode(protocol: Protocol, message: &[u8], is_request: bool) -> Vec<String> {
+    match (protocol, is_request) {
+        (Protocol::Auto | Protocol::Raw, _) => Vec::new(),
+        (Protocol::Ftp, true) => decode_ftp_request(message),
+        (Protocol::Ftp, false) => decode_ftp_reply(message),
+        (Protocol::Http, _) => decode_head("HTTP", message, is_request),
+        (Protocol::Rtsp, _) => decode_head("RTSP", message, is_request),
+    }
+}
+
+fn decode_ftp_request(message: &[u8]) -> Vec<String> {
+    lines(message)
+        .into_iter()
+        .map(|line| {
+            let line = String::from_utf8_lossy(line);
+            match line.split_once(' ') {
+                Some((command, argument)) => {
+                    format!("FTP command {}, argument {argument:?}", command.to_uppercase())
+                }
+                None => format!("FTP command {}", line.to_uppercase()),
+            }
+        })
+        .collect()
+}
+
+fn decode_ftp_reply(message: &[u8]) -> Vec<String> {
+    lines(message)
+        .into_iter()
+        .map(|line| {
+            let line = String::from_utf8_lossy(line);
+            let code = line.get(..3).filter(|code| code.bytes().all(|b| b.is_ascii_digit()));
+            match (code, line.get(3..4)) {
+                (Some(code), Some("-")) => format!(
+                    "FTP reply {code} ({}, continued): {}",
+                    ftp_reply_kind(code),
+                    &line[4..]
+                ),
+                (Some(code), Some(" ") | None) => format!(
+                    "FTP reply {code} ({}): {}",
+                    ftp_reply_kind(code),
+                    line.get(4..).unwrap_or_default()
+                ),
+                _ => format!("FTP reply text: {line}"),
+            }
+        })
+        .collect()
+}
+
+/// The meaning of the first digit of an FTP reply code
+fn ftp_reply_kind(code: &str) -> &'static str {
+    match code.as_bytes()[0] {
+        b'1' => "positive preliminary",
+        b'2' => "positive completion",
+        b'3' => "positive intermediate",
+        b'4' => "transient negative",
+        b'5' => "permanent negative",
+        _ => "unknown",
+    }
+}
+
+/// Decode the start line, headers and body of an HTTP or RTSP message.
+fn decode_head(name: &str, message: &[u8], is_request: bool) -> Vec<String> {
+    // the head ends with an empty line, everything after it is the body
+    let head_end = [b"\r\n\r\n".as_slice(), b"\n\n"].iter().find_map(|separator| {
+        message
+            .windows(separator.len())
+            .position(|window| window == *separator)
+            .map(|idx| idx + separator.len())
+    });
+    let (head, body) = message.split_at(head_end.unwrap_or(message.len()));
+
+    let mut decoded = Vec::new();
+    let mut lines = lines(head).into_iter();
+    if let Some(start_line) = lines.next() {
+        let start_line = String::from_utf8_lossy(start_line);
+        let parts: Vec<&str> = start_line.splitn(3, ' ').collect();
+        decoded.push(match (is_request, parts.as_slice()) {
+            (true, [method, uri, version]) => format!("{name} request {method} {uri} ({version})"),
+            (false, [version, status, reason]) => {
+                format!("{name} response {status} {reason} ({version})")
+            }
+            (false, [version, status]) => format!("{name} response {status} ({version})"),
+            _ => format!("{name} malformed start line: {start_line}"),
+        });
+    }
+    for line in lines.filter(|line| !line.is_empty()) {
+        let line = String::from_utf8_lossy(line);
+        decoded.push(match line.split_once(':') {
+            Some((header, value)) => format!("header {header}: {}", value.trim()),
+            None => format!("malformed header: {line}"),
+        });
+    }
+    if head_end.is_none() {
+        decoded.push("incomplete head, no empty line".to_string());
+    } else if !body.is_empty() {
+        decoded.push(format!("body, {} bytes", body.len()));
+    }
+    decoded
+}
@@ -0,0 +1,321 @@
+mod cli;
+mod decode;
+
+use std::path::Path;
+
+use clap::Parser;
+use libafl::Error;
+use libaflstar::{
+    crash_record::{is_crash_record, CrashRecord},
+    replay::{input_files, is_trace, TraceHeader, TraceReader},
+};
+use serde::Serialize;
+
+use crate::{
+    cli::{Cli, OutputFormat},
+    decode::Protocol,
+};
+
+
+/// A request or response, rendered in several ways.
+#[derive(Debug, Serialize)]
+struct DumpedBytes {
+    len: usize,
+    /// The printable bytes, with the others escaped
+    text: String,
+    hex: String,
+    /// Protocol-aware description of each line, see [`decode::decode`]
+    decoded: Vec<String>,
+}
+
+impl DumpedBytes {
+    fn new(bytes: &[u8], protocol: Protocol, is_request: bool) -> Self {
+        Self {
+            len: bytes.len(),
+            text: escape(bytes),
+            hex: bytes
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            decoded: decode::decode(protocol, bytes, is_request),
+        }
+    }
+}
+
+/// A message of a trace or crash record.
+#[derive(Debug, Serialize)]
+struct DumpedMessage {
+    index: usize,
+    /// Where the message of a crash record comes from: `prefix`, `history` or `input`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    part: Option<&'static str>,
+    exit_kind: String,
+    /// When the message was sent, in milliseconds since the UNIX epoch, 0 if unknown
+    time: u64,
+    execution: u64,
+    latency_us: u64,
+    request: DumpedBytes,
+    /// Crash records hold no responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<DumpedBytes>,
+    /// The raw bytes, for the hex dump of the text output
+    #[serde(skip)]
+    raw: (Vec<u8>, Option<Vec<u8>>),
+}
+
+/// A trace or crash record.
+#[derive(Debug, Serialize)]
+struct DumpedFile {
+    file: String,
+    /// `trace` or `crash_record`
+    kind: &'static str,
+    protocol: String,
+    /// The header of a trace, if it has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    header: Option<TraceHeader>,
+    /// The target state of a crash record, or of a trace with header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_idx: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_name: Option<String>,
+    /// The signal that terminated the target, for crash records
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signal: Option<i32>,
+    /// If the history of a crash record is truncated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    history_truncated: Option<bool>,
+    messages: Vec<DumpedMessage>,
+}
+
+/// Escape the non-printable bytes, like a Rust byte string.
+fn escape(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
+
+/// The protocol to decode the messages as, detected from the first request if `protocol` is `auto`.
+fn resolve_protocol(protocol: Protocol, first_request: Option<&[u8]>) -> Protocol {
+    match (protocol, first_request) {
+        (Protocol::Auto, Some(request)) => decode::detect(request),
+        (Protocol::Auto, None) => Protocol::Raw,
+        (protocol, _) => protocol,
+    }
+}
+
+/// Read a trace, with its responses and the metadata of each message.
+fn dump_trace(path: &Path, protocol: Protocol) -> Result<DumpedFile, Error> {
+    let reader = TraceReader::open(path)?;
+    let header = reader.header().cloned();
+    let messages = reader.collect::<Result<Vec<_>, _>>()?;
+    let protocol = resolve_protocol(protocol, messages.first().map(|m| m.req.as_slice()));
+
+    let messages = messages
+        .into_iter()
+        .enumerate()
+        .map(|(index, message)| DumpedMessage {
+            index,
+            part: None,
+            exit_kind: message.ek,
+            time: message.time,
+            execution: message.execution,
+            latency_us: message.latency_us,
+            request: DumpedBytes::new(&message.req, protocol, true),
+            response: Some(DumpedBytes::new(&message.resp, protocol, false)),
+            raw: (message.req, Some(message.resp)),
+        })
+        .collect();
+
+    let target_state = header.as_ref().and_then(|header| header.target_state.clone());
+    Ok(DumpedFile {
+        file: path.display().to_string(),
+        kind: "trace",
+        protocol: format!("{protocol:?}"),
+        state_idx: target_state.as_ref().map(|state| state.idx),
+        state_name: target_state.map(|state| state.name),
+        header,
+        signal: None,
+        history_truncated: None,
+        messages,
+    })
+}
+
+/// Read a crash record: the prefix, the history and the input, without responses.
+fn dump_record(path: &Path, protocol: Protocol) -> Result<DumpedFile, Error> {
+    let record = CrashRecord::from_file(path)?;
+
+    // the history already starts with the prefix if it was sent to the child the solution was found in
+    let history_has_prefix = record.history.starts_with(&record.prefix);
+    let mut parts: Vec<(&'static str, &[u8])> = Vec::new();
+    if !history_has_prefix {
+        parts.extend(record.prefix.iter().map(|m| ("prefix", m.as_slice())));
+    }
+    parts.extend(record.history.iter().enumerate().map(|(idx, m)| {
+        if history_has_prefix && idx < record.prefix.len() {
+            ("prefix", m.as_slice())
+        } else {
+            ("history", m.as_slice())
+        }
+    }));
+    parts.push(("input", record.input.as_slice()));
+    let protocol = resolve_protocol(protocol, parts.first().map(|(_, m)| *m));
+
+    let last = parts.len() - 1;
+    let messages = parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, (part, message))| DumpedMessage {
+            index,
+            part: Some(part),
+            exit_kind: if index == last {
+                record.exit_kind.clone()
+            } else {
+                "Ok".to_string()
+            },
+            time: if index == last { record.time * 1000 } else { 0 },
+            execution: if index == last { record.executions } else { 0 },
+            latency_us: 0,
+            request: DumpedBytes::new(message, protocol, true),
+            response: None,
+            raw: (message.to_vec(), None),
+        })
+        .collect();
+
+    Ok(DumpedFile {
+        file: path.display().to_string(),
+        kind: "crash_record",
+        protocol: format!("{protocol:?}"),
+        header: None,
+        state_idx: Some(record.state_idx),
+        state_name: Some(record.state_name),
+        signal: record.signal,
+        history_truncated: Some(record.history_truncated),
+        messages,
+    })
+}
+
+/// Apply the filters of the command line, returns `None` if nothing of the file is left.
+fn filter(mut dumped: DumpedFile, cli: &Cli) -> Option<DumpedFile> {
+    if let Some(state) = &cli.state {
+        let matches = match state.parse::<usize>() {
+            Ok(idx) => dumped.state_idx == Some(idx),
+            Err(_) => dumped.state_name.as_ref() == Some(state),
+        };
+        if !matches {
+            return None;
+        }
+    }
+    if let Some(range) = cli.range {
+        dumped.messages.retain(|message| range.contains(message.index));
+    }
+    if cli.only_crashes {
+        dumped.messages.retain(|message| message.exit_kind != "Ok");
+        if dumped.messages.is_empty() {
+            return None;
+        }
+    }
+    Some(dumped)
+}
+
+/// Print a hex dump, 16 bytes per line with offset and printable characters.
+fn print_hex_dump(bytes: &[u8]) {
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02x}")).collect();
+        let printable: String = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!("      {:08x}  {:<47}  |{printable}|", line * 16, hex.join(" "));
+    }
+}
+
+fn print_bytes(marker: &str, dumped: &DumpedBytes, raw: &[u8], always_hex: bool) {
+    println!("  {marker} ({} bytes) b\"{}\"", dumped.len, dumped.text);
+    let printable = raw
+        .iter()
+        .all(|byte| byte.is_ascii_graphic() || b" \r\n\t".contains(byte));
+    if always_hex || !printable {
+        print_hex_dump(raw);
+    }
+    for line in &dumped.decoded {
+        println!("      {line}");
+    }
+}
+
+fn print_text(dumped: &DumpedFile, always_hex: bool) {
+    println!("== {} ({}, {})", dumped.file, dumped.kind, dumped.protocol);
+    if let Some(header) = &dumped.header {
+        println!(
+            "   format version {}, campaign {}, started at {} ms",
+            header.version, header.campaign_id, header.start_time
+        );
+    }
+    if let (Some(idx), Some(name)) = (dumped.state_idx, &dumped.state_name) {
+        match dumped.header.as_ref().and_then(|h| h.target_state.as_ref()) {
+            Some(state) => println!(
+                "   target state {idx} ({name}), prefix hash {:016x}",
+                state.prefix_hash
+            ),
+            None => println!("   target state {idx} ({name})"),
+        }
+    }
+    if let Some(signal) = dumped.signal {
+        println!("   terminated by signal {signal}");
+    }
+    if dumped.history_truncated == Some(true) {
+        println!("   the history is truncated");
+    }
+
+    for message in &dumped.messages {
+        let mut line = format!("[{}] {}", message.index, message.exit_kind);
+        if let Some(part) = message.part {
+            line.push_str(&format!(" {part}"));
+        }
+        if message.time != 0 {
+            line.push_str(&format!(" time={}ms", message.time));
+        }
+        if message.execution != 0 {
+            line.push_str(&format!(" execution={}", message.execution));
+        }
+        if message.latency_us != 0 {
+            line.push_str(&format!(" latency={}us", message.latency_us));
+        }
+        println!("{line}");
+        print_bytes(">", &message.request, &message.raw.0, always_hex);
+        if let (Some(response), Some(raw)) = (&message.response, &message.raw.1) {
+            print_bytes("<", response, raw, always_hex);
+        }
+    }
+    println!();
+}
+
+fn main() -> Result<(), Error> {
+    let cli = Cli::parse();
+
+    // the given file, or all traces and crash records in the given directory
+    for path in input_files(cli.input.clone(), |path| is_trace(path) || is_crash_record(path))? {
+        let dumped = if is_crash_record(&path) {
+            dump_record(&path, cli.protocol)?
+        } else {
+            dump_trace(&path, cli.protocol)?
+        };
+        let Some(dumped) = filter(dumped, &cli) else {
+            continue;
+        };
+
+        match cli.format {
+            OutputFormat::Text => print_text(&dumped, cli.hex),
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string(&dumped)
+                    .map_err(|e| Error::serialize(format!("Could not write JSON: {e}")))?
+            ),
+        }
+    }
+    Ok(())
+}
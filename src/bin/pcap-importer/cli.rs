@@ -0,0 +1,50 @@
+//! The command line interface of the PCAP importer
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use libaflstar::pcap::{MessageSplit, Transport};
+
+#[derive(Debug, Parser)]
+#[command(about = "Converts a packet capture of client sessions into prefixes and seeds")]
+pub struct Cli {
+    #[arg(
+        help = "The PCAP or PCAPNG capture",
+        short = 'i',
+        long = "in-file",
+        required = true
+    )]
+    pub in_file: PathBuf,
+
+    #[arg(
+        help = "The directory to store the prefixes and seeds in, it can be used as the in-dir of the fuzzer",
+        short = 'o',
+        long = "out-dir",
+        required = true
+    )]
+    pub out_dir: PathBuf,
+
+    #[arg(
+        help = "The port the server listens on in the capture",
+        short = 'p',
+        long = "server-port",
+        required = true
+    )]
+    pub server_port: u16,
+
+    #[arg(
+        help = "The transport protocol the server speaks",
+        long = "transport",
+        value_enum,
+        default_value = "tcp"
+    )]
+    pub transport: Transport,
+
+    #[arg(
+        help = "How the TCP stream of a client is split into messages: at each response of the server, or at each segment",
+        long = "split",
+        value_enum,
+        default_value = "response"
+    )]
+    pub split: MessageSplit,
+}
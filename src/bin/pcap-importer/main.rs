@@ -0,0 +1,115 @@
+mod cli;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+};
+
+use clap::Parser;
+use libafl::Error;
+use libaflstar::pcap::read_sessions;
+
+/// A prefix found in the capture, i.e., the first messages of at least one session.
+struct FoundPrefix {
+    name: String,
+    messages: Vec<Vec<u8>>,
+    /// The different messages that were sent after this prefix
+    next: BTreeSet<Vec<u8>>,
+}
+
+fn main() -> Result<(), Error> {
+    env_logger::init();
+
+    let cli = cli::Cli::parse();
+
+    // check if the capture exists
+    if !cli.in_file.exists() {
+        return Err(Error::illegal_argument(format!(
+            "IN_FILE [{}] does not exist",
+            cli.in_file.display()
+        )));
+    }
+
+    // make output dir, exist if it already exists
+    let out_dir = cli.out_dir;
+    if out_dir.exists() {
+        if out_dir.read_dir()?.next().is_some() {
+            return Err(Error::illegal_argument(format!(
+                "OUT_DIR [{}] must be empty or not exist.",
+                out_dir.display()
+            )));
+        }
+    } else {
+        fs::create_dir(&out_dir)?;
+    }
+
+    let sessions = read_sessions(&cli.in_file, cli.transport, cli.server_port, cli.split)?;
+    if sessions.is_empty() {
+        return Err(Error::illegal_argument(format!(
+            "No {:?} sessions to port {} found in {}",
+            cli.transport,
+            cli.server_port,
+            cli.in_file.display()
+        )));
+    }
+
+    // Every sequence of first messages of a session is a prefix. Prefixes shared by several sessions are
+    // only stored once, and the number of different messages sent after a prefix are its outgoing edges.
+    let mut prefixes: Vec<FoundPrefix> = Vec::new();
+    let mut prefix_idx: HashMap<Vec<Vec<u8>>, usize> = HashMap::new();
+    let mut seeds: Vec<&Vec<u8>> = Vec::new();
+    let mut seen_seeds = BTreeSet::new();
+    for (session_no, session) in sessions.iter().enumerate() {
+        log::info!(
+            "Session {session_no} from {}:{} with {} messages",
+            session.client.0,
+            session.client.1,
+            session.messages.len()
+        );
+        for len in 0..=session.messages.len() {
+            let messages = session.messages[..len].to_vec();
+            let idx = *prefix_idx.entry(messages.clone()).or_insert_with(|| {
+                let name = if len == 0 {
+                    "000_initial".to_string()
+                } else {
+                    format!("{:03}_s{session_no}_m{len}", prefixes.len())
+                };
+                prefixes.push(FoundPrefix {
+                    name,
+                    messages,
+                    next: BTreeSet::new(),
+                });
+                prefixes.len() - 1
+            });
+            if let Some(next) = session.messages.get(len) {
+                prefixes[idx].next.insert(next.clone());
+            }
+        }
+        for message in &session.messages {
+            if seen_seeds.insert(message) {
+                seeds.push(message);
+            }
+        }
+    }
+
+    for prefix in &prefixes {
+        let dir = out_dir.join(&prefix.name);
+        fs::create_dir(&dir)?;
+        for (idx, message) in prefix.messages.iter().enumerate() {
+            fs::write(dir.join(format!("{idx:04}")), message)?;
+        }
+        fs::write(dir.join("metadata"), prefix.next.len().to_string())?;
+    }
+    for (idx, seed) in seeds.iter().enumerate() {
+        fs::write(out_dir.join(format!("seed_{idx:04}")), seed)?;
+    }
+
+    println!(
+        "Imported {} sessions: {} prefixes and {} seeds written to {}",
+        sessions.len(),
+        prefixes.len(),
+        seeds.len(),
+        out_dir.display()
+    );
+    Ok(())
+}
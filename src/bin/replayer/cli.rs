@@ -3,6 +3,7 @@
 use std::{error::Error, path::PathBuf};
 
 use clap::Parser;
+use libaflstar::executor::memlimit::MemLimitMode;
 use nix::sys::signal::Signal;
 
 #[derive(Debug, Parser)]
@@ -64,12 +65,40 @@ pub struct Cli {
     pub environment_variables: Option<std::vec::Vec<(String, String)>>,
 
     #[arg(
-        help = "Port the target uses",
+        help = "Port the target uses. If not given, a free port is picked, which is filled in wherever @@PORT@@ is used in the target's arguments, environment variables and config templates",
         short = 'p',
-        long = "target-port",
-        required = true
+        long = "target-port"
+    )]
+    pub target_port: Option<u16>,
+
+    #[arg(
+        help = "Config file templates in which @@PORT@@ is replaced by the target port, as TEMPLATE=DESTINATION pairs",
+        long = "config-template",
+        value_parser = parse_key_val_pairs::<PathBuf, PathBuf>,
+    )]
+    pub config_templates: Option<std::vec::Vec<(PathBuf, PathBuf)>>,
+
+    #[arg(
+        help = "Run the target in its own network namespace with only loopback up, so instances can share a port and the target cannot reach the network (requires root)",
+        long = "netns",
+        default_value = "false"
+    )]
+    pub netns: bool,
+
+    #[arg(
+        help = "Memory limit of the target in MB, 0 means no limit",
+        long = "mem-limit",
+        default_value = "0"
+    )]
+    pub mem_limit: u64,
+
+    #[arg(
+        help = "How the memory limit is enforced: rlimit limits the address space (does not go well with ASAN), cgroup limits the used memory (requires root)",
+        long = "mem-limit-mode",
+        value_enum,
+        default_value = "rlimit"
     )]
-    pub target_port: u16,
+    pub mem_limit_mode: MemLimitMode,
 
     #[arg(
         help = "Signal used to stop child",
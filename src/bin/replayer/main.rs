@@ -1,12 +1,18 @@
 mod cli;
 
-use std::{fs::File, io::BufReader, path::PathBuf, process::exit, time::Duration};
+use std::{path::PathBuf, process::exit, time::Duration};
 
 use clap::Parser;
 
 use libaflstar::{
+    child_output::{ChildOutput, ChildOutputFeedback, ChildOutputObserver},
+    crash_dedup::CrashDedupFeedback,
+    crash_record::{Conversation, ConversationObserver, CrashRecordFeedback},
     event_manager::LibAFLStarManager,
     executor::{forkserver::ForkserverExecutor, StatefulPersistentExecutor},
+    feedbacks::{OomFeedback, SeparateSolutionsFeedback},
+    port,
+    replay::TraceReader,
     state::{LibAFLStarState, Prefix, PrefixMetadata},
 };
 use libafl::{
@@ -29,18 +35,6 @@ use libafl_bolts::{
     tuples::{tuple_list, MatchName},
     AsMutSlice, Error, Truncate,
 };
-use serde::{Deserialize, Serialize};
-
-/// Request response pair that just handles bytes (u8) which can be serialized.
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RequestResponsePair {
-    // execution number of this request (test case)
-    ek: String,
-    // request
-    req: Vec<u8>,
-    // response
-    resp: Vec<u8>,
-}
 
 #[allow(clippy::similar_names)]
 fn main() -> Result<(), Error> {
@@ -83,6 +77,14 @@ fn main() -> Result<(), Error> {
     // Create an observation channel to keep track of the execution time
     let time_observer = TimeObserver::new("time");
 
+    // Create an observation channel to capture the output of the target, it is stored next to the solutions
+    let child_output = ChildOutput::default();
+    let child_output_observer = ChildOutputObserver::new("child_output", child_output.clone());
+
+    // Create an observation channel that keeps the conversation with the target, it is stored with the solutions
+    let conversation = Conversation::default();
+    let conversation_observer = ConversationObserver::new("conversation", conversation.clone());
+
     // Feedback to rate the interestingness of an input
     // This one is composed by two Feedbacks in OR
     let mut feedback = feedback_or!(
@@ -94,12 +96,35 @@ fn main() -> Result<(), Error> {
 
     // A feedback to choose if an input is a solution or not
     // We want to do the same crash deduplication that AFL does
-    let mut objective = feedback_and_fast!(
-        // Must be a crash
-        CrashFeedback::new(),
-        // Take it only if trigger new coverage over crashes
-        // Uses `with_name` to create a different history from the `MaxMapFeedback` in `feedback` above
-        MaxMapFeedback::with_name("mapfeedback_metadata_objective", &edges_observer)
+    let mut objective = feedback_or!(
+        feedback_and_fast!(
+            // Must be a crash
+            CrashFeedback::new(),
+            // Take it only if it is a new bug, over the crashes of all target states.
+            // Crashes are bucketed by the stack trace of the sanitizer report, without one they must reach new coverage
+            CrashDedupFeedback::new(
+                &edges_observer,
+                Some(&child_output_observer),
+                out_dir.join("crash_buckets.txt"),
+            )
+        ),
+        // Never interesting by itself, but stores the output of the target next to the solution
+        ChildOutputFeedback::new(&child_output_observer, out_dir.join("crashes")),
+        // Same, but stores the target state and the conversation that led to the solution
+        CrashRecordFeedback::new(&conversation_observer, out_dir.join("crashes")),
+        // Inputs that make the target run out of memory are no crashes, they are stored apart in `ooms/`
+        SeparateSolutionsFeedback::new(
+            "oom_solutions",
+            feedback_or!(
+                feedback_and_fast!(
+                    OomFeedback::new(),
+                    MaxMapFeedback::with_name("mapfeedback_metadata_oom", &edges_observer)
+                ),
+                ChildOutputFeedback::new(&child_output_observer, out_dir.join("ooms")),
+                CrashRecordFeedback::new(&conversation_observer, out_dir.join("ooms"))
+            ),
+            out_dir.join("ooms"),
+        )?
     );
 
     let monitor = OnDiskJSONMonitor::new(
@@ -118,8 +143,23 @@ fn main() -> Result<(), Error> {
     // If we should debug the child
     let debug_child = cli.debug_child;
 
+    // Pick the port of the target and fill it in wherever the placeholder is used
+    let target_port = match cli.target_port {
+        Some(port) => port,
+        None => port::find_free_port()?,
+    };
+    port::record_port(&out_dir, target_port)?;
+    log::info!("Target port: {target_port}");
+    for (template, destination) in cli.config_templates.iter().flatten() {
+        port::render_template(template, destination, target_port)?;
+    }
+
     // Create the executor for the forkserver
-    let args = cli.arguments;
+    let args: Vec<String> = cli
+        .arguments
+        .iter()
+        .map(|arg| port::substitute_port(arg, target_port))
+        .collect();
 
     // Kill signal to kill the target:
     let kill_signal = cli.signal;
@@ -128,20 +168,33 @@ fn main() -> Result<(), Error> {
 
     let mut frsv_builder = ForkserverExecutor::builder();
     if let Some(env_vars) = cli.environment_variables {
-        frsv_builder = frsv_builder.envs(env_vars);
+        frsv_builder = frsv_builder.envs(
+            env_vars
+                .into_iter()
+                .map(|(key, val)| (key, port::substitute_port(&val, target_port))),
+        );
     }
 
     let mut fsrv_executor = frsv_builder
         .program(cli.executable)
         .debug_child(debug_child)
-        .socket_client_port(cli.target_port)
+        .socket_client_port(target_port)
+        .network_namespace(cli.netns)
         .autotokens(&mut tokens)
         .is_persistent(true)
         .timeout(timeout_duration)
         .parse_afl_cmdline(args)
         .coverage_map_size(MAP_SIZE)
         .kill_signal(kill_signal)
-        .build(tuple_list!(time_observer, edges_observer))
+        .capture_child_output(child_output)
+        .record_conversation(conversation)
+        .memlimit(cli.mem_limit, cli.mem_limit_mode)
+        .build(tuple_list!(
+            time_observer,
+            edges_observer,
+            child_output_observer,
+            conversation_observer
+        ))
         .expect("Building forkserver");
 
     if let Some(dynamic_map_size) = fsrv_executor.coverage_map_size() {
@@ -162,7 +215,12 @@ fn main() -> Result<(), Error> {
 
     let prefixes = vec![Prefix {
         prefix: Vec::new(),
-        metadata: PrefixMetadata { outgoing_edges: 0 },
+        metadata: PrefixMetadata {
+            outgoing_edges: 0,
+            name: "empty".to_string(),
+            prefix_hash: 0,
+            tags: Default::default(),
+        },
     }];
 
     // create the LibAFLStarState
@@ -188,20 +246,17 @@ fn main() -> Result<(), Error> {
         println!("in_file does not exist!");
         exit(1)
     } else {
-        let mut reader = BufReader::new(File::open(trace_file)?);
-
-        loop {
-            let pair: RequestResponsePair = match ciborium::from_reader(&mut reader) {
-                Ok(a) => a,
-                Err(_) => {
-                    break;
-                }
-            };
-            if pair.ek == "Tm".to_owned() {
-                println!("Timeout pair: {:?}", pair);
+        let reader = TraceReader::open(&trace_file)?;
+        if let Some(header) = reader.header() {
+            println!("Trace header: {:?}", header);
+        }
+
+        for message in reader {
+            let message = message?;
+            if message.ek == "Tm" {
+                println!("Timeout pair: {:?}", message);
             }
-            let request = pair.req;
-            let input = BytesInput::new(request);
+            let input = BytesInput::new(message.req);
 
             let (result, _) = fuzzer.evaluate_input(&mut state, &mut executor, &mut mgr, input)?;
             println!("{:?}", result);
@@ -0,0 +1,42 @@
+//! The command line interface of the reproducer exporter
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use libaflstar::pcap::Transport;
+
+#[derive(Debug, Parser)]
+#[command(about = "Exports replay traces and crash records as standalone Python scripts and PCAP files")]
+pub struct Cli {
+    #[arg(
+        help = "A replay trace (trace_N.cbor) or crash record (<solution>.record.cbor), or a directory holding them (e.g. replay_traces/ or crashes/)",
+        short = 'i',
+        long = "in",
+        required = true
+    )]
+    pub input: PathBuf,
+
+    #[arg(
+        help = "The directory to store the scripts and PCAP files in",
+        short = 'o',
+        long = "out-dir",
+        required = true
+    )]
+    pub out_dir: PathBuf,
+
+    #[arg(
+        help = "The port the server listens on, used in the PCAP files and as default in the scripts",
+        short = 'p',
+        long = "target-port",
+        required = true
+    )]
+    pub target_port: u16,
+
+    #[arg(
+        help = "The transport protocol the server speaks",
+        long = "transport",
+        value_enum,
+        default_value = "tcp"
+    )]
+    pub transport: Transport,
+}
@@ -0,0 +1,169 @@
+mod cli;
+
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+
+use clap::Parser;
+use libafl::Error;
+use libaflstar::{
+    crash_record::{is_crash_record, CrashRecord, CRASH_RECORD_SUFFIX},
+    pcap::{Direction, PcapWriter, Transport},
+    replay::{input_files, is_trace, TraceReader, RESPONSE_ERROR},
+    verify::full_conversation,
+};
+
+/// The messages of a trace or crash record, in order, and who sent them.
+fn read_conversation(path: &Path) -> Result<Vec<(Direction, Vec<u8>)>, Error> {
+    if is_crash_record(path) {
+        let record = CrashRecord::from_file(path)?;
+        return Ok(full_conversation(&record)
+            .into_iter()
+            .map(|message| (Direction::ToServer, message))
+            .collect());
+    }
+
+    let mut conversation = Vec::new();
+    for message in TraceReader::open(path)? {
+        let message = message?;
+        conversation.push((Direction::ToServer, message.req));
+        if !message.resp.is_empty() && message.resp != RESPONSE_ERROR {
+            conversation.push((Direction::ToClient, message.resp));
+        }
+    }
+    Ok(conversation)
+}
+
+/// A Python bytes literal holding `bytes`.
+fn python_bytes(bytes: &[u8]) -> String {
+    let mut literal = String::from("b\"");
+    for &byte in bytes {
+        match byte {
+            b'\\' => literal.push_str("\\\\"),
+            b'"' => literal.push_str("\\\""),
+            b'\n' => literal.push_str("\\n"),
+            b'\r' => literal.push_str("\\r"),
+            b'\t' => literal.push_str("\\t"),
+            0x20..=0x7e => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\x{byte:02x}")),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// A standalone Python script that sends `requests` to a running server and prints its responses.
+fn python_script(source: &str, requests: &[&[u8]], transport: Transport, port: u16) -> String {
+    let messages: String = requests
+        .iter()
+        .map(|request| format!("    {},\n", python_bytes(request)))
+        .collect();
+    format!(
+        r#"#!/usr/bin/env python3
+"""Reproducer exported by LibAFLstar from {source}.
+
+Sends the messages, in order, over a single connection to a running server and prints the responses.
+Usage: python3 <this script> [host] [port]
+"""
+import socket
+import sys
+
+HOST = sys.argv[1] if len(sys.argv) > 1 else "127.0.0.1"
+PORT = int(sys.argv[2]) if len(sys.argv) > 2 else {port}
+UDP = {udp}
+
+MESSAGES = [
+{messages}]
+
+
+def main():
+    if UDP:
+        sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+        sock.connect((HOST, PORT))
+    else:
+        sock = socket.create_connection((HOST, PORT))
+    sock.settimeout(0.5)
+    for i, message in enumerate(MESSAGES):
+        print(f"> [{{i}}] {{message!r}}")
+        try:
+            sock.sendall(message)
+            response = sock.recv(65536)
+        except socket.timeout:
+            continue
+        except OSError as e:
+            print(f"connection lost: {{e}}")
+            break
+        if response:
+            print(f"< {{response!r}}")
+        elif not UDP:
+            print("connection closed by the server")
+            break
+    sock.close()
+
+
+if __name__ == "__main__":
+    main()
+"#,
+        udp = if transport == Transport::Udp {
+            "True"
+        } else {
+            "False"
+        },
+    )
+}
+
+fn main() -> Result<(), Error> {
+    let cli = cli::Cli::parse();
+
+    // make output dir, exist if it already exists
+    let out_dir = cli.out_dir;
+    if out_dir.exists() {
+        if out_dir.read_dir()?.next().is_some() {
+            return Err(Error::illegal_argument(format!(
+                "OUT_DIR [{}] must be empty or not exist.",
+                out_dir.display()
+            )));
+        }
+    } else {
+        fs::create_dir(&out_dir)?;
+    }
+
+    // the given file, or all traces and crash records in the given directory
+    let files = input_files(cli.input, |path| is_trace(path) || is_crash_record(path))?;
+    for path in &files {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let stem = name
+            .strip_suffix(CRASH_RECORD_SUFFIX)
+            .or_else(|| name.strip_suffix(".cbor"))
+            .or_else(|| name.strip_suffix(".cbor.gz"))
+            .unwrap_or(&name);
+        let conversation = read_conversation(path)?;
+
+        let requests: Vec<&[u8]> = conversation
+            .iter()
+            .filter(|(direction, _)| *direction == Direction::ToServer)
+            .map(|(_, message)| message.as_slice())
+            .collect();
+        let script_path = out_dir.join(format!("{stem}.py"));
+        fs::write(
+            &script_path,
+            python_script(&name, &requests, cli.transport, cli.target_port),
+        )?;
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755))?;
+
+        let writer = BufWriter::new(File::create(out_dir.join(format!("{stem}.pcap")))?);
+        let mut pcap = PcapWriter::new(writer, cli.transport, cli.target_port)?;
+        for (direction, message) in &conversation {
+            pcap.write_message(*direction, message)?;
+        }
+        pcap.finish()?;
+
+        println!("Exported {name}: {} requests", requests.len());
+    }
+
+    println!("Exported {} files to {}", files.len(), out_dir.display());
+    Ok(())
+}
@@ -0,0 +1,256 @@
+mod cli;
+
+use std::{
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+use clap::Parser;
+
+use libaflstar::{
+    child_output::ChildOutput,
+    event_manager::LibAFLStarManager,
+    executor::{forkserver::ForkserverExecutor, StatefulPersistentExecutor},
+    minimize::TraceMinimizer,
+    port,
+    replay::{TraceHeader, TraceMessage, TraceReader, TraceWriter},
+    state::{LibAFLStarState, Prefix, PrefixMetadata},
+};
+use libafl::{
+    corpus::InMemoryCorpus,
+    executors::HasObservers,
+    fuzzer::StdFuzzer,
+    inputs::BytesInput,
+    monitors::MultiMonitor,
+    observers::{HitcountsMapObserver, StdMapObserver, TimeObserver},
+    schedulers::QueueScheduler,
+};
+use libafl_bolts::{
+    current_nanos,
+    rands::StdRand,
+    shmem::{ShMem, ShMemProvider, UnixShMemProvider},
+    tuples::{tuple_list, MatchName},
+    AsMutSlice, Error, Truncate,
+};
+
+/// Read the header, if any, and the messages of a replay trace.
+fn read_trace(path: &Path) -> Result<(Option<TraceHeader>, Vec<TraceMessage>), Error> {
+    if !path.exists() {
+        return Err(Error::illegal_argument(format!(
+            "IN_FILE [{}] does not exist",
+            path.display()
+        )));
+    }
+    let reader = TraceReader::open(path)?;
+    let header = reader.header().cloned();
+    let messages = reader.collect::<Result<Vec<_>, _>>()?;
+    Ok((header, messages))
+}
+
+/// Write the minimized messages as a replay trace. The responses are not known, so they are left empty.
+///
+/// The trace keeps the campaign and target state of the original trace, if it had a header.
+fn write_trace(
+    path: &Path,
+    original: Option<&TraceHeader>,
+    messages: &[Vec<u8>],
+    exit_kind: &str,
+) -> Result<(), Error> {
+    let header = match original {
+        Some(original) => TraceHeader::new(&original.campaign_id, original.target_state.clone()),
+        None => TraceHeader::new("minimized", None),
+    };
+    let mut writer = TraceWriter::create(path, &header)?;
+    for (idx, message) in messages.iter().enumerate() {
+        let message = TraceMessage {
+            ek: if idx + 1 == messages.len() {
+                exit_kind.to_string()
+            } else {
+                "Ok".to_string()
+            },
+            req: message.clone(),
+            ..TraceMessage::default()
+        };
+        writer.write_message(&message)?;
+    }
+    writer.flush()
+}
+
+/// Write all messages but the last as a prefix directory, which can be used as a target state by the fuzzer,
+/// and the last message as `crashing_input`.
+fn write_prefix_dir(out_dir: &Path, messages: &[Vec<u8>]) -> Result<(), Error> {
+    let prefix_dir = out_dir.join("prefixes").join("minimized");
+    fs::create_dir_all(&prefix_dir)?;
+    let Some((input, prefix)) = messages.split_last() else {
+        return Ok(());
+    };
+    for (idx, message) in prefix.iter().enumerate() {
+        fs::write(prefix_dir.join(format!("{idx:04}")), message)?;
+    }
+    fs::write(prefix_dir.join("metadata"), "0")?;
+    fs::write(out_dir.join("crashing_input"), input)?;
+    Ok(())
+}
+
+#[allow(clippy::similar_names)]
+fn main() -> Result<(), Error> {
+    env_logger::init();
+
+    const MAP_SIZE: usize = 65536;
+
+    let cli = cli::Cli::parse();
+
+    // Get out dir ready
+    let out_dir = cli.out_dir;
+    if out_dir.exists() {
+        if out_dir.read_dir()?.next().is_some() {
+            return Err(Error::illegal_argument(format!(
+                "OUT_DIR [{}] must be empty or not exist.",
+                out_dir.display()
+            )));
+        }
+    } else {
+        std::fs::create_dir(&out_dir)?;
+    }
+
+    // Read the trace, up to the message that did not end well
+    let (header, trace) = read_trace(&cli.in_file)?;
+    let Some(last) = trace.iter().position(|message| message.ek != "Ok") else {
+        return Err(Error::illegal_argument(format!(
+            "The trace [{}] does not contain a crash",
+            cli.in_file.display()
+        )));
+    };
+    let expected = trace[last].ek.clone();
+    let messages: Vec<Vec<u8>> = trace
+        .into_iter()
+        .take(last + 1)
+        .map(|message| message.req)
+        .collect();
+    println!("Minimizing {} messages ending with {expected}", messages.len());
+
+    let timeout_duration = Duration::from_millis(cli.timeout);
+
+    // The unix shmem provider supported by AFL++ for shared memory
+    let mut shmem_provider = UnixShMemProvider::new().unwrap();
+
+    // The coverage map shared between observer and executor
+    let mut shmem = shmem_provider.new_shmem(MAP_SIZE).unwrap();
+    // let the forkserver know the shmid
+    shmem.write_to_env("__AFL_SHM_ID").unwrap();
+    let shmem_buf = shmem.as_mut_slice();
+
+    // Create an observation channel using the signals map
+    let edges_observer =
+        unsafe { HitcountsMapObserver::new(StdMapObserver::new("shared_mem", shmem_buf)) };
+
+    // Create an observation channel to keep track of the execution time
+    let time_observer = TimeObserver::new("time");
+
+    // The output of the target is captured to recognize sanitizer reports of failed allocations as out-of-memory
+    let child_output = ChildOutput::default();
+
+    // Nothing is stored, we only look at how each execution ends
+    let mut feedback = ();
+    let mut objective = ();
+
+    let monitor = MultiMonitor::new(|s| log::info!("{s}"));
+
+    // The event manager handle the various events generated during the fuzzing loop
+    let mut mgr = LibAFLStarManager::new(monitor);
+
+    // Pick the port of the target and fill it in wherever the placeholder is used
+    let target_port = match cli.target_port {
+        Some(port) => port,
+        None => port::find_free_port()?,
+    };
+    port::record_port(&out_dir, target_port)?;
+    log::info!("Target port: {target_port}");
+    for (template, destination) in cli.config_templates.iter().flatten() {
+        port::render_template(template, destination, target_port)?;
+    }
+
+    // Create the executor for the forkserver
+    let args: Vec<String> = cli
+        .arguments
+        .iter()
+        .map(|arg| port::substitute_port(arg, target_port))
+        .collect();
+
+    let mut frsv_builder = ForkserverExecutor::builder();
+    if let Some(env_vars) = cli.environment_variables {
+        frsv_builder = frsv_builder.envs(
+            env_vars
+                .into_iter()
+                .map(|(key, val)| (key, port::substitute_port(&val, target_port))),
+        );
+    }
+
+    let mut fsrv_executor = frsv_builder
+        .program(cli.executable)
+        .debug_child(cli.debug_child)
+        .socket_client_port(target_port)
+        .network_namespace(cli.netns)
+        .is_persistent(true)
+        .timeout(timeout_duration)
+        .parse_afl_cmdline(args)
+        .coverage_map_size(MAP_SIZE)
+        .kill_signal(cli.signal)
+        .capture_child_output(child_output)
+        .memlimit(cli.mem_limit, cli.mem_limit_mode)
+        .build(tuple_list!(time_observer, edges_observer))
+        .expect("Building forkserver");
+
+    if let Some(dynamic_map_size) = fsrv_executor.coverage_map_size() {
+        fsrv_executor
+            .observers_mut()
+            .match_name_mut::<HitcountsMapObserver<StdMapObserver<'_, u8, false>>>("shared_mem")
+            .unwrap()
+            .truncate(dynamic_map_size);
+    }
+
+    let mut executor = StatefulPersistentExecutor::new(fsrv_executor);
+
+    // The messages of the trace are sent as they are, so the state needs no prefixes of its own
+    let prefixes = vec![Prefix {
+        prefix: Vec::new(),
+        metadata: PrefixMetadata {
+            outgoing_edges: 0,
+            name: "empty".to_string(),
+            prefix_hash: 0,
+            tags: Default::default(),
+        },
+    }];
+
+    let mut state = LibAFLStarState::new(
+        StdRand::with_seed(current_nanos()),
+        vec![InMemoryCorpus::<BytesInput>::new()],
+        InMemoryCorpus::new(),
+        &mut feedback,
+        &mut objective,
+        prefixes,
+    )?;
+
+    let mut fuzzer = StdFuzzer::new(QueueScheduler::new(), feedback, objective);
+
+    let mut minimizer = TraceMinimizer::new(&expected, cli.attempts);
+    let minimized =
+        minimizer.minimize(&mut executor, &mut fuzzer, &mut state, &mut mgr, messages)?;
+
+    write_trace(
+        &out_dir.join("trace_minimized.cbor"),
+        header.as_ref(),
+        &minimized,
+        &expected,
+    )?;
+    write_prefix_dir(&out_dir, &minimized)?;
+
+    println!(
+        "Minimized to {} messages of {} bytes in total, after {} tests",
+        minimized.len(),
+        minimized.iter().map(Vec::len).sum::<usize>(),
+        minimizer.tests()
+    );
+    Ok(())
+}
@@ -0,0 +1,243 @@
+//! Calibration of the timeout of each target state.
+//!
+//! A single timeout does not fit every target state: data transfers or an RTSP `DESCRIBE` are legitimately slower than
+//! most commands, while a `NOOP` answers in a fraction of the timeout. [`calibrate_timeouts`] measures the latency of
+//! the prefix messages and the seeds in each target state before fuzzing, and stores a timeout per target state
+//! (see [`MultipleStates::timeout`]), which the executor switches to whenever the fuzzer switches target states.
+
+use std::time::{Duration, Instant};
+
+use libafl::{
+    corpus::Corpus,
+    events::ProgressReporter,
+    executors::ExitKind,
+    inputs::UsesInput,
+    state::{HasCorpus, HasExecutions, HasLastReportTime, HasMetadata, UsesState},
+    Error, ExecutesInput,
+};
+
+use crate::{
+    executor::ResettableForkserver,
+    replay::TraceTargetState,
+    state::{MultipleStates, TargetStateIdx},
+};
+
+/// Calibrated timeouts are never shorter than this, the latency of a fast target state is dominated by noise.
+pub const MIN_CALIBRATED_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Calibrated timeouts are at most this many times the timeout, which is also the timeout of the calibration runs.
+pub const MAX_TIMEOUT_FACTOR: u32 = 10;
+
+/// How the timeout of each target state is derived from the latencies measured in it.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeoutCalibration {
+    /// How often the prefix and seeds of each target state are executed
+    pub runs: usize,
+    /// The timeout of a target state is this multiple of the 99th percentile of its latencies
+    pub multiplier: u32,
+    /// The shortest timeout
+    pub min: Duration,
+    /// The longest timeout, calibration runs use it as their timeout
+    pub max: Duration,
+}
+
+impl TimeoutCalibration {
+    /// Calibrate timeouts of at most [`MAX_TIMEOUT_FACTOR`] times `timeout`, with a multiplier of 5 over 3 runs.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            runs: 3,
+            multiplier: 5,
+            min: MIN_CALIBRATED_TIMEOUT,
+            max: timeout * MAX_TIMEOUT_FACTOR,
+        }
+    }
+
+    /// Use `multiplier` times the 99th percentile of the latencies as timeout.
+    pub fn with_multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// The timeout for the `latencies` measured in a target state, [`TimeoutCalibration::max`] if there are none.
+    pub fn timeout(&self, latencies: &mut [Duration]) -> Duration {
+        if latencies.is_empty() {
+            return self.max;
+        }
+        latencies.sort_unstable();
+        let p99 = latencies[(latencies.len() * 99).div_ceil(100) - 1];
+        (p99 * self.multiplier).clamp(self.min, self.max)
+    }
+}
+
+/// Measure the latencies of the prefix messages and the seeds (i.e., the corpus) of each target state, and set the
+/// timeout of each target state accordingly.
+///
+/// An execution that times out counts with the latency [`TimeoutCalibration::max`]. After an execution that did not
+/// end with [`ExitKind::Ok`], the target is reset and the prefix is sent again.
+/// Leaves the first target state selected, call [`crate::fuzzer::change_target_state`] afterwards.
+pub fn calibrate_timeouts<Z, E, EM>(
+    fuzzer: &mut Z,
+    executor: &mut E,
+    state: &mut Z::State,
+    manager: &mut EM,
+    calibration: &TimeoutCalibration,
+) -> Result<(), Error>
+where
+    Z: ExecutesInput<E, EM>,
+    Z::State: MultipleStates + HasMetadata + HasExecutions + HasLastReportTime + HasCorpus,
+    E: UsesState<State = Z::State> + ResettableForkserver,
+    EM: ProgressReporter<State = Z::State>,
+{
+    executor.set_timeout(calibration.max);
+    for idx in 0..state.states_len() {
+        state.switch_state(TargetStateIdx(idx))?;
+        let metadata = &state.prefix().metadata;
+        executor.target_state_changed(TraceTargetState {
+            idx,
+            name: metadata.name.clone(),
+            prefix_hash: metadata.prefix_hash,
+        });
+
+        let prefix = state
+            .prefix()
+            .prefix
+            .iter()
+            .map(|testcase| {
+                testcase
+                    .input()
+                    .clone()
+                    .expect("Prefix testcases should always have input")
+            })
+            .collect::<Vec<_>>();
+        let ids = state.corpus().ids().collect::<Vec<_>>();
+        let mut seeds = Vec::with_capacity(ids.len());
+        for id in ids {
+            let input = state
+                .corpus()
+                .get(id)?
+                .borrow_mut()
+                .load_input(state.corpus())?
+                .clone();
+            seeds.push(input);
+        }
+
+        let mut latencies = Vec::new();
+        for _ in 0..calibration.runs {
+            executor.reset_target_state()?;
+            measure(fuzzer, executor, state, manager, &prefix, calibration, &mut latencies)?;
+            for seed in &seeds {
+                let exit_kind = measure(
+                    fuzzer,
+                    executor,
+                    state,
+                    manager,
+                    std::slice::from_ref(seed),
+                    calibration,
+                    &mut latencies,
+                )?;
+                if exit_kind != ExitKind::Ok || executor.state_reset_occurred() {
+                    executor.reset_target_state()?;
+                    measure(fuzzer, executor, state, manager, &prefix, calibration, &mut latencies)?;
+                }
+            }
+        }
+
+        let timeout = calibration.timeout(&mut latencies);
+        log::info!(
+            "Calibrated the timeout of target state {idx} ({}): {} ms over {} executions",
+            state.prefix().metadata.name,
+            timeout.as_millis(),
+            latencies.len()
+        );
+        *state.timeout() = Some(timeout);
+        manager.maybe_report_progress(state, Duration::from_secs(15))?;
+    }
+    state.switch_state(TargetStateIdx(0))?;
+    Ok(())
+}
+
+/// Execute the `inputs` one after the other and push their latencies, an execution that timed out counts with the
+/// latency [`TimeoutCalibration::max`]. Returns the exit kind of the last execution.
+fn measure<Z, E, EM>(
+    fuzzer: &mut Z,
+    executor: &mut E,
+    state: &mut Z::State,
+    manager: &mut EM,
+    inputs: &[<Z::State as UsesInput>::Input],
+    calibration: &TimeoutCalibration,
+    latencies: &mut Vec<Duration>,
+) -> Result<ExitKind, Error>
+where
+    Z: ExecutesInput<E, EM>,
+    E: UsesState<State = Z::State>,
+    EM: UsesState<State = Z::State>,
+{
+    let mut exit_kind = ExitKind::Ok;
+    for input in inputs {
+        let start = Instant::now();
+        exit_kind = fuzzer.execute_input(state, executor, manager, input)?;
+        latencies.push(match exit_kind {
+            ExitKind::Timeout => calibration.max,
+            _ => start.elapsed(),
+        });
+    }
+    Ok(exit_kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{TimeoutCalibration, MAX_TIMEOUT_FACTOR, MIN_CALIBRATED_TIMEOUT};
+
+    fn millis(latencies: &[u64]) -> Vec<Duration> {
+        latencies
+            .iter()
+            .copied()
+            .map(Duration::from_millis)
+            .collect()
+    }
+
+    #[test]
+    fn without_latencies_the_timeout_is_the_maximum() {
+        let calibration = TimeoutCalibration::new(Duration::from_secs(1));
+        assert_eq!(calibration.max, Duration::from_secs(1) * MAX_TIMEOUT_FACTOR);
+        assert_eq!(calibration.timeout(&mut []), calibration.max);
+    }
+
+    #[test]
+    fn multiple_of_the_99th_percentile() {
+        let calibration = TimeoutCalibration::new(Duration::from_secs(1));
+        // 99 fast executions and one outlier, which is above the 99th percentile
+        let mut latencies = millis(&[40; 99]);
+        latencies.push(Duration::from_millis(900));
+        assert_eq!(
+            calibration.timeout(&mut latencies),
+            Duration::from_millis(200)
+        );
+        // the latencies do not have to be sorted
+        let mut latencies = millis(&[10, 70, 30]);
+        assert_eq!(
+            calibration.timeout(&mut latencies),
+            Duration::from_millis(350)
+        );
+        let calibration = calibration.with_multiplier(2);
+        assert_eq!(
+            calibration.timeout(&mut millis(&[60])),
+            Duration::from_millis(120)
+        );
+    }
+
+    #[test]
+    fn clamped_to_min_and_max() {
+        let calibration = TimeoutCalibration::new(Duration::from_millis(100));
+        assert_eq!(
+            calibration.timeout(&mut millis(&[1, 2, 1])),
+            MIN_CALIBRATED_TIMEOUT
+        );
+        assert_eq!(
+            calibration.timeout(&mut millis(&[900])),
+            Duration::from_secs(1)
+        );
+    }
+}
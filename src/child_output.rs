@@ -0,0 +1,515 @@
+//! Capture of the output of the target.
+//!
+//! Without `--debug-child` the output of the target is discarded, with it the terminal is flooded. Instead, the
+//! [`crate::executor::forkserver::ForkserverExecutor`] can capture stdout and stderr into a bounded [`ChildOutput`] buffer,
+//! which is cleared each time a new child is started. It thus contains (the tail of) the output of the current child.
+//!
+//! The [`ChildOutputObserver`] takes a snapshot of the buffer when the child crashes or times out, and the
+//! [`ChildOutputFeedback`] stores that snapshot next to the solution, together with the classification of the
+//! sanitizer report in it, see [`SanitizerReport`].
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::Read,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use libafl::{
+    corpus::Testcase,
+    events::EventFirer,
+    executors::ExitKind,
+    feedbacks::Feedback,
+    inputs::UsesInput,
+    observers::{Observer, ObserversTuple},
+    state::{HasMetadata, State},
+    Error,
+};
+use libafl_bolts::{hash_std, impl_serdeany, tuples::MatchName, Named};
+use serde::{Deserialize, Serialize};
+
+use crate::feedbacks::solution_filename;
+
+/// Default number of bytes of output that are kept per child
+pub const CHILD_OUTPUT_CAPACITY_DEFAULT: usize = 64 * 1024;
+
+/// Bug types (or parts of the summary) of sanitizer reports about failing allocations, see [`SanitizerReport::is_oom`].
+const OOM_BUG_TYPES: [&str; 5] = [
+    "allocation-size-too-big",
+    "requested allocation size",
+    "out of memory",
+    "out-of-memory",
+    "rss-limit-exceeded",
+];
+
+/// Number of (top) stack frames that make up the stack hash, see [`SanitizerReport::stack_hash`].
+pub const STACK_HASH_FRAMES: usize = 5;
+
+/// How long the output must be quiet before a snapshot is taken, see [`ChildOutput::settle`].
+const SETTLE_QUIET: Duration = Duration::from_millis(10);
+/// The maximum time to wait for the output to become quiet.
+const SETTLE_MAX: Duration = Duration::from_millis(250);
+
+#[derive(Debug)]
+struct OutputRing {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    /// When the last bytes were written
+    last_write: Instant,
+}
+
+/// Ring buffer holding the last `capacity` bytes written by the target to stdout and stderr.
+///
+/// Cloning gives a handle to the same buffer.
+#[derive(Debug, Clone)]
+pub struct ChildOutput {
+    inner: Arc<Mutex<OutputRing>>,
+}
+
+impl Default for ChildOutput {
+    fn default() -> Self {
+        Self::new(CHILD_OUTPUT_CAPACITY_DEFAULT)
+    }
+}
+
+impl ChildOutput {
+    /// Create a new buffer that keeps the last `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(OutputRing {
+                buf: VecDeque::with_capacity(capacity),
+                capacity,
+                last_write: Instant::now(),
+            })),
+        }
+    }
+
+    /// Append `bytes`, dropping the oldest bytes if the buffer is full.
+    pub fn push(&self, bytes: &[u8]) {
+        let mut ring = self.inner.lock().unwrap();
+        let bytes = &bytes[bytes.len().saturating_sub(ring.capacity)..];
+        let overflow = (ring.buf.len() + bytes.len()).saturating_sub(ring.capacity);
+        ring.buf.drain(..overflow);
+        ring.buf.extend(bytes);
+        ring.last_write = Instant::now();
+    }
+
+    /// Throw away everything, e.g., because a new child is started.
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().buf.clear();
+    }
+
+    /// A copy of the current contents.
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.inner.lock().unwrap().buf.iter().copied().collect()
+    }
+
+    /// Wait (shortly) until nothing was written for a little while.
+    ///
+    /// A crashing child writes its sanitizer report right before it dies, this gives the
+    /// capture threads the chance to catch up with it.
+    pub fn settle(&self) {
+        let start = Instant::now();
+        while start.elapsed() < SETTLE_MAX {
+            let quiet = self.inner.lock().unwrap().last_write.elapsed();
+            if quiet >= SETTLE_QUIET {
+                break;
+            }
+            sleep(SETTLE_QUIET - quiet);
+        }
+    }
+
+    /// Spawn a thread that copies everything from `reader` into this buffer, until EOF.
+    pub fn capture<R>(&self, mut reader: R) -> JoinHandle<()>
+    where
+        R: Read + Send + 'static,
+    {
+        let output = self.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => output.push(&buf[..n]),
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        log::debug!("Stopped capturing the output of the target: {e}");
+                        break;
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Classification of a sanitizer report, parsed from its header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SanitizerReport {
+    /// The sanitizer that reported, e.g. `AddressSanitizer`
+    pub sanitizer: String,
+    /// The kind of bug, e.g. `heap-buffer-overflow` or `signed integer overflow`
+    pub bug_type: String,
+    /// The `SUMMARY:` line of the report, if any
+    pub summary: Option<String>,
+    /// Hash of the function names of the top [`STACK_HASH_FRAMES`] frames of the stack trace in the report, if any
+    #[serde(default)]
+    pub stack_hash: Option<u64>,
+}
+
+impl SanitizerReport {
+    /// Parse the first ASAN (or LSAN, MSAN, TSAN) or UBSAN report in `output`.
+    ///
+    /// ASAN style reports start with `==<pid>==ERROR: AddressSanitizer: <bug type> on ...`,
+    /// UBSAN reports with `<file>:<line>:<col>: runtime error: <bug type>: ...`.
+    pub fn parse(output: &str) -> Option<Self> {
+        let summary = output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("SUMMARY: "))
+            .map(ToString::to_string);
+        let stack_hash = Self::parse_stack_hash(output);
+
+        output.lines().find_map(|line| {
+            let header = line
+                .split_once("ERROR: ")
+                .or_else(|| line.split_once("WARNING: "))
+                .and_then(|(_, rest)| rest.split_once(": "))
+                .filter(|(sanitizer, _)| sanitizer.ends_with("Sanitizer"));
+            if let Some((sanitizer, description)) = header {
+                // e.g. `heap-buffer-overflow on address ...` or `data race (pid=...)`
+                let description = description.split('(').next().unwrap_or_default();
+                let bug_type: Vec<_> = description
+                    .split_whitespace()
+                    .take_while(|word| *word != "on")
+                    .collect();
+                return Some(Self {
+                    sanitizer: sanitizer.to_string(),
+                    bug_type: bug_type.join(" "),
+                    summary: summary.clone(),
+                    stack_hash,
+                });
+            }
+            line.split_once("runtime error: ").map(|(_, description)| {
+                // e.g. `signed integer overflow: 2147483647 + 1 cannot be represented ...`
+                let bug_type = description
+                    .split_once(':')
+                    .map_or(description, |(bug_type, _)| bug_type);
+                Self {
+                    sanitizer: "UndefinedBehaviorSanitizer".to_string(),
+                    bug_type: bug_type.trim().to_string(),
+                    summary: summary.clone(),
+                    stack_hash,
+                }
+            })
+        })
+    }
+
+    /// Hash the function names of the first stack trace after the report header.
+    ///
+    /// Frames look like `#0 0x4f1c2b in parse_cmd /src/server.c:12:3`. Only the function names are used,
+    /// so the hash does not change when unrelated code moves around.
+    fn parse_stack_hash(output: &str) -> Option<u64> {
+        let functions: Vec<&str> = output
+            .lines()
+            .skip_while(|line| !line.contains("ERROR: ") && !line.contains("runtime error: "))
+            .map(str::trim)
+            .skip_while(|line| !line.starts_with('#'))
+            .take_while(|line| line.starts_with('#'))
+            .filter_map(|frame| frame.split_once(" in ").map(|(_, rest)| rest))
+            .filter_map(|rest| rest.split_whitespace().next())
+            .take(STACK_HASH_FRAMES)
+            .collect();
+        if functions.is_empty() {
+            return None;
+        }
+        Some(hash_std(functions.join("\n").as_bytes()))
+    }
+
+    /// If the report is about an allocation that failed, rather than a memory safety bug.
+    pub fn is_oom(&self) -> bool {
+        OOM_BUG_TYPES.iter().any(|oom| {
+            self.bug_type.contains(oom)
+                || self
+                    .summary
+                    .as_ref()
+                    .is_some_and(|summary| summary.contains(oom))
+        })
+    }
+}
+
+/// Takes a snapshot of the [`ChildOutput`] whenever the child crashes, times out or runs out of memory.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChildOutputObserver {
+    name: String,
+    #[serde(skip)]
+    child_output: ChildOutput,
+    output: Option<Vec<u8>>,
+}
+
+impl ChildOutputObserver {
+    /// Create a new [`ChildOutputObserver`] reading from `child_output`, which should be the buffer
+    /// that is given to the executor.
+    pub fn new(name: &str, child_output: ChildOutput) -> Self {
+        Self {
+            name: name.to_string(),
+            child_output,
+            output: None,
+        }
+    }
+
+    /// The output of the child, if the last execution crashed, timed out or ran out of memory.
+    pub fn output(&self) -> Option<&[u8]> {
+        self.output.as_deref()
+    }
+}
+
+impl Named for ChildOutputObserver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Observer<S> for ChildOutputObserver
+where
+    S: UsesInput,
+{
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.output = None;
+        Ok(())
+    }
+
+    fn post_exec(
+        &mut self,
+        _state: &mut S,
+        _input: &S::Input,
+        exit_kind: &ExitKind,
+    ) -> Result<(), Error> {
+        if matches!(exit_kind, ExitKind::Crash | ExitKind::Timeout | ExitKind::Oom) {
+            self.child_output.settle();
+            self.output = Some(self.child_output.snapshot());
+        }
+        Ok(())
+    }
+}
+
+/// Metadata added to a solution by the [`ChildOutputFeedback`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChildOutputMetadata {
+    /// The file the output of the child was written to
+    pub output_file: PathBuf,
+    /// The sanitizer report found in the output, if any
+    pub report: Option<SanitizerReport>,
+}
+
+impl_serdeany!(ChildOutputMetadata);
+
+/// Writes the output captured by a [`ChildOutputObserver`] next to the solution, as `<solution>.output`.
+///
+/// It never makes an input interesting by itself, combine it using `feedback_or!` with the objective, so it only
+/// gets to add its metadata to solutions.
+#[derive(Debug)]
+pub struct ChildOutputFeedback {
+    name: String,
+    observer_name: String,
+    solutions_dir: PathBuf,
+}
+
+impl ChildOutputFeedback {
+    /// Create a new [`ChildOutputFeedback`] for the `observer`, where `solutions_dir` is the directory of the
+    /// solutions corpus (e.g. `crashes/`).
+    pub fn new(observer: &ChildOutputObserver, solutions_dir: PathBuf) -> Self {
+        Self {
+            name: format!("{}_feedback", observer.name()),
+            observer_name: observer.name().to_string(),
+            solutions_dir,
+        }
+    }
+}
+
+impl Named for ChildOutputFeedback {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Feedback<S> for ChildOutputFeedback
+where
+    S: State,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        Ok(false)
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        _state: &mut S,
+        observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        let Some(output) = observers
+            .match_name::<ChildOutputObserver>(&self.observer_name)
+            .and_then(ChildOutputObserver::output)
+        else {
+            return Ok(());
+        };
+
+        let Some(filename) = solution_filename(testcase) else {
+            return Ok(());
+        };
+
+        let output_file = self.solutions_dir.join(format!("{filename}.output"));
+        fs::create_dir_all(&self.solutions_dir)?;
+        fs::write(&output_file, output)?;
+
+        let report = SanitizerReport::parse(&String::from_utf8_lossy(output));
+        match &report {
+            Some(report) => log::info!(
+                "Solution {filename}: {} reported {}",
+                report.sanitizer,
+                report.bug_type
+            ),
+            None => log::info!("Solution {filename}: no sanitizer report in the output"),
+        }
+
+        testcase.add_metadata(ChildOutputMetadata {
+            output_file,
+            report,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SanitizerReport;
+
+    const HEAP_BUFFER_OVERFLOW: &str = "\
+=================================================================
+==12345==ERROR: AddressSanitizer: heap-buffer-overflow on address 0x602000000011 at pc 0x0000004f1c2b bp 0x7ffd5a8e0a10 sp 0x7ffd5a8e0a08
+READ of size 1 at 0x602000000011 thread T0
+    #0 0x4f1c2b in parse_cmd /src/lightftp/ftpserv.c:1042:9
+    #1 0x4f2a11 in ftp_session /src/lightftp/ftpserv.c:1590:13
+    #2 0x7f3c2a1b6ea6 in start_thread nptl/pthread_create.c:477:8
+    #3 0x7f3c2a0d6a2e in clone misc/../sysdeps/unix/sysv/linux/x86_64/clone.S:95
+
+0x602000000011 is located 0 bytes to the right of 1-byte region [0x602000000010,0x602000000011)
+allocated by thread T0 here:
+    #0 0x4b2a3d in malloc (/fuzz/fftp+0x4b2a3d)
+    #1 0x4f1b00 in recv_cmd /src/lightftp/ftpserv.c:1030:15
+
+SUMMARY: AddressSanitizer: heap-buffer-overflow /src/lightftp/ftpserv.c:1042:9 in parse_cmd
+Shadow bytes around the buggy address:
+==12345==ABORTING
+";
+
+    #[test]
+    fn heap_buffer_overflow_with_frames() {
+        let report = SanitizerReport::parse(HEAP_BUFFER_OVERFLOW).unwrap();
+        assert_eq!(report.sanitizer, "AddressSanitizer");
+        assert_eq!(report.bug_type, "heap-buffer-overflow");
+        assert_eq!(
+            report.summary.as_deref(),
+            Some("AddressSanitizer: heap-buffer-overflow /src/lightftp/ftpserv.c:1042:9 in parse_cmd")
+        );
+        assert!(report.stack_hash.is_some());
+        assert!(!report.is_oom());
+    }
+
+    #[test]
+    fn stack_hash_only_depends_on_the_functions_of_the_first_stack() {
+        let hash = SanitizerReport::parse_stack_hash(HEAP_BUFFER_OVERFLOW);
+        // other addresses and lines, e.g. from a rebuilt target, and another allocation stack
+        let moved = HEAP_BUFFER_OVERFLOW
+            .replace("0x4f1c2b", "0x4f2d3c")
+            .replace("ftpserv.c:1590:13", "ftpserv.c:1602:13")
+            .replace("recv_cmd", "other_alloc");
+        assert_eq!(SanitizerReport::parse_stack_hash(&moved), hash);
+        // another function in the crashing stack
+        let other = HEAP_BUFFER_OVERFLOW.replace("in ftp_session", "in ftp_list");
+        assert_ne!(SanitizerReport::parse_stack_hash(&other), hash);
+    }
+
+    #[test]
+    fn allocation_size_too_big_is_oom() {
+        let output = "\
+==4242==ERROR: AddressSanitizer: requested allocation size 0xffffffffffffffff (0x800 after adjustments for alignment, red zones etc.) exceeds maximum supported size of 0x10000000000 (thread T0)
+    #0 0x4b2a3d in malloc (/fuzz/fftp+0x4b2a3d)
+    #1 0x4f1b00 in read_line /src/server.c:88:15
+
+==4242==HINT: if you don't care about these errors you may set allocator_may_return_null=1
+SUMMARY: AddressSanitizer: allocation-size-too-big (/fuzz/fftp+0x4b2a3d) in malloc
+==4242==ABORTING
+";
+        let report = SanitizerReport::parse(output).unwrap();
+        assert_eq!(report.sanitizer, "AddressSanitizer");
+        assert_eq!(
+            report.bug_type,
+            "requested allocation size 0xffffffffffffffff"
+        );
+        assert!(report.stack_hash.is_some());
+        assert!(report.is_oom());
+    }
+
+    #[test]
+    fn out_of_memory_is_oom() {
+        let output = "\
+==777==ERROR: AddressSanitizer: out of memory: allocator is trying to allocate 0x100000000 bytes
+    #0 0x4b2a3d in malloc (/fuzz/fftp+0x4b2a3d)
+    #1 0x4f1b00 in store_file /src/server.c:310:9
+
+SUMMARY: AddressSanitizer: out-of-memory (/fuzz/fftp+0x4b2a3d) in malloc
+";
+        assert!(SanitizerReport::parse(output).unwrap().is_oom());
+    }
+
+    #[test]
+    fn ubsan_without_stack() {
+        let output = "\
+220 LightFTP server ready
+/src/server.c:120:17: runtime error: signed integer overflow: 2147483647 + 1 cannot be represented in type 'int'
+SUMMARY: UndefinedBehaviorSanitizer: undefined-behavior /src/server.c:120:17
+";
+        let report = SanitizerReport::parse(output).unwrap();
+        assert_eq!(report.sanitizer, "UndefinedBehaviorSanitizer");
+        assert_eq!(report.bug_type, "signed integer overflow");
+        assert_eq!(report.stack_hash, None);
+        assert!(!report.is_oom());
+    }
+
+    #[test]
+    fn truncated_output() {
+        // the output is cut off in the middle of the stack trace
+        let end = HEAP_BUFFER_OVERFLOW
+            .find("0x4f2a11 in ftp_session")
+            .unwrap();
+        let report = SanitizerReport::parse(&HEAP_BUFFER_OVERFLOW[..end]).unwrap();
+        assert_eq!(report.bug_type, "heap-buffer-overflow");
+        assert_eq!(report.summary, None);
+        let only_top = HEAP_BUFFER_OVERFLOW.find("    #1 0x4f2a11").unwrap();
+        assert_eq!(
+            report.stack_hash,
+            SanitizerReport::parse_stack_hash(&HEAP_BUFFER_OVERFLOW[..only_top])
+        );
+        // the buffer only keeps the tail of the output, the header may be gone
+        let start = HEAP_BUFFER_OVERFLOW.find("READ of size").unwrap();
+        assert_eq!(SanitizerReport::parse(&HEAP_BUFFER_OVERFLOW[start..]), None);
+        assert_eq!(SanitizerReport::parse("==12345==ERROR: AddressSani"), None);
+        assert_eq!(SanitizerReport::parse(""), None);
+    }
+}
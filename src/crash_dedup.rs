@@ -0,0 +1,262 @@
+//! Crash deduplication across target states.
+//!
+//! In the `MultiCorpMultiMeta` state access mode, the metadata of the objective feedbacks is kept per target state.
+//! A `MaxMapFeedback` in the objective then deduplicates crashes per state, and the same bug reached from ten states
+//! is stored ten times. The [`CrashDedupFeedback`] instead keeps its buckets in the shared metadata of the state
+//! (see [`HasSharedMetadata`]), so crashes are deduplicated over all target states.
+//!
+//! A crash is put in a bucket by the hash of the stack trace in its sanitizer report (see
+//! [`crate::child_output::SanitizerReport::stack_hash`]). Only the first crash of a bucket is a solution, for the
+//! others it is only recorded from which target state they were found. A crash without stack trace is deduplicated
+//! like a `MaxMapFeedback` would: it is a solution, and gets a bucket of its own, only if it reaches an edge more often
+//! than all crashes without stack trace before, over all target states.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Display, Formatter},
+    fs,
+    marker::PhantomData,
+    path::PathBuf,
+};
+
+use libafl::{
+    corpus::Testcase,
+    events::EventFirer,
+    executors::ExitKind,
+    feedbacks::Feedback,
+    observers::{MapObserver, ObserversTuple},
+    state::State,
+    Error,
+};
+use libafl_bolts::{hash_std, impl_serdeany, tuples::MatchName, Named};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    child_output::{ChildOutputObserver, SanitizerReport},
+    feedbacks::solution_filename,
+    state::{HasSharedMetadata, MultipleStates},
+};
+
+/// What a crash is deduplicated by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum BucketKey {
+    /// Hash of the top of the stack trace in the sanitizer report
+    Stack(u64),
+    /// Hash of the hit counts the crashing input reached first
+    Coverage(u64),
+}
+
+impl Display for BucketKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BucketKey::Stack(hash) => write!(f, "stack-{hash:016x}"),
+            BucketKey::Coverage(hash) => write!(f, "cov-{hash:016x}"),
+        }
+    }
+}
+
+/// All crashes that are considered the same bug.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashBucket {
+    /// Filename of the solution that represents this bucket
+    pub representative: Option<String>,
+    /// Kind of bug according to the sanitizer, if any
+    pub bug_type: Option<String>,
+    /// The target states the bug was reached from
+    pub states: BTreeSet<usize>,
+    /// Number of crashes that ended up in this bucket
+    pub hits: u64,
+}
+
+/// The crash buckets, shared by all target states.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CrashBuckets {
+    pub buckets: BTreeMap<BucketKey, CrashBucket>,
+    /// The highest hit count of each edge over the crashes without stack trace
+    #[serde(default)]
+    pub coverage_history: Vec<u8>,
+}
+
+impl_serdeany!(CrashBuckets);
+
+impl CrashBuckets {
+    /// Update the coverage history with the hit counts `map`. Returns the key of the bucket of a crash with this
+    /// coverage, `None` if it reaches no edge more often than the crashes before.
+    fn coverage_key(&mut self, map: &[u8]) -> Option<BucketKey> {
+        if self.coverage_history.len() < map.len() {
+            self.coverage_history.resize(map.len(), 0);
+        }
+        let mut novelties = Vec::new();
+        for (idx, (&hits, max)) in map.iter().zip(self.coverage_history.iter_mut()).enumerate() {
+            if hits > *max {
+                *max = hits;
+                novelties.extend((idx as u32).to_le_bytes());
+                novelties.push(hits);
+            }
+        }
+        (!novelties.is_empty()).then(|| BucketKey::Coverage(hash_std(&novelties)))
+    }
+
+    /// Write an overview of the buckets, one line per bucket.
+    fn write_summary(&self, path: &PathBuf) -> Result<(), Error> {
+        let mut summary = String::new();
+        for (key, bucket) in &self.buckets {
+            let states: Vec<String> = bucket.states.iter().map(ToString::to_string).collect();
+            summary.push_str(&format!(
+                "{key} representative={} bug_type={} hits={} states={}\n",
+                bucket.representative.as_deref().unwrap_or("-"),
+                bucket.bug_type.as_deref().unwrap_or("-"),
+                bucket.hits,
+                states.join(",")
+            ));
+        }
+        fs::write(path, summary)?;
+        Ok(())
+    }
+}
+
+/// Is interesting only for the first crash of each [`CrashBucket`], across all target states.
+///
+/// Use it in the objective after a `CrashFeedback`, i.e., `feedback_and_fast!(CrashFeedback::new(), CrashDedupFeedback::new(..))`.
+#[derive(Debug)]
+pub struct CrashDedupFeedback<O> {
+    name: String,
+    map_observer_name: String,
+    output_observer_name: Option<String>,
+    /// File with an overview of the buckets, rewritten when the buckets change
+    summary_file: PathBuf,
+    /// The bucket of the crash that was found interesting, until it is added to the solutions
+    pending: Option<BucketKey>,
+    phantom: PhantomData<O>,
+}
+
+impl<O> CrashDedupFeedback<O>
+where
+    O: Named,
+{
+    /// Create a new [`CrashDedupFeedback`].
+    ///
+    /// - `map_observer`: The coverage map observer, its hit counts deduplicate crashes without stack trace
+    /// - `output_observer`: The observer of the output of the target, to find the stack trace in, if any
+    /// - `summary_file`: File to write the overview of the buckets to
+    pub fn new(
+        map_observer: &O,
+        output_observer: Option<&ChildOutputObserver>,
+        summary_file: PathBuf,
+    ) -> Self {
+        Self {
+            name: "CrashDedupFeedback".to_string(),
+            map_observer_name: map_observer.name().to_string(),
+            output_observer_name: output_observer.map(|o| o.name().to_string()),
+            summary_file,
+            pending: None,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<O> Named for CrashDedupFeedback<O> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<O, S> Feedback<S> for CrashDedupFeedback<O>
+where
+    O: MapObserver<Entry = u8>,
+    S: State + MultipleStates + HasSharedMetadata,
+{
+    fn init_state(&mut self, state: &mut S) -> Result<(), Error> {
+        if !state.has_shared_metadata::<CrashBuckets>() {
+            state.add_shared_metadata(CrashBuckets::default());
+        }
+        Ok(())
+    }
+
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        let report = self
+            .output_observer_name
+            .as_ref()
+            .and_then(|name| observers.match_name::<ChildOutputObserver>(name))
+            .and_then(ChildOutputObserver::output)
+            .and_then(|output| SanitizerReport::parse(&String::from_utf8_lossy(output)));
+
+        let state_idx = state.current_state_idx().0;
+        let buckets = state.shared_metadata_mut::<CrashBuckets>()?;
+        let key = match report.as_ref().and_then(|report| report.stack_hash) {
+            Some(hash) => BucketKey::Stack(hash),
+            None => {
+                let map = observers
+                    .match_name::<O>(&self.map_observer_name)
+                    .ok_or_else(|| Error::key_not_found("Map observer not found".to_string()))?
+                    .to_vec();
+                match buckets.coverage_key(&map) {
+                    Some(key) => key,
+                    // nothing new, a duplicate of an earlier crash
+                    None => return Ok(false),
+                }
+            }
+        };
+
+        match buckets.buckets.get_mut(&key) {
+            Some(bucket) => {
+                bucket.hits += 1;
+                if bucket.states.insert(state_idx) {
+                    log::info!("Crash {key} was also reached from target state {state_idx}");
+                    buckets.write_summary(&self.summary_file)?;
+                }
+                Ok(false)
+            }
+            None => {
+                buckets.buckets.insert(
+                    key,
+                    CrashBucket {
+                        representative: None,
+                        bug_type: report.map(|report| report.bug_type),
+                        states: BTreeSet::from([state_idx]),
+                        hits: 1,
+                    },
+                );
+                self.pending = Some(key);
+                Ok(true)
+            }
+        }
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        state: &mut S,
+        _observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        let Some(key) = self.pending.take() else {
+            return Ok(());
+        };
+        let filename = solution_filename(testcase);
+        let buckets = state.shared_metadata_mut::<CrashBuckets>()?;
+        if let Some(bucket) = buckets.buckets.get_mut(&key) {
+            bucket.representative = filename;
+        }
+        buckets.write_summary(&self.summary_file)
+    }
+
+    fn discard_metadata(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.pending = None;
+        Ok(())
+    }
+}
+
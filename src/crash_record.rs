@@ -0,0 +1,337 @@
+//! Self-contained records of solutions.
+//!
+//! A crash in a stateful target is rarely caused by the final input alone. The child has received the prefix of its
+//! target state, and possibly many more inputs before it, all of which may have changed its state.
+//! The [`crate::executor::forkserver::ForkserverExecutor`] keeps every message the current child received in a
+//! [`Conversation`]. The [`ConversationObserver`] takes a snapshot of it whenever an execution does not end well, and the
+//! [`CrashRecordFeedback`] stores it as a [`CrashRecord`] next to the solution, as `<solution>.record.cbor`.
+//!
+//! Replaying [`CrashRecord::history`] followed by [`CrashRecord::input`] to a fresh target reproduces the crash,
+//! if the target is deterministic.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use libafl::{
+    corpus::Testcase,
+    events::EventFirer,
+    executors::ExitKind,
+    feedbacks::Feedback,
+    inputs::{HasTargetBytes, UsesInput},
+    observers::{Observer, ObserversTuple},
+    state::{HasExecutions, State},
+    Error,
+};
+use libafl_bolts::{current_time, tuples::MatchName, AsSlice, Named};
+use serde::{Deserialize, Serialize};
+
+use crate::{feedbacks::solution_filename, replay::exit_kind_str, state::MultipleStates};
+
+/// Maximum number of messages kept per child. Older messages are dropped, and the record is marked as truncated.
+pub const CONVERSATION_MAX_LEN: usize = 10_000;
+
+#[derive(Debug, Default)]
+struct ConversationInner {
+    messages: VecDeque<Vec<u8>>,
+    truncated: bool,
+    signal: Option<i32>,
+}
+
+/// The messages the current child received, in order, and the signal that terminated it.
+///
+/// Cloning gives a handle to the same conversation.
+#[derive(Debug, Clone, Default)]
+pub struct Conversation {
+    inner: Arc<Mutex<ConversationInner>>,
+}
+
+impl Conversation {
+    /// Start a new conversation, because a new child is started.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.messages.clear();
+        inner.truncated = false;
+        inner.signal = None;
+    }
+
+    /// Add a message that is sent to the child.
+    pub fn push(&self, message: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.messages.len() >= CONVERSATION_MAX_LEN {
+            inner.messages.pop_front();
+            inner.truncated = true;
+        }
+        inner.messages.push_back(message.to_vec());
+    }
+
+    /// The last message that was sent to the child, if any.
+    pub fn last(&self) -> Option<Vec<u8>> {
+        self.inner.lock().unwrap().messages.back().cloned()
+    }
+
+    /// Set the signal that terminated the child.
+    pub fn set_signal(&self, signal: Option<i32>) {
+        self.inner.lock().unwrap().signal = signal;
+    }
+
+    /// Copy of the messages, if older messages were dropped and the signal that terminated the child.
+    pub fn snapshot(&self) -> (Vec<Vec<u8>>, bool, Option<i32>) {
+        let inner = self.inner.lock().unwrap();
+        (
+            inner.messages.iter().cloned().collect(),
+            inner.truncated,
+            inner.signal,
+        )
+    }
+}
+
+/// Snapshot of a [`Conversation`], taken by the [`ConversationObserver`] when an execution did not end with [`ExitKind::Ok`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationSnapshot {
+    /// Every message the child received, the last one is the input of this execution
+    pub messages: Vec<Vec<u8>>,
+    /// If older messages were dropped
+    pub truncated: bool,
+    /// The signal that terminated the child, if any
+    pub signal: Option<i32>,
+    /// How the execution ended
+    pub exit_kind: ExitKind,
+}
+
+/// Takes a snapshot of the [`Conversation`] whenever an execution ends with something other than [`ExitKind::Ok`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConversationObserver {
+    name: String,
+    #[serde(skip)]
+    conversation: Conversation,
+    snapshot: Option<ConversationSnapshot>,
+}
+
+impl ConversationObserver {
+    /// Create a new [`ConversationObserver`] reading from `conversation`, which should be the conversation
+    /// that is given to the executor.
+    pub fn new(name: &str, conversation: Conversation) -> Self {
+        Self {
+            name: name.to_string(),
+            conversation,
+            snapshot: None,
+        }
+    }
+
+    /// The conversation, if the last execution did not end with [`ExitKind::Ok`].
+    pub fn snapshot(&self) -> Option<&ConversationSnapshot> {
+        self.snapshot.as_ref()
+    }
+}
+
+impl Named for ConversationObserver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Observer<S> for ConversationObserver
+where
+    S: UsesInput,
+{
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.snapshot = None;
+        Ok(())
+    }
+
+    fn post_exec(
+        &mut self,
+        _state: &mut S,
+        _input: &S::Input,
+        exit_kind: &ExitKind,
+    ) -> Result<(), Error> {
+        if *exit_kind != ExitKind::Ok {
+            let (messages, truncated, signal) = self.conversation.snapshot();
+            self.snapshot = Some(ConversationSnapshot {
+                messages,
+                truncated,
+                signal,
+                exit_kind: *exit_kind,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Everything needed to reproduce a solution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashRecord {
+    /// Index of the target state the solution was found in
+    pub state_idx: usize,
+    /// Name of the target state, i.e., the name of its prefix directory
+    pub state_name: String,
+    /// The prefix messages of the target state
+    pub prefix: Vec<Vec<u8>>,
+    /// Every message the child received before the input, in order.
+    /// This includes the prefix, if it was sent to this child.
+    pub history: Vec<Vec<u8>>,
+    /// If the oldest messages of the history were dropped, see [`CONVERSATION_MAX_LEN`]
+    pub history_truncated: bool,
+    /// The input that was the last straw
+    pub input: Vec<u8>,
+    /// How the execution ended, e.g. `Cr` for a crash
+    pub exit_kind: String,
+    /// The signal that terminated the child, if any
+    pub signal: Option<i32>,
+    /// When the solution was found, in seconds since the UNIX epoch
+    pub time: u64,
+    /// The number of executions when the solution was found
+    pub executions: u64,
+}
+
+impl CrashRecord {
+    /// Create the record of the execution `snapshot` was taken of, in the current target state of `state`.
+    pub fn new<S>(state: &S, snapshot: &ConversationSnapshot) -> Self
+    where
+        S: State + MultipleStates + HasExecutions,
+        S::Input: HasTargetBytes,
+    {
+        let prefix = state
+            .prefix()
+            .prefix
+            .iter()
+            .filter_map(|testcase| testcase.input().as_ref())
+            .map(|input| input.target_bytes().as_slice().to_vec())
+            .collect();
+        // the last message is the input itself
+        let mut history = snapshot.messages.clone();
+        let input = history.pop().unwrap_or_default();
+
+        Self {
+            state_idx: state.current_state_idx().0,
+            state_name: state.prefix().metadata.name.clone(),
+            prefix,
+            history,
+            history_truncated: snapshot.truncated,
+            input,
+            exit_kind: exit_kind_str(snapshot.exit_kind).to_string(),
+            signal: snapshot.signal,
+            time: current_time().as_secs(),
+            executions: *state.executions() as u64,
+        }
+    }
+
+    /// Read a record from a CBOR file.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let reader = BufReader::new(File::open(path)?);
+        ciborium::from_reader(reader).map_err(|e| {
+            Error::serialize(format!(
+                "Could not read crash record {}: {e}",
+                path.display()
+            ))
+        })
+    }
+
+    /// Write the record to a CBOR file.
+    pub fn to_file(&self, path: &Path) -> Result<(), Error> {
+        let writer = BufWriter::new(File::create(path)?);
+        ciborium::into_writer(self, writer).map_err(|e| {
+            Error::serialize(format!(
+                "Could not write crash record {}: {e}",
+                path.display()
+            ))
+        })
+    }
+}
+
+/// The suffix of the files crash records are stored in, next to their solution.
+pub const CRASH_RECORD_SUFFIX: &str = ".record.cbor";
+
+/// If `path` is named like a crash record.
+pub fn is_crash_record(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().ends_with(CRASH_RECORD_SUFFIX))
+}
+
+/// Stores a [`CrashRecord`] next to each solution, as `<solution>.record.cbor`.
+///
+/// It never makes an input interesting by itself, combine it using `feedback_or!` with the objective, so it only
+/// gets to add its record to solutions.
+#[derive(Debug)]
+pub struct CrashRecordFeedback {
+    name: String,
+    observer_name: String,
+    solutions_dir: PathBuf,
+}
+
+impl CrashRecordFeedback {
+    /// Create a new [`CrashRecordFeedback`] for the `observer`, where `solutions_dir` is the directory of the
+    /// solutions corpus (e.g. `crashes/`).
+    pub fn new(observer: &ConversationObserver, solutions_dir: PathBuf) -> Self {
+        Self {
+            name: format!("{}_record_feedback", observer.name()),
+            observer_name: observer.name().to_string(),
+            solutions_dir,
+        }
+    }
+}
+
+impl Named for CrashRecordFeedback {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Feedback<S> for CrashRecordFeedback
+where
+    S: State + MultipleStates + HasExecutions,
+    S::Input: HasTargetBytes,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        Ok(false)
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        state: &mut S,
+        observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        let Some(snapshot) = observers
+            .match_name::<ConversationObserver>(&self.observer_name)
+            .and_then(ConversationObserver::snapshot)
+        else {
+            return Ok(());
+        };
+        let Some(filename) = solution_filename(testcase) else {
+            return Ok(());
+        };
+
+        let record = CrashRecord::new(state, snapshot);
+
+        fs::create_dir_all(&self.solutions_dir)?;
+        let path = self.solutions_dir.join(format!("{filename}{CRASH_RECORD_SUFFIX}"));
+        record.to_file(&path)?;
+        log::info!(
+            "Solution {filename} found in target state {} ({}) after {} messages",
+            record.state_idx,
+            record.state_name,
+            record.history.len()
+        );
+        Ok(())
+    }
+}
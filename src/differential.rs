@@ -0,0 +1,655 @@
+//! Compare how two builds of a target, e.g. patched and unpatched, or two implementations of a protocol, handle the
+//! same messages.
+//!
+//! When replaying (see `differential-replayer`), each message is sent to both builds, and their [`Outcome`]s are
+//! compared on exit kind, response status code (see [`status_code`]) and coverage. The coverage maps of two builds
+//! cannot be compared edge by edge, so the coverage is normalized to whether the message reached edges the build had
+//! not reached before in the same conversation.
+//!
+//! When fuzzing, the [`DifferentialExecutor`] drives both builds with the same prefix and inputs. The responses are
+//! normalized into classes by [`NormalizationRules`], e.g. FTP replies `230` and `250` are both `2xx`. If the builds
+//! disagree, the execution ends with [`ExitKind::Diff`] (unless the primary did not end well, then it ends with the
+//! exit kind of the primary, so crashes, OOMs and hangs are found as usual), and the [`DisagreementFeedback`] stores
+//! both responses next to the solution.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt::{self, Debug, Display, Formatter},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use libafl::{
+    corpus::Testcase,
+    events::EventFirer,
+    executors::{DiffExitKind, Executor, ExitKind, HasObservers},
+    feedbacks::Feedback,
+    inputs::{BytesInput, HasTargetBytes, UsesInput},
+    observers::{MapObserver, ObserversTuple, UsesObservers},
+    state::{State, UsesState},
+    Error,
+};
+use libafl_bolts::{tuples::MatchName, AsSlice, Named};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    executor::{ResettableForkserver, RestartReason},
+    feedbacks::solution_filename,
+    replay::{exit_kind_str, TraceTargetState},
+    response::{status_code, HasLastResponse},
+};
+
+/// How a build handled a message.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub exit_kind: ExitKind,
+    /// The response, if it could be read
+    pub response: Option<Vec<u8>>,
+    /// Number of edges the message reached that were not reached before in the conversation
+    pub new_edges: usize,
+}
+
+impl Outcome {
+    /// The status code of the response, see [`status_code`].
+    pub fn status_code(&self) -> Option<u16> {
+        self.response.as_deref().and_then(status_code)
+    }
+}
+
+/// A way in which two builds handled a message differently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// The executions ended differently, see [`exit_kind_str`]
+    ExitKind { a: String, b: String },
+    /// The responses have different status codes
+    ResponseCode { a: Option<u16>, b: Option<u16> },
+    /// Only one of the builds reached new edges
+    Coverage { a_new_edges: usize, b_new_edges: usize },
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let code = |code: &Option<u16>| code.map_or("none".to_string(), |code| code.to_string());
+        match self {
+            Difference::ExitKind { a, b } => write!(f, "exit kind {a} != {b}"),
+            Difference::ResponseCode { a, b } => {
+                write!(f, "response code {} != {}", code(a), code(b))
+            }
+            Difference::Coverage {
+                a_new_edges,
+                b_new_edges,
+            } => write!(f, "new edges {a_new_edges} vs {b_new_edges}"),
+        }
+    }
+}
+
+/// Compare the outcomes of the same message on build `a` and build `b`.
+pub fn compare(a: &Outcome, b: &Outcome) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    if a.exit_kind != b.exit_kind {
+        differences.push(Difference::ExitKind {
+            a: exit_kind_str(a.exit_kind).to_string(),
+            b: exit_kind_str(b.exit_kind).to_string(),
+        });
+    }
+    if a.status_code() != b.status_code() {
+        differences.push(Difference::ResponseCode {
+            a: a.status_code(),
+            b: b.status_code(),
+        });
+    }
+    if (a.new_edges > 0) != (b.new_edges > 0) {
+        differences.push(Difference::Coverage {
+            a_new_edges: a.new_edges,
+            b_new_edges: b.new_edges,
+        });
+    }
+    differences
+}
+
+/// Keeps track of the edges a build reached during a conversation.
+#[derive(Debug, Default)]
+pub struct CoverageTracker {
+    seen: HashSet<usize>,
+}
+
+impl CoverageTracker {
+    /// Forget the reached edges, e.g. when the target is reset.
+    pub fn clear(&mut self) {
+        self.seen.clear();
+    }
+
+    /// Count the edges in `map` that were not reached before, and remember them.
+    pub fn new_edges<O>(&mut self, map: &O) -> usize
+    where
+        O: MapObserver,
+    {
+        let initial = map.initial();
+        (0..map.usable_count())
+            .filter(|idx| *map.get(*idx) != initial)
+            .filter(|idx| self.seen.insert(*idx))
+            .count()
+    }
+}
+
+/// Send `message` to the build behind `executor` and observe how it handled it.
+///
+/// The coverage map observer named `map_observer_name` is reset before the execution.
+pub fn run_message<E, EM, Z, O>(
+    executor: &mut E,
+    fuzzer: &mut Z,
+    state: &mut E::State,
+    mgr: &mut EM,
+    map_observer_name: &str,
+    coverage: &mut CoverageTracker,
+    message: &[u8],
+) -> Result<Outcome, Error>
+where
+    E: Executor<EM, Z> + HasObservers + HasLastResponse + UsesInput<Input = BytesInput>,
+    EM: UsesState<State = E::State>,
+    Z: UsesState<State = E::State>,
+    O: MapObserver + 'static,
+{
+    let map_not_found = || Error::key_not_found(format!("Map observer {map_observer_name} not found"));
+    executor
+        .observers_mut()
+        .match_name_mut::<O>(map_observer_name)
+        .ok_or_else(map_not_found)?
+        .reset_map()?;
+
+    let exit_kind = executor.run_target(fuzzer, state, mgr, &BytesInput::new(message.to_vec()))?;
+
+    let map = executor
+        .observers()
+        .match_name::<O>(map_observer_name)
+        .ok_or_else(map_not_found)?;
+    Ok(Outcome {
+        exit_kind,
+        response: executor.last_response().map(<[u8]>::to_vec),
+        new_edges: coverage.new_edges(map),
+    })
+}
+
+/// Rules to normalize responses into classes that can be compared across implementations of a protocol.
+///
+/// They are read from a JSON file, missing fields get their default, e.g.
+/// `{"digits": 1, "aliases": {"502": 500}, "ignore": [421], "compare_exit_kinds": true, "compare_missing": false}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NormalizationRules {
+    /// Number of leading digits of the status code that make up its class: 1 turns `230` into `2xx`, 3 keeps `230`
+    pub digits: usize,
+    /// Status codes that are replaced by another code before they are classified
+    pub aliases: BTreeMap<u16, u16>,
+    /// Status codes (after aliasing) that never disagree with anything, e.g. codes that depend on timing
+    pub ignore: BTreeSet<u16>,
+    /// If executions that end differently, e.g. a crash of only one build, disagree
+    pub compare_exit_kinds: bool,
+    /// If a response without status code, or no response at all, disagrees with a response with status code
+    pub compare_missing: bool,
+}
+
+impl Default for NormalizationRules {
+    fn default() -> Self {
+        Self {
+            digits: 1,
+            aliases: BTreeMap::new(),
+            ignore: BTreeSet::new(),
+            compare_exit_kinds: true,
+            compare_missing: false,
+        }
+    }
+}
+
+impl NormalizationRules {
+    /// Read the rules from a JSON file.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|e| {
+            Error::illegal_argument(format!(
+                "Could not parse normalization rules {}: {e}",
+                path.display()
+            ))
+        })
+    }
+
+    /// The status code of `response` after aliasing.
+    fn code(&self, response: Option<&[u8]>) -> Option<u16> {
+        let code = response.and_then(status_code)?;
+        Some(self.aliases.get(&code).copied().unwrap_or(code))
+    }
+
+    /// The class of `response`, e.g. `2xx`, `None` if it has no status code.
+    pub fn classify(&self, response: Option<&[u8]>) -> Option<String> {
+        let code = self.code(response)?.to_string();
+        let digits = self.digits.clamp(1, code.len());
+        Some(format!("{}{}", &code[..digits], "x".repeat(code.len() - digits)))
+    }
+
+    /// Why the two builds disagree, `None` if they agree.
+    pub fn disagreement(
+        &self,
+        exit_kind_a: ExitKind,
+        response_a: Option<&[u8]>,
+        exit_kind_b: ExitKind,
+        response_b: Option<&[u8]>,
+    ) -> Option<String> {
+        if exit_kind_a != exit_kind_b {
+            return self.compare_exit_kinds.then(|| {
+                format!(
+                    "exit kind {} != {}",
+                    exit_kind_str(exit_kind_a),
+                    exit_kind_str(exit_kind_b)
+                )
+            });
+        }
+        let (code_a, code_b) = (self.code(response_a), self.code(response_b));
+        if [code_a, code_b]
+            .iter()
+            .flatten()
+            .any(|code| self.ignore.contains(code))
+        {
+            return None;
+        }
+        let (class_a, class_b) = (self.classify(response_a), self.classify(response_b));
+        let differ = match (&class_a, &class_b) {
+            (Some(a), Some(b)) => a != b,
+            (None, None) => false,
+            _ => self.compare_missing,
+        };
+        differ.then(|| {
+            format!(
+                "response class {} != {}",
+                class_a.as_deref().unwrap_or("none"),
+                class_b.as_deref().unwrap_or("none")
+            )
+        })
+    }
+}
+
+/// Two builds handled the same input differently.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Disagreement {
+    /// Why they disagree, see [`NormalizationRules::disagreement`]
+    pub reason: String,
+    pub exit_kind_a: String,
+    pub exit_kind_b: String,
+    /// The responses, empty if there was none
+    pub response_a: Vec<u8>,
+    pub response_b: Vec<u8>,
+}
+
+/// The disagreement of the last execution of a [`DifferentialExecutor`], if any.
+///
+/// Cloning gives a handle to the same disagreement.
+#[derive(Debug, Clone, Default)]
+pub struct LastDisagreement {
+    inner: Arc<Mutex<Option<Disagreement>>>,
+}
+
+impl LastDisagreement {
+    fn set(&self, disagreement: Option<Disagreement>) {
+        *self.inner.lock().unwrap() = disagreement;
+    }
+
+    /// The disagreement of the last execution
+    pub fn get(&self) -> Option<Disagreement> {
+        self.inner.lock().unwrap().clone()
+    }
+}
+
+/// Drives two builds, e.g. two implementations of the same protocol, with the same prefixes and inputs.
+///
+/// The observers are those of the primary build, so coverage feedback is about the primary. An execution ends with
+/// [`ExitKind::Diff`] if the builds disagree according to the [`NormalizationRules`] and the primary ended with
+/// [`ExitKind::Ok`], otherwise with the exit kind of the primary. Every disagreement is made available in the
+/// [`LastDisagreement`], also if the primary crashed. Both builds are restarted together, so they always handle the same conversation.
+pub struct DifferentialExecutor<A, B> {
+    primary: A,
+    secondary: B,
+    rules: NormalizationRules,
+    last_disagreement: LastDisagreement,
+    state_reset_occurred: bool,
+}
+
+impl<A, B> Debug for DifferentialExecutor<A, B>
+where
+    A: Debug,
+    B: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DifferentialExecutor")
+            .field("primary", &self.primary)
+            .field("secondary", &self.secondary)
+            .field("rules", &self.rules)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<A, B> DifferentialExecutor<A, B> {
+    /// Create a new [`DifferentialExecutor`], disagreements are made available in `last_disagreement`.
+    pub fn new(
+        primary: A,
+        secondary: B,
+        rules: NormalizationRules,
+        last_disagreement: LastDisagreement,
+    ) -> Self {
+        Self {
+            primary,
+            secondary,
+            rules,
+            last_disagreement,
+            state_reset_occurred: false,
+        }
+    }
+
+    /// The executor of the primary build
+    pub fn primary_mut(&mut self) -> &mut A {
+        &mut self.primary
+    }
+
+    /// The executor of the secondary build
+    pub fn secondary_mut(&mut self) -> &mut B {
+        &mut self.secondary
+    }
+
+    /// Split into the executors of the primary and the secondary build, e.g. to recreate them.
+    pub fn into_inner(self) -> (A, B) {
+        (self.primary, self.secondary)
+    }
+}
+
+impl<A, B> UsesState for DifferentialExecutor<A, B>
+where
+    A: UsesState,
+{
+    type State = A::State;
+}
+
+impl<A, B> UsesObservers for DifferentialExecutor<A, B>
+where
+    A: UsesObservers,
+{
+    type Observers = A::Observers;
+}
+
+impl<A, B> HasObservers for DifferentialExecutor<A, B>
+where
+    A: HasObservers,
+{
+    fn observers(&self) -> &Self::Observers {
+        self.primary.observers()
+    }
+
+    fn observers_mut(&mut self) -> &mut Self::Observers {
+        self.primary.observers_mut()
+    }
+}
+
+impl<A, B> HasLastResponse for DifferentialExecutor<A, B>
+where
+    A: HasLastResponse,
+{
+    /// The response of the primary build
+    fn last_response(&self) -> Option<&[u8]> {
+        self.primary.last_response()
+    }
+}
+
+impl<A, B, EM, Z> Executor<EM, Z> for DifferentialExecutor<A, B>
+where
+    A: Executor<EM, Z> + ResettableForkserver + HasLastResponse,
+    B: Executor<EM, Z> + ResettableForkserver + HasLastResponse + UsesState<State = A::State>,
+    EM: UsesState<State = A::State>,
+    Z: UsesState<State = A::State>,
+{
+    fn run_target(
+        &mut self,
+        fuzzer: &mut Z,
+        state: &mut Self::State,
+        mgr: &mut EM,
+        input: &Self::Input,
+    ) -> Result<ExitKind, Error> {
+        let exit_kind_a = self.primary.run_target(fuzzer, state, mgr, input)?;
+        let exit_kind_b = self.secondary.run_target(fuzzer, state, mgr, input)?;
+
+        // If only one of the children is gone, restart the other one too, so both get the same conversation
+        let reset_a = self.primary.state_reset_occurred() || exit_kind_a != ExitKind::Ok;
+        let reset_b = self.secondary.state_reset_occurred() || exit_kind_b != ExitKind::Ok;
+        if reset_a != reset_b {
+            if reset_a {
+                self.secondary.reset_target_state()?;
+            } else {
+                self.primary.reset_target_state()?;
+            }
+            self.state_reset_occurred = true;
+        } else if reset_a {
+            self.state_reset_occurred = true;
+        }
+
+        let response_a = self.primary.last_response();
+        let response_b = self.secondary.last_response();
+        let disagreement = self
+            .rules
+            .disagreement(exit_kind_a, response_a, exit_kind_b, response_b)
+            .map(|reason| Disagreement {
+                reason,
+                exit_kind_a: exit_kind_str(exit_kind_a).to_string(),
+                exit_kind_b: exit_kind_str(exit_kind_b).to_string(),
+                response_a: response_a.unwrap_or_default().to_vec(),
+                response_b: response_b.unwrap_or_default().to_vec(),
+            });
+        let disagree = disagreement.is_some();
+        self.last_disagreement.set(disagreement);
+        Ok(combined_exit_kind(exit_kind_a, exit_kind_b, disagree))
+    }
+}
+
+/// The exit kind of a [`DifferentialExecutor`], where `disagree` tells if the builds disagree.
+///
+/// A crash, OOM or timeout of the primary is a finding by itself and keeps its exit kind, so it reaches the crash, OOM
+/// and hang feedbacks. The disagreement is still available in the [`LastDisagreement`].
+fn combined_exit_kind(exit_kind_a: ExitKind, exit_kind_b: ExitKind, disagree: bool) -> ExitKind {
+    if disagree && exit_kind_a == ExitKind::Ok {
+        ExitKind::Diff {
+            primary: DiffExitKind::from(exit_kind_a),
+            secondary: DiffExitKind::from(exit_kind_b),
+        }
+    } else {
+        exit_kind_a
+    }
+}
+
+impl<A, B> ResettableForkserver for DifferentialExecutor<A, B>
+where
+    A: ResettableForkserver,
+    B: ResettableForkserver,
+{
+    fn reset_target_state(&mut self) -> Result<(), Error> {
+        self.primary.reset_target_state()?;
+        self.secondary.reset_target_state()?;
+        self.state_reset_occurred = false;
+        Ok(())
+    }
+
+    fn state_reset_occurred(&mut self) -> bool {
+        // take the flags of both, so they are cleared
+        let reset_a = self.primary.state_reset_occurred();
+        let reset_b = self.secondary.state_reset_occurred();
+        std::mem::take(&mut self.state_reset_occurred) || reset_a || reset_b
+    }
+
+    fn target_state_changed(&mut self, target_state: TraceTargetState) {
+        self.primary.target_state_changed(target_state.clone());
+        self.secondary.target_state_changed(target_state);
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.primary.set_timeout(timeout);
+        self.secondary.set_timeout(timeout);
+    }
+
+    fn has_running_child(&self) -> bool {
+        self.primary.has_running_child() && self.secondary.has_running_child()
+    }
+
+    fn restart_due(&self) -> Option<RestartReason> {
+        self.primary.restart_due().or_else(|| self.secondary.restart_due())
+    }
+
+    fn workdir_restores(&self) -> Option<usize> {
+        match (self.primary.workdir_restores(), self.secondary.workdir_restores()) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+        }
+    }
+}
+
+/// Is interesting if the builds of a [`DifferentialExecutor`] disagree, i.e., the [`ExitKind`] is
+/// [`ExitKind::Diff`]. Stores the input and both responses next to the solution, as `<solution>.diff.txt`, also if
+/// the solution is a crash of the primary that the secondary did not share.
+#[derive(Debug)]
+pub struct DisagreementFeedback {
+    last_disagreement: LastDisagreement,
+    solutions_dir: PathBuf,
+}
+
+impl DisagreementFeedback {
+    /// Create a new [`DisagreementFeedback`] for the solutions stored in `solutions_dir`.
+    pub fn new(last_disagreement: LastDisagreement, solutions_dir: PathBuf) -> Self {
+        Self {
+            last_disagreement,
+            solutions_dir,
+        }
+    }
+}
+
+impl Named for DisagreementFeedback {
+    fn name(&self) -> &str {
+        "DisagreementFeedback"
+    }
+}
+
+impl<S> Feedback<S> for DisagreementFeedback
+where
+    S: State,
+    S::Input: HasTargetBytes,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        Ok(matches!(exit_kind, ExitKind::Diff { .. }))
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        _state: &mut S,
+        _observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        let Some(disagreement) = self.last_disagreement.get() else {
+            return Ok(());
+        };
+        let input = testcase
+            .input()
+            .as_ref()
+            .map(|input| input.target_bytes().as_slice().to_vec())
+            .unwrap_or_default();
+        let Some(filename) = solution_filename(testcase) else {
+            return Ok(());
+        };
+
+        fs::create_dir_all(&self.solutions_dir)?;
+        fs::write(
+            self.solutions_dir.join(format!("{filename}.diff.txt")),
+            format!(
+                "reason: {}\ninput: b\"{}\"\nA ({}): b\"{}\"\nB ({}): b\"{}\"\n",
+                disagreement.reason,
+                input.escape_ascii(),
+                disagreement.exit_kind_a,
+                disagreement.response_a.escape_ascii(),
+                disagreement.exit_kind_b,
+                disagreement.response_b.escape_ascii(),
+            ),
+        )?;
+        log::info!("Disagreement {filename}: {}", disagreement.reason);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libafl::executors::ExitKind;
+
+    use super::{combined_exit_kind, NormalizationRules};
+
+    #[test]
+    fn crash_of_primary_alone_stays_a_crash() {
+        let rules = NormalizationRules::default();
+        let disagree = rules
+            .disagreement(
+                ExitKind::Crash,
+                None,
+                ExitKind::Ok,
+                Some(b"200 OK\r\n".as_slice()),
+            )
+            .is_some();
+        assert!(disagree);
+        // `CrashFeedback` only takes `ExitKind::Crash`, so the crash is stored in `crashes/` and deduplicated
+        assert_eq!(
+            combined_exit_kind(ExitKind::Crash, ExitKind::Ok, disagree),
+            ExitKind::Crash
+        );
+        assert_eq!(
+            combined_exit_kind(ExitKind::Oom, ExitKind::Ok, true),
+            ExitKind::Oom
+        );
+        assert_eq!(
+            combined_exit_kind(ExitKind::Timeout, ExitKind::Ok, true),
+            ExitKind::Timeout
+        );
+    }
+
+    #[test]
+    fn disagreement_of_working_primary_is_a_diff() {
+        assert!(matches!(
+            combined_exit_kind(ExitKind::Ok, ExitKind::Crash, true),
+            ExitKind::Diff { .. }
+        ));
+        assert_eq!(
+            combined_exit_kind(ExitKind::Ok, ExitKind::Ok, false),
+            ExitKind::Ok
+        );
+    }
+
+    #[test]
+    fn response_classes() {
+        let rules = NormalizationRules::default();
+        let (login, changed, denied) = (
+            b"230 Logged in\r\n".as_slice(),
+            b"250 Okay\r\n".as_slice(),
+            b"530 Not logged in\r\n".as_slice(),
+        );
+        assert_eq!(rules.classify(Some(login)).as_deref(), Some("2xx"));
+        assert!(rules
+            .disagreement(ExitKind::Ok, Some(login), ExitKind::Ok, Some(changed))
+            .is_none());
+        assert!(rules
+            .disagreement(ExitKind::Ok, Some(login), ExitKind::Ok, Some(denied))
+            .is_some());
+    }
+}
@@ -0,0 +1,349 @@
+//! Detection of inputs that move the target out of the target state that is fuzzed.
+//!
+//! During the `loops` iterations in a target state, the inputs are sent one after the other to the same child. An input
+//! may change the session state itself (e.g. `REIN`, `QUIT`, or `USER`, which resets the login), and all following
+//! inputs are then fuzzed in the wrong state. The [`DriftStage`] runs after each execution and detects such a drift
+//! by the response (a [`ResponseRule`] whose match means the state changed, e.g. `220` after `REIN`), or by sending a
+//! cheap probe message whose response tells the state (e.g. `PWD` answers `530` once the login is gone). Both are
+//! given in a JSON file, see [`DriftRules`].
+//!
+//! On a drift, the target is reset and the prefix sent again. Drifts are counted per target state (see
+//! [`MultipleStates::drifts`]) and per input: the inputs that changed the state are stored in `drift/`, as candidates
+//! for new target states, with how often they did so in `drift/candidates.txt`. A probe only tells that one of the
+//! inputs since the last probe changed the state, so with a probe `interval` above 1 all of them are candidates.
+//!
+//! The probe and the prefix are evaluated like any other input, so a crash, OOM or hang they trigger is a solution.
+//! The probe is a message the child received: it is part of the replay traces and of the conversation, so the crash
+//! records of later crashes send it again as part of the history.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use libafl::{
+    events::{Event, EventFirer},
+    executors::HasObservers,
+    inputs::UsesInput,
+    monitors::{AggregatorOps, UserStats, UserStatsValue},
+    stages::Stage,
+    state::{HasExecutions, State, UsesState},
+    Error, Evaluator,
+};
+use libafl_bolts::{tuples::MatchName, Named};
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crash_record::Conversation,
+    executor::ResettableForkserver,
+    oracle::{ResponseRule, ResponseRules},
+    replay::hash_messages,
+    response::ResponseObserver,
+    state::MultipleStates,
+};
+
+/// A probe message that is sent after an input to find out if the target is still in the target state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriftProbe {
+    /// The message, it should not change the state itself
+    pub message: String,
+    /// Regex the response to the probe must match in each target state (by index or name of their prefix), other
+    /// target states are not probed
+    pub expected: BTreeMap<String, String>,
+    /// The probe is sent after every `interval`th execution
+    #[serde(default = "default_interval")]
+    pub interval: usize,
+}
+
+fn default_interval() -> usize {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+struct DriftFile {
+    #[serde(default)]
+    rules: Vec<ResponseRule>,
+    #[serde(default)]
+    probe: Option<DriftProbe>,
+}
+
+/// How drifts are detected, read from a JSON file, e.g.
+///
+/// ```json
+/// {"rules": [{"name": "REIN", "request": "(?i)^REIN", "codes": [220]}, {"name": "USER", "states": ["2_password"], "request": "(?i)^USER", "codes": [331]}],
+///  "probe": {"message": "PWD\r\n", "expected": {"2_password": "^257"}, "interval": 10}}
+/// ```
+///
+/// A response that matches one of the `rules` (see [`ResponseRule`]) means the input changed the state, and so does a
+/// response to the `probe` that does not match the regex expected in the target state.
+#[derive(Debug, Clone, Default)]
+pub struct DriftRules {
+    rules: ResponseRules,
+    probe: Option<(Vec<u8>, Vec<(String, Regex)>, usize)>,
+}
+
+impl DriftRules {
+    /// Compile the `rules` and the `probe`, fails if a regex is invalid or a rule has no condition.
+    pub fn new(rules: Vec<ResponseRule>, probe: Option<DriftProbe>) -> Result<Self, Error> {
+        let probe = probe
+            .map(|probe| {
+                let expected = probe
+                    .expected
+                    .into_iter()
+                    .map(|(state, regex)| {
+                        let regex = Regex::new(&regex).map_err(|e| {
+                            Error::illegal_argument(format!(
+                                "Invalid regex of the probe in state {state}: {e}"
+                            ))
+                        })?;
+                        Ok((state, regex))
+                    })
+                    .collect::<Result<_, Error>>()?;
+                Ok::<_, Error>((probe.message.into_bytes(), expected, probe.interval.max(1)))
+            })
+            .transpose()?;
+        Ok(Self {
+            rules: ResponseRules::new(rules)?,
+            probe,
+        })
+    }
+
+    /// Read the rules and the probe from a JSON file.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+        let file: DriftFile = serde_json::from_str(&content).map_err(|e| {
+            Error::illegal_argument(format!(
+                "Could not parse drift rules {}: {e}",
+                path.display()
+            ))
+        })?;
+        Self::new(file.rules, file.probe)
+    }
+
+    /// If drifts are never detected
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.probe.is_none()
+    }
+
+    /// The regex the response to the probe must match in the target state, if it is probed.
+    fn expected(&self, state_idx: usize, state_name: &str) -> Option<&Regex> {
+        let (_, expected, _) = self.probe.as_ref()?;
+        expected
+            .iter()
+            .find(|(state, _)| state == state_name || state.parse() == Ok(state_idx))
+            .map(|(_, regex)| regex)
+    }
+}
+
+/// An input that moved the target out of a target state.
+#[derive(Debug, Clone)]
+struct DriftCandidate {
+    state_idx: usize,
+    state_name: String,
+    reasons: Vec<String>,
+    count: usize,
+}
+
+/// Detects if the last input moved the target out of the current target state, see the [module documentation](self).
+///
+/// Put it after the mutational stage. If the child is no longer running, there is nothing to detect: the prefix is
+/// sent again anyway.
+#[derive(Debug)]
+pub struct DriftStage<S> {
+    response_observer_name: String,
+    rules: DriftRules,
+    conversation: Conversation,
+    drift_dir: PathBuf,
+    /// The inputs since the last probe, with the target state they were sent in
+    unprobed: Vec<(usize, Vec<u8>)>,
+    /// The inputs that drifted, by the hash of the input and the target state
+    candidates: HashMap<(u64, usize), DriftCandidate>,
+    phantom: PhantomData<S>,
+}
+
+impl<S> DriftStage<S> {
+    /// Create a new [`DriftStage`] looking at the response kept by the `response_observer`, where `conversation` is
+    /// the conversation that is given to the executor and `drift_dir` the directory the candidates are stored in
+    /// (e.g. `drift/`).
+    pub fn new(
+        response_observer: &ResponseObserver,
+        rules: DriftRules,
+        conversation: Conversation,
+        drift_dir: PathBuf,
+    ) -> Self {
+        Self {
+            response_observer_name: response_observer.name().to_string(),
+            rules,
+            conversation,
+            drift_dir,
+            unprobed: Vec::new(),
+            candidates: HashMap::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Count the drift of `input` out of the target state, store the input and rewrite `candidates.txt`.
+    fn record(
+        &mut self,
+        input: &[u8],
+        state_idx: usize,
+        state_name: &str,
+        reasons: Vec<String>,
+    ) -> Result<(), Error> {
+        let hash = hash_messages([input]);
+        fs::create_dir_all(&self.drift_dir)?;
+        let candidate = self
+            .candidates
+            .entry((hash, state_idx))
+            .or_insert_with(|| DriftCandidate {
+                state_idx,
+                state_name: state_name.to_string(),
+                reasons: Vec::new(),
+                count: 0,
+            });
+        candidate.count += 1;
+        for reason in reasons {
+            if !candidate.reasons.contains(&reason) {
+                candidate.reasons.push(reason);
+            }
+        }
+        let file = self.drift_dir.join(format!("{hash:016x}"));
+        if !file.exists() {
+            fs::write(file, input)?;
+        }
+
+        let mut candidates = self.candidates.iter().collect::<Vec<_>>();
+        candidates.sort_by(|(_, a), (_, b)| b.count.cmp(&a.count));
+        let summary = candidates
+            .into_iter()
+            .map(|((hash, _), candidate)| {
+                format!(
+                    "{:016x} count={} state={} ({}) reasons={}\n",
+                    hash,
+                    candidate.count,
+                    candidate.state_idx,
+                    candidate.state_name,
+                    candidate.reasons.join(",")
+                )
+            })
+            .collect::<String>();
+        fs::write(self.drift_dir.join("candidates.txt"), summary)?;
+        Ok(())
+    }
+}
+
+impl<S> UsesState for DriftStage<S>
+where
+    S: State,
+{
+    type State = S;
+}
+
+impl<E, EM, Z, S> Stage<E, EM, Z> for DriftStage<S>
+where
+    E: UsesState<State = S> + HasObservers + ResettableForkserver,
+    EM: UsesState<State = S> + EventFirer,
+    Z: Evaluator<E, EM, State = S>,
+    S: State + MultipleStates + HasExecutions,
+    <S as UsesInput>::Input: From<Vec<u8>>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut S,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        if self.rules.is_empty() || !executor.has_running_child() {
+            // a new child gets the prefix, the inputs before did not move it
+            self.unprobed.clear();
+            return Ok(());
+        }
+        let Some(request) = self.conversation.last() else {
+            return Ok(());
+        };
+        let state_idx = state.current_state_idx().0;
+        self.unprobed.retain(|(idx, _)| *idx == state_idx);
+        let state_name = state.prefix().metadata.name.clone();
+        let response = |executor: &E| {
+            executor
+                .observers()
+                .match_name::<ResponseObserver>(&self.response_observer_name)
+                .and_then(|observer| observer.response().map(<[u8]>::to_vec))
+        };
+
+        let reasons = self.rules.rules.matching(
+            state_idx,
+            &state_name,
+            &request,
+            response(executor).as_deref(),
+        );
+        // the inputs that moved the target out of the target state, with the reasons
+        let mut drifted = Vec::new();
+        if !reasons.is_empty() {
+            drifted.push((request, reasons));
+        } else if let (Some((probe, _, interval)), Some(expected)) =
+            (&self.rules.probe, self.rules.expected(state_idx, &state_name))
+        {
+            self.unprobed.push((state_idx, request));
+            if self.unprobed.len() >= *interval {
+                let unprobed = std::mem::take(&mut self.unprobed);
+                let input: <S as UsesInput>::Input = probe.clone().into();
+                fuzzer.evaluate_input(state, executor, manager, input)?;
+                // if the probe did not end well, the prefix is sent again anyway
+                if !executor.has_running_child() {
+                    return Ok(());
+                }
+                let probe_response = response(executor);
+                if !probe_response.is_some_and(|response| expected.is_match(&response)) {
+                    drifted = unprobed
+                        .into_iter()
+                        .map(|(_, request)| (request, vec!["probe".to_string()]))
+                        .collect();
+                }
+            }
+        }
+        if drifted.is_empty() {
+            return Ok(());
+        }
+
+        log::debug!(
+            "Input moved the target out of target state {state_idx} ({state_name}): {}",
+            drifted[0].1.join(", ")
+        );
+        *state.drifts() += 1;
+        let drifts = *state.drifts();
+        manager.fire(
+            state,
+            Event::UpdateUserStats {
+                name: "drifts".to_string(),
+                value: UserStats::new(UserStatsValue::Number(drifts as u64), AggregatorOps::Sum),
+                phantom: PhantomData,
+            },
+        )?;
+        for (request, reasons) in drifted {
+            self.record(&request, state_idx, &state_name, reasons)?;
+        }
+
+        // the next inputs belong in the target state
+        executor.reset_target_state()?;
+        let prefix = state
+            .prefix()
+            .prefix
+            .iter()
+            .map(|testcase| {
+                testcase
+                    .input()
+                    .clone()
+                    .expect("Prefix testcases should always have input")
+            })
+            .collect::<Vec<_>>();
+        for message in prefix {
+            fuzzer.evaluate_input(state, executor, manager, message)?;
+        }
+        Ok(())
+    }
+}
@@ -22,6 +22,7 @@ use std::{
 };
 use std::{
     ffi::{OsStr, OsString},
+    fs::File,
     io::{self, prelude::*, ErrorKind},
     net::{TcpListener, TcpStream},
     os::{
@@ -31,9 +32,11 @@ use std::{
     path::Path,
     process::{Child, Command, Stdio},
     thread::JoinHandle,
+    time::Instant,
 };
 
 use libafl_bolts::{
+    current_time,
     fs::{get_unique_std_input_file, InputFile},
     os::{dup2, pipes::Pipe},
     shmem::{ShMem, ShMemProvider, UnixShMemProvider},
@@ -46,14 +49,18 @@ use nix::{
         select::{pselect, FdSet},
         signal::{kill, SigSet, Signal},
         time::TimeSpec,
-        wait::waitpid,
+        wait::{waitpid, WaitPidFlag, WaitStatus},
     },
     unistd::Pid,
 };
 
+use super::memlimit::{MemLimitMode, MemoryCgroup};
 use crate::{
+    child_output::{ChildOutput, SanitizerReport},
+    crash_record::Conversation,
     libaflstar_bolts::create_timeout_error,
-    replay::{RequestResponseCollector, RequestResponsePair},
+    replay::{RequestResponseCollector, TraceMessage, TraceTargetState, RESPONSE_ERROR},
+    response::SharedResponse,
 };
 use libafl::{
     executors::{Executor, ExitKind, HasObservers},
@@ -100,6 +107,8 @@ pub trait ConfigTarget {
     fn setlimit(&mut self, memlimit: u64) -> &mut Self;
     /// Sets the stdin
     fn setstdin(&mut self, fd: RawFd, use_stdin: bool) -> &mut Self;
+    /// Moves the target into a new network namespace with only the loopback interface up
+    fn setnetns(&mut self, netns: bool) -> &mut Self;
     /// Sets the AFL forkserver pipes
     fn setpipe(
         &mut self,
@@ -170,6 +179,42 @@ impl ConfigTarget for Command {
         }
     }
 
+    fn setnetns(&mut self, netns: bool) -> &mut Self {
+        if !netns {
+            return self;
+        }
+        // # Safety
+        // Only calls async-signal-safe libc functions, no allocations.
+        let func = move || {
+            if unsafe { libc::unshare(libc::CLONE_NEWNET) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // A new network namespace only has a loopback interface, and it is down.
+            let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+            if sock < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut ifr: libc::ifreq = unsafe { core::mem::zeroed() };
+            for (dst, src) in ifr.ifr_name.iter_mut().zip(b"lo\0") {
+                *dst = *src as libc::c_char;
+            }
+            let mut ret = unsafe { libc::ioctl(sock, libc::SIOCGIFFLAGS, &mut ifr) };
+            if ret >= 0 {
+                unsafe {
+                    ifr.ifr_ifru.ifru_flags |= (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+                }
+                ret = unsafe { libc::ioctl(sock, libc::SIOCSIFFLAGS, &ifr) };
+            }
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(sock) };
+            if ret < 0 {
+                return Err(err);
+            }
+            Ok(())
+        };
+        unsafe { self.pre_exec(func) }
+    }
+
     #[allow(trivial_numeric_casts, clippy::cast_possible_wrap)]
     fn setlimit(&mut self, memlimit: u64) -> &mut Self {
         if memlimit == 0 {
@@ -223,6 +268,12 @@ pub struct Forkserver {
     last_run_timed_out: i32,
     /// The signal this [`Forkserver`] will use to kill (defaults to [`self.kill_signal`])
     kill_signal: Signal,
+    /// Number of leftover target processes killed by [`Forkserver::kill_process_group`] that were not yet taken
+    leftover_processes: usize,
+    /// Buffer the output of the target is captured in, if any
+    child_output: Option<ChildOutput>,
+    /// The memory cgroup the target runs in, if any. Declared last, so it is removed after the processes are gone.
+    cgroup: Option<MemoryCgroup>,
 }
 
 impl Drop for Forkserver {
@@ -241,6 +292,10 @@ impl Drop for Forkserver {
             }
         }
 
+        // Take down everything the target forked as well, otherwise a recreated forkserver
+        // finds its port still taken by the orphans of this one.
+        self.kill_process_group();
+
         let forkserver_pid = Pid::from_raw(self.fsrv_handle.id().try_into().unwrap());
         if let Err(err) = kill(forkserver_pid, self.kill_signal) {
             log::warn!(
@@ -287,12 +342,19 @@ impl Forkserver {
             is_deferred_frksrv,
             debug_output,
             KILL_SIGNAL_DEFAULT,
+            false,
+            None,
+            None,
         )
     }
 
     /// Create a new [`Forkserver`] that will kill child processes
     /// with the given `kill_signal`.
     /// Using `Forkserver::new(..)` will default to [`Signal::SIGTERM`].
+    /// If `netns` is set, the forkserver (and thus every child) runs in its own network namespace,
+    /// see [`Forkserver::network_namespace`].
+    /// If `child_output` is given (and `debug_output` is not set), stdout and stderr of the target are captured in it.
+    /// If `cgroup` is given, every child is moved into it, see [`Forkserver::move_child_to_cgroup`].
     #[allow(clippy::too_many_arguments)]
     pub fn with_kill_signal(
         target: OsString,
@@ -305,12 +367,28 @@ impl Forkserver {
         is_deferred_frksrv: bool,
         debug_output: bool,
         kill_signal: Signal,
+        netns: bool,
+        child_output: Option<ChildOutput>,
+        cgroup: Option<MemoryCgroup>,
     ) -> Result<Self, Error> {
         let mut st_pipe = Pipe::new().unwrap();
         let mut ctl_pipe = Pipe::new().unwrap();
 
+        // Processes forked by the child (e.g. per-connection workers) are orphaned when the child is killed.
+        // As a subreaper, they are re-parented to us instead of init, so we can reap them.
+        // # Safety
+        // Only sets a flag on the current process.
+        if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+            log::warn!(
+                "Could not become a child subreaper, zombies of the target will not be reaped: {}",
+                io::Error::last_os_error()
+            );
+        }
+
         let (stdout, stderr) = if debug_output {
             (Stdio::inherit(), Stdio::inherit())
+        } else if child_output.is_some() {
+            (Stdio::piped(), Stdio::piped())
         } else {
             (Stdio::null(), Stdio::null())
         };
@@ -333,11 +411,12 @@ impl Forkserver {
             command.env("__AFL_DEFER_FORKSRV", "1");
         }
 
-        let fsrv_handle = match command
+        let mut fsrv_handle = match command
             .env("LD_BIND_NOW", "1")
             .envs(envs)
             .setlimit(memlimit)
             .setsid()
+            .setnetns(netns)
             .setstdin(input_filefd, use_stdin)
             .setpipe(
                 st_pipe.read_end().unwrap(),
@@ -359,6 +438,17 @@ impl Forkserver {
         ctl_pipe.close_read_end();
         st_pipe.close_write_end();
 
+        // The capture threads end by themselves once the forkserver and all its children are gone
+        let child_output = child_output.filter(|_| !debug_output);
+        if let Some(output) = &child_output {
+            if let Some(stdout) = fsrv_handle.stdout.take() {
+                output.capture(stdout);
+            }
+            if let Some(stderr) = fsrv_handle.stderr.take() {
+                output.capture(stderr);
+            }
+        }
+
         Ok(Self {
             fsrv_handle,
             st_pipe,
@@ -367,9 +457,45 @@ impl Forkserver {
             status: 0,
             last_run_timed_out: 0,
             kill_signal,
+            leftover_processes: 0,
+            child_output,
+            cgroup,
         })
     }
 
+    /// The network namespace of the forkserver, which is shared by all its children.
+    ///
+    /// Only differs from our own network namespace if the forkserver was created with `netns`.
+    pub fn network_namespace(&self) -> Result<NetNamespace, Error> {
+        NetNamespace::of_process(self.fsrv_handle.id())
+    }
+
+    /// Move the current child into the memory cgroup, if there is one.
+    ///
+    /// Only the child runs in the cgroup, the forkserver stays outside, so the kernel never kills the forkserver when
+    /// a child exceeds the limit. The child should be moved before it gets its input: what it allocated before is
+    /// not charged to the cgroup.
+    pub fn move_child_to_cgroup(&self) -> Result<(), Error> {
+        match (&self.cgroup, self.child_pid) {
+            (Some(cgroup), Some(pid)) => cgroup.add_process(pid),
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of children that were killed for exceeding the memory limit of the cgroup, since the last call.
+    ///
+    /// Always 0 if the target does not run in a memory cgroup.
+    pub fn take_oom_kills(&mut self) -> u64 {
+        self.cgroup
+            .as_mut()
+            .map_or(0, MemoryCgroup::take_oom_kills)
+    }
+
+    /// The buffer the output of the target is captured in, if it is captured.
+    pub fn child_output(&self) -> Option<&ChildOutput> {
+        self.child_output.as_ref()
+    }
+
     /// If the last run timed out (as in-target i32)
     #[must_use]
     pub fn last_run_timed_out_raw(&self) -> i32 {
@@ -421,6 +547,62 @@ impl Forkserver {
         self.child_pid = None;
     }
 
+    /// Kill all processes of the target, except the forkserver itself, and reap them.
+    ///
+    /// The forkserver runs in its own session (see [`ConfigTarget::setsid`]), so its process group is the
+    /// target's process group. Every child it forks, and every process those children fork, stays in that session.
+    /// Killing only the child leaves per-connection workers (e.g. bftpd, proftpd) behind as orphans that hold on to
+    /// ports and files.
+    ///
+    /// Returns the number of leftover processes that were killed, not counting the current child.
+    pub fn kill_process_group(&mut self) -> usize {
+        let Ok(fsrv_pid) = i32::try_from(self.fsrv_handle.id()) else {
+            return 0;
+        };
+        let child_pid = self.child_pid.map(Pid::as_raw);
+        let our_pid = std::process::id() as i32;
+
+        let mut killed = Vec::new();
+        let mut leftovers = Vec::new();
+        for proc_stat in session_processes(fsrv_pid) {
+            if proc_stat.pid == fsrv_pid {
+                continue;
+            }
+            if proc_stat.state == 'Z' {
+                // already dead, only needs to be reaped if it was re-parented to us
+                if proc_stat.ppid == our_pid {
+                    killed.push(proc_stat.pid);
+                }
+                continue;
+            }
+            if let Err(err) = kill(Pid::from_raw(proc_stat.pid), Signal::SIGKILL) {
+                log::debug!("Failed to kill target process {}: {err}", proc_stat.pid);
+                continue;
+            }
+            if Some(proc_stat.pid) != child_pid {
+                leftovers.push(proc_stat.pid);
+            }
+            killed.push(proc_stat.pid);
+        }
+
+        reap_processes(&killed);
+
+        if !leftovers.is_empty() {
+            log::warn!(
+                "Killed {} leftover target process(es): {:?}",
+                leftovers.len(),
+                leftovers
+            );
+            self.leftover_processes += leftovers.len();
+        }
+        leftovers.len()
+    }
+
+    /// Returns the number of leftover processes killed since the last call, resetting the count.
+    pub fn take_leftover_processes(&mut self) -> usize {
+        std::mem::take(&mut self.leftover_processes)
+    }
+
     /// Read from the st pipe
     pub fn read_st(&mut self) -> Result<(usize, i32), Error> {
         let mut buf: [u8; 4] = [0_u8; 4];
@@ -527,6 +709,99 @@ impl Forkserver {
     }
 }
 
+/// Handle to the network namespace of a process.
+///
+/// Sockets are bound to the network namespace of the thread that creates them. [`NetNamespace::run`] creates them on a
+/// short-lived thread that first enters the namespace, so the rest of the fuzzer stays in its own namespace.
+#[derive(Debug)]
+pub struct NetNamespace {
+    ns: File,
+}
+
+impl NetNamespace {
+    /// Open the network namespace the process with `pid` is in.
+    pub fn of_process(pid: u32) -> Result<Self, Error> {
+        let path = format!("/proc/{pid}/ns/net");
+        let ns = File::open(&path).map_err(|e| {
+            Error::illegal_state(format!("Could not open network namespace {path}: {e}"))
+        })?;
+        Ok(Self { ns })
+    }
+
+    /// Run `f` on a new thread inside this network namespace and return its result.
+    ///
+    /// Entering a namespace requires `CAP_SYS_ADMIN`.
+    pub fn run<T, F>(&self, f: F) -> io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> io::Result<T> + Send + 'static,
+    {
+        let ns = self.ns.try_clone()?;
+        std::thread::spawn(move || {
+            if unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            f()
+        })
+        .join()
+        .map_err(|_| io::Error::new(ErrorKind::Other, "Network namespace thread panicked"))?
+    }
+}
+
+/// The fields of `/proc/[pid]/stat` we care about.
+struct ProcStat {
+    pid: i32,
+    state: char,
+    ppid: i32,
+    session: i32,
+}
+
+/// Parse `/proc/[pid]/stat`. The `comm` field is enclosed by parentheses, but may itself contain
+/// spaces and parentheses, so we split after the last `)`.
+fn read_proc_stat(pid: i32) -> Option<ProcStat> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let (_, rest) = stat.rsplit_once(')')?;
+    let mut fields = rest.split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let ppid = fields.next()?.parse().ok()?;
+    let _pgrp = fields.next()?;
+    let session = fields.next()?.parse().ok()?;
+    Some(ProcStat {
+        pid,
+        state,
+        ppid,
+        session,
+    })
+}
+
+/// All processes that are part of the given session.
+fn session_processes(session: i32) -> Vec<ProcStat> {
+    let Ok(proc_dir) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    proc_dir
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+        .filter_map(read_proc_stat)
+        .filter(|proc_stat| proc_stat.session == session)
+        .collect()
+}
+
+/// Reap the given processes, if they are our children.
+///
+/// The processes were just sent a SIGKILL, so we give them a brief moment to die.
+fn reap_processes(pids: &[i32]) {
+    for pid in pids {
+        for _ in 0..10 {
+            match waitpid(Pid::from_raw(*pid), Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::StillAlive) => sleep(Duration::from_millis(1)),
+                // reaped, or not our child (i.e., the forkserver reaps it)
+                _ => break,
+            }
+        }
+    }
+}
+
 #[non_exhaustive]
 #[derive(Debug, PartialEq)]
 enum InputMode {
@@ -555,6 +830,10 @@ where
     map_size: Option<usize>,
     timeout: TimeSpec,
     request_response_collector: Option<RequestResponseCollector>,
+    conversation: Option<Conversation>,
+    capture_responses: bool,
+    last_response: Option<Vec<u8>>,
+    shared_response: Option<SharedResponse>,
 }
 
 impl<OT, S, SP> Debug for ForkserverExecutor<OT, S, SP>
@@ -604,6 +883,20 @@ where
         &self.forkserver
     }
 
+    /// If the child that just terminated was killed for exceeding the memory limit, or its sanitizer
+    /// reported that an allocation failed.
+    fn child_ran_out_of_memory(&mut self) -> bool {
+        if self.forkserver.take_oom_kills() > 0 {
+            return true;
+        }
+        let Some(output) = self.forkserver.child_output() else {
+            return false;
+        };
+        output.settle();
+        SanitizerReport::parse(&String::from_utf8_lossy(&output.snapshot()))
+            .is_some_and(|report| report.is_oom())
+    }
+
     /// Get a mutable reference to the [`Forkserver`] instance.
     pub fn forkserver_mut(&mut self) -> &mut Forkserver {
         &mut self.forkserver
@@ -619,6 +912,29 @@ where
         self.map_size
     }
 
+    /// The timeout of an execution
+    pub fn timeout(&self) -> Duration {
+        Duration::from(self.timeout)
+    }
+
+    /// Change the timeout of the next executions
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = TimeSpec::from_duration(timeout);
+    }
+
+    /// Tell the [`RequestResponseCollector`], if any, which target state the next traces are recorded in.
+    pub fn set_trace_target_state(&mut self, target_state: TraceTargetState) {
+        if let Some(collector) = self.request_response_collector.as_mut() {
+            collector.set_target_state(target_state);
+        }
+    }
+
+    /// The response of the target to the last input, if responses are read
+    /// (see [`ForkserverExecutorBuilder::capture_responses`]) and it could be read.
+    pub fn last_response(&self) -> Option<&[u8]> {
+        self.last_response.as_deref()
+    }
+
     // Drops the forkserver, returning the RequestResponseCollector, enables creating a new forkserver.
     pub fn shutdown(mut self) -> (Option<RequestResponseCollector>, OT) {
         (self.request_response_collector.take(), self.observers)
@@ -648,6 +964,13 @@ pub struct ForkserverExecutorBuilder<'a, SP> {
     kill_signal: Option<Signal>,
     timeout: Option<Duration>,
     request_response_collector: Option<RequestResponseCollector>,
+    netns: bool,
+    child_output: Option<ChildOutput>,
+    memlimit: u64,
+    memlimit_mode: MemLimitMode,
+    conversation: Option<Conversation>,
+    capture_responses: bool,
+    shared_response: Option<SharedResponse>,
 }
 
 impl<'a, SP> ForkserverExecutorBuilder<'a, SP> {
@@ -675,9 +998,13 @@ impl<'a, SP> ForkserverExecutorBuilder<'a, SP> {
             self.use_stdin
         );
 
+        let netns = match self.netns {
+            true => Some(forkserver.network_namespace()?),
+            false => None,
+        };
         let socket_con = match input_mode {
-            InputMode::SocketServer(port) => Some(SocketConnector::new_server(port)?),
-            InputMode::SocketClient(port) => Some(SocketConnector::new_client(port)),
+            InputMode::SocketServer(port) => Some(SocketConnector::new_server(port, netns)?),
+            InputMode::SocketClient(port) => Some(SocketConnector::new_client(port, netns)),
             _ => None,
         };
 
@@ -705,6 +1032,10 @@ impl<'a, SP> ForkserverExecutorBuilder<'a, SP> {
             timeout,
             input_mode,
             request_response_collector: self.request_response_collector.take(),
+            conversation: self.conversation.clone(),
+            capture_responses: self.capture_responses,
+            last_response: None,
+            shared_response: self.shared_response.clone(),
         })
     }
 
@@ -740,7 +1071,11 @@ impl<'a, SP> ForkserverExecutorBuilder<'a, SP> {
         );
 
         let socket_con = if let InputMode::SocketServer(port) = input_mode {
-            Some(SocketConnector::new_server(port)?)
+            let netns = match self.netns {
+                true => Some(forkserver.network_namespace()?),
+                false => None,
+            };
+            Some(SocketConnector::new_server(port, netns)?)
         } else {
             None
         };
@@ -775,6 +1110,10 @@ impl<'a, SP> ForkserverExecutorBuilder<'a, SP> {
             timeout,
             input_mode,
             request_response_collector: self.request_response_collector.take(),
+            conversation: self.conversation.clone(),
+            capture_responses: self.capture_responses,
+            last_response: None,
+            shared_response: self.shared_response.clone(),
         })
     }
 
@@ -826,6 +1165,12 @@ impl<'a, SP> ForkserverExecutorBuilder<'a, SP> {
             .map(|f| f.as_raw_fd())
             .unwrap_or_default();
 
+        let (rlimit, cgroup) = match self.memlimit_mode {
+            _ if self.memlimit == 0 => (0, None),
+            MemLimitMode::Rlimit => (self.memlimit, None),
+            MemLimitMode::Cgroup => (0, Some(MemoryCgroup::new(self.memlimit)?)),
+        };
+
         let mut forkserver = match &self.program {
             Some(t) => Forkserver::with_kill_signal(
                 t.clone(),
@@ -833,11 +1178,14 @@ impl<'a, SP> ForkserverExecutorBuilder<'a, SP> {
                 self.envs.clone(),
                 input_fd,
                 input_mode == InputMode::Stdin,
-                0,
+                rlimit,
                 self.is_persistent,
                 self.is_deferred_frksrv,
                 self.debug_child,
                 self.kill_signal.unwrap_or(KILL_SIGNAL_DEFAULT),
+                self.netns,
+                self.child_output.clone(),
+                cgroup,
             )?,
             None => {
                 return Err(Error::illegal_argument(
@@ -1160,6 +1508,59 @@ impl<'a, SP> ForkserverExecutorBuilder<'a, SP> {
         self.request_response_collector = Some(collector);
         self
     }
+
+    /// Run the target in its own network namespace, with only the loopback interface up.
+    ///
+    /// The socket connections to the target are made from inside that namespace. This way, several instances can use
+    /// the same port without interfering, and the target cannot reach the real network. Requires `CAP_SYS_ADMIN` (root).
+    #[must_use]
+    pub fn network_namespace(mut self, netns: bool) -> Self {
+        self.netns = netns;
+        self
+    }
+
+    /// Capture stdout and stderr of the target in `child_output`, instead of discarding them.
+    ///
+    /// The buffer is cleared each time a new child is started. Ignored if `debug_child` is set.
+    #[must_use]
+    pub fn capture_child_output(mut self, child_output: ChildOutput) -> Self {
+        self.child_output = Some(child_output);
+        self
+    }
+
+    /// Limit the memory of the target to `memlimit` megabytes, 0 means no limit; default is no limit.
+    ///
+    /// Children that exceed the limit, and children that report an allocation failure in their sanitizer
+    /// output (requires [`ForkserverExecutorBuilder::capture_child_output`]), result in [`ExitKind::Oom`].
+    #[must_use]
+    pub fn memlimit(mut self, memlimit: u64, mode: MemLimitMode) -> Self {
+        self.memlimit = memlimit;
+        self.memlimit_mode = mode;
+        self
+    }
+
+    /// Keep every message the current child received, and the signal it was terminated with, in `conversation`.
+    #[must_use]
+    pub fn record_conversation(mut self, conversation: Conversation) -> Self {
+        self.conversation = Some(conversation);
+        self
+    }
+
+    /// Read the response of the target after each execution, also without a [`RequestResponseCollector`],
+    /// see [`ForkserverExecutor::last_response`]. Only works if the input mode is through a socket.
+    #[must_use]
+    pub fn capture_responses(mut self, capture_responses: bool) -> Self {
+        self.capture_responses = capture_responses;
+        self
+    }
+
+    /// Read the response of the target after each execution, and keep it in `response` for observers,
+    /// see [`crate::response::ResponseObserver`]. Only works if the input mode is through a socket.
+    #[must_use]
+    pub fn share_responses(mut self, response: SharedResponse) -> Self {
+        self.shared_response = Some(response);
+        self
+    }
 }
 
 impl<'a> ForkserverExecutorBuilder<'a, UnixShMemProvider> {
@@ -1192,6 +1593,13 @@ impl<'a> ForkserverExecutorBuilder<'a, UnixShMemProvider> {
             kill_signal: None,
             timeout: None,
             request_response_collector: None,
+            netns: false,
+            child_output: None,
+            memlimit: 0,
+            memlimit_mode: MemLimitMode::Rlimit,
+            conversation: None,
+            capture_responses: false,
+            shared_response: None,
         }
     }
 
@@ -1220,6 +1628,13 @@ impl<'a> ForkserverExecutorBuilder<'a, UnixShMemProvider> {
             kill_signal: None,
             timeout: None,
             request_response_collector: self.request_response_collector,
+            netns: self.netns,
+            child_output: self.child_output,
+            memlimit: self.memlimit,
+            memlimit_mode: self.memlimit_mode,
+            conversation: self.conversation,
+            capture_responses: self.capture_responses,
+            shared_response: self.shared_response,
         }
     }
 }
@@ -1258,6 +1673,13 @@ where
             if let Some(ref mut collector) = self.request_response_collector {
                 collector.start_new_trace()?;
             }
+            // and the output and conversation belong to the new child
+            if let Some(output) = self.forkserver.child_output() {
+                output.clear();
+            }
+            if let Some(conversation) = &self.conversation {
+                conversation.clear();
+            }
         }
 
         match self.input_mode {
@@ -1338,6 +1760,14 @@ where
         }
 
         self.forkserver.set_child_pid(Pid::from_raw(pid));
+        self.forkserver.move_child_to_cgroup()?;
+
+        let sent_at = Instant::now();
+        let sent_time = current_time();
+
+        if let Some(conversation) = &self.conversation {
+            conversation.push(input.target_bytes().as_slice());
+        }
 
         // Communicate test case through socket.
         match self.input_mode {
@@ -1359,11 +1789,16 @@ where
         }
 
         // Wait for the test case to execute
-        if let Some(status) = self.forkserver.read_st_timed(&self.timeout)? {
+        let status = self.forkserver.read_st_timed(&self.timeout)?;
+        let latency = sent_at.elapsed();
+        if let Some(status) = status {
             self.forkserver.set_status(status);
             if libc::WIFSIGNALED(self.forkserver().status()) {
                 exit_kind = ExitKind::Crash;
             }
+            if !libc::WIFSTOPPED(self.forkserver().status()) && self.child_ran_out_of_memory() {
+                exit_kind = ExitKind::Oom;
+            }
         } else {
             self.forkserver.set_last_run_timed_out(true);
 
@@ -1381,6 +1816,7 @@ where
                 .read_st_timed(&TimeSpec::from_duration(Duration::from_secs(2)))?
             {
                 self.forkserver.set_status(status);
+                self.forkserver.kill_process_group();
                 exit_kind = ExitKind::Timeout;
             } else {
                 return Err(create_timeout_error(
@@ -1389,42 +1825,62 @@ where
             }
         }
 
-        // At the end of each run, collect the request response pair if we have a collector
-        if let Some(ref mut collector) = self.request_response_collector {
+        if let Some(conversation) = &self.conversation {
+            let status = self.forkserver().status();
+            conversation.set_signal(libc::WIFSIGNALED(status).then_some(libc::WTERMSIG(status)));
+        }
+
+        // At the end of each run, read the response if it is captured or collected
+        let mut response_read = false;
+        self.last_response = None;
+        if self.capture_responses
+            || self.shared_response.is_some()
+            || self.request_response_collector.is_some()
+        {
             match self.input_mode {
                 InputMode::SocketClient(_) | InputMode::SocketServer(_) => {
                     // # Safety
                     // Struct can never be created when input mode is SocketServer and socket connector is none.
                     let socket_con = unsafe { self.socket_con.as_mut().unwrap_unchecked() };
                     if let Some(ref mut stream) = socket_con.stream {
+                        response_read = true;
                         // !! This limits responses to be of 4096 bytes or less!
                         // is that a good size? depends on the target, but should be good most of the time
                         let mut response = vec![0u8; 4096];
-                        let input_bytes = input.target_bytes();
-                        let pair = match stream.read(&mut response) {
-                            Ok(num_bytes) => RequestResponsePair::new(
-                                exit_kind,
-                                input_bytes.as_slice(),
-                                &response[..num_bytes],
-                            ),
-                            Err(e) => {
-                                log::warn!("Could not read response from the target: {e}");
-                                RequestResponsePair::new(
-                                    exit_kind,
-                                    input_bytes.as_slice(),
-                                    "LibAFLStar_err".as_bytes(),
-                                )
+                        match stream.read(&mut response) {
+                            Ok(num_bytes) => {
+                                response.truncate(num_bytes);
+                                self.last_response = Some(response);
                             }
-                        };
-                        collector.write_pair(&pair)?
+                            Err(e) => log::warn!("Could not read response from the target: {e}"),
+                        }
                     }
                 }
                 _ => {}
             }
+        }
+        if let Some(shared_response) = &self.shared_response {
+            shared_response.set(self.last_response.clone());
+        }
+
+        // collect the request response pair if we have a collector
+        if let Some(ref mut collector) = self.request_response_collector {
+            if response_read {
+                let input_bytes = input.target_bytes();
+                let response = self
+                    .last_response
+                    .as_deref()
+                    .unwrap_or(RESPONSE_ERROR);
+                let mut message = TraceMessage::new(exit_kind, input_bytes.as_slice(), response);
+                message.time = sent_time.as_millis() as u64;
+                message.execution = *state.executions() as u64;
+                message.latency_us = latency.as_micros() as u64;
+                collector.write_message(&message)?
+            }
 
             // if it's a crash, save the trace
             if exit_kind == ExitKind::Crash {
-                collector.save_this_trace();
+                collector.save_this_trace()?;
             }
         }
 
@@ -1449,11 +1905,14 @@ where
 /// In server mode, you first have to call [`SocketConnector::serv_start`]. If there is no stream, this spins up a thread that starts listening.
 /// Afterwards you can call [`SocketConnector::serv_finish`] to obtain the mut ref to the TcpStream. Before calling [`SocketConnector::serv_start`] _again_,
 /// you *must* have first called [`SocketConnector::serv_finish`].
+///
+/// If a [`NetNamespace`] is given, the sockets are created inside that namespace.
 struct SocketConnector {
     port: u16,
     listener: Option<TcpListener>,
     stream: Option<TcpStream>,
     handle: Option<JoinHandle<Result<(TcpListener, TcpStream), Error>>>,
+    netns: Option<NetNamespace>,
 }
 
 impl SocketConnector {
@@ -1462,26 +1921,39 @@ impl SocketConnector {
     /// You are only allowed to call [`SocketConnector::serv_start`] and [`SocketConnector::serv_finish`].
     /// These calls *MUST* be alternating, starting with a [`SocketConnector::serv_start`]. Calling either method
     /// twice without calling the other will yield bad results, probably a panic.
-    pub fn new_server(port: u16) -> Result<Self, Error> {
-        let listener = TcpListener::bind(format!("localhost:{port}"))?;
+    pub fn new_server(port: u16, netns: Option<NetNamespace>) -> Result<Self, Error> {
+        let listener = match &netns {
+            Some(ns) => ns.run(move || TcpListener::bind(format!("localhost:{port}")))?,
+            None => TcpListener::bind(format!("localhost:{port}"))?,
+        };
 
         Ok(Self {
             port,
             listener: Some(listener),
             stream: None,
             handle: None,
+            netns,
         })
     }
 
     /// Creates a new SocketConnector in client mode.
     ///
     /// You are only allowed to call [`SocketConnector::client_connect`].
-    pub fn new_client(port: u16) -> Self {
+    pub fn new_client(port: u16, netns: Option<NetNamespace>) -> Self {
         Self {
             port,
             listener: None,
             stream: None,
             handle: None,
+            netns,
+        }
+    }
+
+    /// Connect to `sock`, from inside the network namespace if there is one.
+    fn connect(&self, sock: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        match &self.netns {
+            Some(ns) => ns.run(move || TcpStream::connect_timeout(&sock, timeout)),
+            None => TcpStream::connect_timeout(&sock, timeout),
         }
     }
 
@@ -1581,7 +2053,7 @@ impl SocketConnector {
 
                 let retries = 20;
                 for _ in 0..retries {
-                    match TcpStream::connect_timeout(&sock, Duration::from_secs(1)) {
+                    match self.connect(sock, Duration::from_secs(1)) {
                         Ok(stream) => {
                             // If writing the test case or reading the response takes more than 2 seconds,
                             // something has gone wrong
@@ -0,0 +1,119 @@
+//! Memory limits for the target.
+//!
+//! The limit is either enforced with `setrlimit(RLIMIT_AS)`, see [`super::forkserver::ConfigTarget::setlimit`], or with a
+//! (v2) memory cgroup each child is moved into. A rlimit limits the address space, which does not go well with
+//! sanitizers that reserve a lot of it (e.g. ASAN). A cgroup limits the memory that is actually used, and the kernel
+//! kills a child that exceeds it. Those kills are counted in `memory.events`, which is how [`MemoryCgroup`] detects them.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use libafl::Error;
+use nix::{libc, unistd::Pid};
+
+/// Directory where the (unified, v2) cgroup hierarchy is mounted.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// How the memory limit of the target is enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MemLimitMode {
+    /// Limit the address space of the target with `setrlimit`, allocations beyond the limit fail.
+    Rlimit,
+    /// Limit the memory the target uses with a memory cgroup, the target is killed when it exceeds it.
+    /// Requires root, or a delegated cgroup hierarchy.
+    Cgroup,
+}
+
+/// A memory cgroup each child of the forkserver runs in.
+///
+/// The forkserver itself stays outside, see [`super::forkserver::Forkserver::move_child_to_cgroup`].
+///
+/// The cgroup is removed again on drop, which only works when all processes in it are gone.
+#[derive(Debug)]
+pub struct MemoryCgroup {
+    path: PathBuf,
+    /// The `oom_kill` count of `memory.events` we have seen so far
+    oom_kills: u64,
+}
+
+impl MemoryCgroup {
+    /// Create a new cgroup that limits the memory of its processes to `limit_mb` megabytes, without swap.
+    ///
+    /// # Errors:
+    ///
+    /// - The cgroup cannot be created, e.g. because we are not root or the memory controller is not available
+    pub fn new(limit_mb: u64) -> Result<Self, Error> {
+        let path = Path::new(CGROUP_ROOT).join(format!(
+            "libaflstar-{}-{}",
+            std::process::id(),
+            libafl_bolts::current_nanos()
+        ));
+        fs::create_dir(&path).map_err(|e| {
+            Error::illegal_state(format!(
+                "Could not create memory cgroup {} (requires root and cgroup v2): {e}",
+                path.display()
+            ))
+        })?;
+        let cgroup = Self { path, oom_kills: 0 };
+        cgroup.write("memory.max", &(limit_mb << 20).to_string())?;
+        // swap is not available in every setup, so it is fine if this fails
+        if let Err(e) = cgroup.write("memory.swap.max", "0") {
+            log::warn!("Could not disable swap for the target: {e}");
+        }
+        log::info!(
+            "Created memory cgroup {} with a limit of {limit_mb} MB",
+            cgroup.path.display()
+        );
+        Ok(cgroup)
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<(), Error> {
+        fs::write(self.path.join(file), value).map_err(|e| {
+            Error::illegal_state(format!(
+                "Could not write {value} to {}: {e}",
+                self.path.join(file).display()
+            ))
+        })
+    }
+
+    /// Move the process `pid` into this cgroup. A process that is already gone is ignored.
+    pub fn add_process(&self, pid: Pid) -> Result<(), Error> {
+        match fs::write(self.path.join("cgroup.procs"), pid.to_string()) {
+            Err(e) if e.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+            Err(e) => Err(Error::illegal_state(format!(
+                "Could not move process {pid} into memory cgroup {}: {e}",
+                self.path.display()
+            ))),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Number of processes the kernel killed for exceeding the limit, since the last call.
+    pub fn take_oom_kills(&mut self) -> u64 {
+        let Ok(events) = fs::read_to_string(self.path.join("memory.events")) else {
+            return 0;
+        };
+        let total = events
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .and_then(|count| count.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        let new = total.saturating_sub(self.oom_kills);
+        self.oom_kills = total;
+        new
+    }
+}
+
+impl Drop for MemoryCgroup {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir(&self.path) {
+            log::warn!(
+                "Could not remove memory cgroup {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
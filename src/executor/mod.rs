@@ -1,4 +1,7 @@
-pub use stateful::{ResettableForkserver, StatefulPersistentExecutor};
+pub use stateful::{
+    last_timeout_unconfirmed, ResettableForkserver, RestartPolicy, RestartReason, StatefulPersistentExecutor,
+};
 pub mod stateful;
 
 pub mod forkserver;
+pub mod memlimit;
@@ -10,21 +10,30 @@ use libafl::monitors::{UserStats, UserStatsValue};
 use libafl::observers::{ObserversTuple, UsesObservers};
 use libafl_bolts::impl_serdeany;
 use serde::{Deserialize, Serialize};
-use std::marker::PhantomData;
+use std::{
+    fs,
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
 
 use libafl::prelude::ExitKind;
 use libafl::state::{HasExecutions, HasMetadata, State};
 use libafl::Error;
 use libafl::{executors::Executor, state::UsesState};
 use libafl_bolts::shmem::ShMemProvider;
-use nix::sys::signal::{kill, Signal};
-use nix::unistd::Pid;
+use nix::{
+    libc,
+    sys::signal::{kill, Signal},
+};
 
 use super::forkserver::ForkserverExecutor;
+use crate::{crash_record::CONVERSATION_MAX_LEN, replay::TraceTargetState, response::HasLastResponse};
+use crate::workdir::{RestorePoint, WorkdirSnapshot};
 
 #[derive(Debug)]
 pub struct StatefulPersistentExecutor<OT, S, SP>
 where
+    S: UsesInput,
     SP: ShMemProvider,
 {
     executor: ForkserverExecutor<OT, S, SP>,
@@ -32,6 +41,53 @@ where
     state_reset_occurred: bool,
     /// If the child was reset since the last execution
     child_was_reset: bool,
+    /// The working directory of the target that is restored on resets
+    workdir: Option<WorkdirSnapshot>,
+    /// The timeout with which timeouts are confirmed, see [`StatefulPersistentExecutor::with_hang_confirmation`]
+    hang_timeout: Option<Duration>,
+    /// The inputs the current child received, to replay them when confirming a timeout
+    history: Vec<S::Input>,
+    /// If the history misses inputs, because the child received more than [`CONVERSATION_MAX_LEN`]
+    history_truncated: bool,
+    /// When the child is due for a restart, see [`StatefulPersistentExecutor::with_restart_policy`]
+    restart_policy: RestartPolicy,
+    /// Executions since the child started
+    child_executions: u64,
+    /// When the child started
+    child_started: Instant,
+}
+
+/// When a long-running child is restarted, to bound the memory it leaks and the global state it accumulates.
+///
+/// Limits that are `None` do not apply, the default never restarts the child.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RestartPolicy {
+    /// Restart the child after this many executions
+    pub max_executions: Option<u64>,
+    /// Restart the child after it ran this long
+    pub max_age: Option<Duration>,
+    /// Restart the child once its resident set size exceeds this many bytes
+    pub max_rss: Option<u64>,
+}
+
+/// Why a child is due for a restart, see [`RestartPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartReason {
+    /// It reached [`RestartPolicy::max_executions`]
+    Executions,
+    /// It reached [`RestartPolicy::max_age`]
+    Age,
+    /// It exceeded [`RestartPolicy::max_rss`]
+    Rss,
+}
+
+/// The resident set size of the process `pid` in bytes, from `/proc/<pid>/statm`.
+fn resident_set_size(pid: i32) -> Option<u64> {
+    let statm = fs::read_to_string(format!("/proc/{pid}/statm")).ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // Safety: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * u64::try_from(page_size).ok()?)
 }
 
 pub trait ResettableForkserver {
@@ -48,6 +104,22 @@ pub trait ResettableForkserver {
     /// that it will only return true once whenever a state reset occurs.
     /// The `flag` is also reset when [`ResettableForkserver::reset_target_state`] is called.
     fn state_reset_occurred(&mut self) -> bool;
+
+    /// Called when the fuzzer switches to another target state, before its prefix is sent.
+    fn target_state_changed(&mut self, _target_state: TraceTargetState) {}
+
+    /// Change the timeout of the next executions, e.g., to the calibrated timeout of a target state.
+    fn set_timeout(&mut self, timeout: Duration);
+
+    /// If the child is still running, i.e., the next input is sent to the child that received the previous ones.
+    fn has_running_child(&self) -> bool;
+
+    /// If the running child should be restarted according to the [`RestartPolicy`], and why.
+    /// The restart itself is up to the caller: reset the target and send the prefix again.
+    fn restart_due(&self) -> Option<RestartReason>;
+
+    /// How often the working directory of the target was restored, `None` if it is not restored.
+    fn workdir_restores(&self) -> Option<usize>;
 }
 
 impl<OT, S, SP> StatefulPersistentExecutor<OT, S, SP>
@@ -62,13 +134,58 @@ where
             executor,
             state_reset_occurred: false,
             child_was_reset: false,
+            workdir: None,
+            hang_timeout: None,
+            history: Vec::new(),
+            history_truncated: false,
+            restart_policy: RestartPolicy::default(),
+            child_executions: 0,
+            child_started: Instant::now(),
         }
     }
 
+    /// Restart the child when the `restart_policy` says so, see [`ResettableForkserver::restart_due`].
+    pub fn with_restart_policy(mut self, restart_policy: RestartPolicy) -> Self {
+        self.restart_policy = restart_policy;
+        self
+    }
+
+    /// Confirm timeouts before they are reported: the inputs the child received before are replayed to a new child,
+    /// and the input that timed out is run again with the longer `hang_timeout`.
+    /// A timeout that does not happen again is flaky, the execution ends as it did the second time. A timeout that
+    /// cannot be confirmed still ends with [`ExitKind::Timeout`], see [`last_timeout_unconfirmed`].
+    pub fn with_hang_confirmation(mut self, hang_timeout: Duration) -> Self {
+        self.hang_timeout = Some(hang_timeout);
+        self
+    }
+
+    /// Restore the working directory of the target whenever the [`RestorePoint`] of the snapshot is reached.
+    pub fn with_workdir_snapshot(mut self, workdir: WorkdirSnapshot) -> Self {
+        self.workdir = Some(workdir);
+        self
+    }
+
+    /// Take the working directory snapshot, e.g., to hand it over to a recreated executor.
+    pub fn take_workdir_snapshot(&mut self) -> Option<WorkdirSnapshot> {
+        self.workdir.take()
+    }
+
     pub fn into_inner(self) -> ForkserverExecutor<OT, S, SP> {
         self.executor
     }
 }
+impl<OT, S, SP> HasLastResponse for StatefulPersistentExecutor<OT, S, SP>
+where
+    OT: ObserversTuple<S>,
+    S: UsesInput,
+    SP: ShMemProvider,
+{
+    /// See [`ForkserverExecutor::last_response`].
+    fn last_response(&self) -> Option<&[u8]> {
+        self.executor.last_response()
+    }
+}
+
 impl<OT, S, SP> ResettableForkserver for StatefulPersistentExecutor<OT, S, SP>
 where
     OT: ObserversTuple<S>,
@@ -89,11 +206,12 @@ where
             Some(child_pid) if !timed_out => {
                 // usual path, kill the child
                 if child_pid.as_raw() > 0 {
-                    let process_group = child_pid.as_raw();
-                    let result = kill(Pid::from_raw(process_group), Signal::SIGKILL);
+                    let result = kill(child_pid, Signal::SIGKILL);
                     if let Err(e) = result {
                         log::info!("Error killing child: {e}");
                     }
+                    // also kill anything the child forked, such as per-connection workers
+                    self.executor.forkserver_mut().kill_process_group();
                     self.executor.forkserver_mut().set_last_run_timed_out(true);
                     self.executor.forkserver_mut().reset_child_pid();
                 }
@@ -105,6 +223,10 @@ where
             }
             _ => unreachable!("All arms are covered."),
         };
+        // Both restore points include state switches, and the child is dead now.
+        if let Some(workdir) = self.workdir.as_mut() {
+            workdir.restore()?;
+        }
         self.child_was_reset = true;
         self.state_reset_occurred = false;
         Ok(())
@@ -115,11 +237,65 @@ where
         self.state_reset_occurred = false;
         result
     }
+
+    /// New traces of the request response collector are recorded in the new target state.
+    fn target_state_changed(&mut self, target_state: TraceTargetState) {
+        self.executor.set_trace_target_state(target_state);
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.executor.set_timeout(timeout);
+    }
+
+    fn has_running_child(&self) -> bool {
+        self.executor.forkserver().child_pid().is_some()
+    }
+
+    fn restart_due(&self) -> Option<RestartReason> {
+        let child_pid = self.executor.forkserver().child_pid()?;
+        let policy = &self.restart_policy;
+        if policy
+            .max_executions
+            .is_some_and(|max| self.child_executions >= max)
+        {
+            Some(RestartReason::Executions)
+        } else if policy
+            .max_age
+            .is_some_and(|max| self.child_started.elapsed() >= max)
+        {
+            Some(RestartReason::Age)
+        } else if policy.max_rss.is_some_and(|max| {
+            resident_set_size(child_pid.as_raw()).is_some_and(|rss| rss > max)
+        }) {
+            Some(RestartReason::Rss)
+        } else {
+            None
+        }
+    }
+
+    fn workdir_restores(&self) -> Option<usize> {
+        self.workdir.as_ref().map(WorkdirSnapshot::restores)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct StatefulPersistentExecutorMeta {
     timeouts: u64,
+    /// Timeouts that did not happen again with the longer timeout, see
+    /// [`StatefulPersistentExecutor::with_hang_confirmation`]
+    #[serde(default)]
+    flaky_timeouts: u64,
+    /// Timeouts that could not be confirmed, because the history of the child was truncated, or replaying it did not
+    /// end well
+    #[serde(default)]
+    unconfirmed_timeouts: u64,
+    /// If the last timeout could not be confirmed, see [`last_timeout_unconfirmed`]
+    #[serde(default)]
+    last_timeout_unconfirmed: bool,
+    /// Processes of the target that were still alive after the child was killed
+    leftover_processes: u64,
+    /// Executions where the target ran out of memory
+    ooms: u64,
 }
 
 impl StatefulPersistentExecutorMeta {
@@ -134,6 +310,27 @@ impl StatefulPersistentExecutorMeta {
 
 impl_serdeany!(StatefulPersistentExecutorMeta);
 
+/// If the last execution that ended with [`ExitKind::Timeout`] is a timeout that could not be confirmed, see
+/// [`StatefulPersistentExecutor::with_hang_confirmation`]. Such timeouts are no hangs.
+pub fn last_timeout_unconfirmed<S>(state: &S) -> bool
+where
+    S: HasMetadata,
+{
+    state
+        .metadata::<StatefulPersistentExecutorMeta>()
+        .is_ok_and(|meta| meta.last_timeout_unconfirmed)
+}
+
+/// How confirming a timeout ended, see [`StatefulPersistentExecutor::with_hang_confirmation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HangConfirmation {
+    /// The input was run again, and ended with this exit kind
+    Rerun(ExitKind),
+    /// The timeout could not be confirmed, the execution ends with this exit kind: [`ExitKind::Timeout`] if the history
+    /// of the child is truncated, otherwise how replaying the history ended
+    Unconfirmed(ExitKind),
+}
+
 impl<EM, Z, OT, S, SP> Executor<EM, Z> for StatefulPersistentExecutor<OT, S, SP>
 where
     EM: UsesState<State = S> + EventFirer,
@@ -151,7 +348,95 @@ where
         mgr: &mut EM,
         input: &Self::Input,
     ) -> Result<ExitKind, Error> {
-        let result = self.executor.run_target(fuzzer, state, mgr, input);
+        // A new child will be started, restore the working directory if it was not
+        // already restored by `reset_target_state`.
+        if let Some(workdir) = self.workdir.as_mut() {
+            if workdir.restore_point() == RestorePoint::ChildRestart
+                && !self.child_was_reset
+                && self.executor.forkserver().child_pid().is_none()
+            {
+                workdir.restore()?;
+            }
+        }
+
+        // A new child starts with an empty history
+        if self.executor.forkserver().child_pid().is_none() {
+            self.history.clear();
+            self.history_truncated = false;
+            self.child_executions = 0;
+            self.child_started = Instant::now();
+        }
+
+        let mut result = self.executor.run_target(fuzzer, state, mgr, input);
+        self.child_executions += 1;
+
+        if !state.has_metadata::<StatefulPersistentExecutorMeta>() {
+            state.add_metadata(StatefulPersistentExecutorMeta::default())
+        }
+
+        if let (Ok(ExitKind::Timeout), Some(hang_timeout)) = (&result, self.hang_timeout) {
+            let confirmation = self.confirm_hang(fuzzer, state, mgr, input, hang_timeout)?;
+            let meta = state.metadata_mut::<StatefulPersistentExecutorMeta>()?;
+            let (exit_kind, counter) = match confirmation {
+                HangConfirmation::Rerun(ExitKind::Timeout) => {
+                    meta.last_timeout_unconfirmed = false;
+                    (ExitKind::Timeout, None)
+                }
+                HangConfirmation::Rerun(exit_kind) => {
+                    meta.flaky_timeouts += 1;
+                    (exit_kind, Some(("flaky_timeouts", meta.flaky_timeouts)))
+                }
+                HangConfirmation::Unconfirmed(exit_kind) => {
+                    meta.unconfirmed_timeouts += 1;
+                    meta.last_timeout_unconfirmed = true;
+                    (exit_kind, Some(("unconfirmed_timeouts", meta.unconfirmed_timeouts)))
+                }
+            };
+            if let Some((name, count)) = counter {
+                mgr.fire(
+                    state,
+                    libafl::events::Event::UpdateUserStats {
+                        name: name.to_string(),
+                        value: UserStats::new(
+                            UserStatsValue::Number(count),
+                            libafl::monitors::AggregatorOps::Sum,
+                        ),
+                        phantom: PhantomData,
+                    },
+                )?;
+            }
+            if confirmation == HangConfirmation::Unconfirmed(ExitKind::Timeout) {
+                // the partial coverage of the execution that timed out must not make the input a corpus entry
+                self.executor.observers_mut().pre_exec_all(state, input)?;
+            }
+            result = Ok(exit_kind);
+        }
+        if self.hang_timeout.is_some() && matches!(result, Ok(ExitKind::Ok)) {
+            if self.history.len() < CONVERSATION_MAX_LEN {
+                self.history.push(input.clone());
+            } else {
+                self.history_truncated = true;
+            }
+        }
+
+        // keep track of leftover processes, i.e., processes that survived killing the child
+        let leftovers = self.executor.forkserver_mut().take_leftover_processes();
+        if leftovers > 0 {
+            let meta = state.metadata_mut::<StatefulPersistentExecutorMeta>()?;
+            meta.leftover_processes += leftovers as u64;
+            let leftover_processes = meta.leftover_processes;
+            mgr.fire(
+                state,
+                libafl::events::Event::UpdateUserStats {
+                    name: "leftover_procs".to_string(),
+                    value: UserStats::new(
+                        UserStatsValue::Number(leftover_processes),
+                        libafl::monitors::AggregatorOps::Sum,
+                    ),
+                    phantom: PhantomData,
+                },
+            )?;
+        }
 
         if self.child_was_reset {
             // we communicated to the forkserver that the child was killed via
@@ -167,9 +452,6 @@ where
             self.state_reset_occurred = true;
 
             // keep track of timeouts
-            if !state.has_metadata::<StatefulPersistentExecutorMeta>() {
-                state.add_metadata(StatefulPersistentExecutorMeta { timeouts: 0 })
-            }
             let meta = state.metadata_mut::<StatefulPersistentExecutorMeta>()?;
 
             meta.increment_timeouts();
@@ -190,10 +472,95 @@ where
                 )?;
             }
         }
+
+        if let Ok(ExitKind::Oom) = result {
+            // the child is gone, so the prefix needs to be sent again
+            log::debug!("Target ran out of memory, resetting state");
+            self.state_reset_occurred = true;
+
+            let meta = state.metadata_mut::<StatefulPersistentExecutorMeta>()?;
+            meta.ooms += 1;
+            let ooms = meta.ooms;
+            mgr.fire(
+                state,
+                libafl::events::Event::UpdateUserStats {
+                    name: "ooms".to_string(),
+                    value: UserStats::new(
+                        UserStatsValue::Number(ooms),
+                        libafl::monitors::AggregatorOps::Sum,
+                    ),
+                    phantom: PhantomData,
+                },
+            )?;
+        }
         result
     }
 }
 
+impl<OT, S, SP> StatefulPersistentExecutor<OT, S, SP>
+where
+    OT: ObserversTuple<S>,
+    S: State + HasExecutions + HasMetadata,
+    S::Input: HasTargetBytes,
+    SP: ShMemProvider,
+{
+    /// Replay the history of the child that timed out to a new child, and run `input` again with `hang_timeout`.
+    ///
+    /// If the history cannot be replayed, the timeout cannot be confirmed. Then the execution still ends with
+    /// [`ExitKind::Timeout`], or with the exit kind of the replay if a message of the history crashed the new child.
+    fn confirm_hang<EM, Z>(
+        &mut self,
+        fuzzer: &mut Z,
+        state: &mut S,
+        mgr: &mut EM,
+        input: &S::Input,
+        hang_timeout: Duration,
+    ) -> Result<HangConfirmation, Error>
+    where
+        EM: UsesState<State = S> + EventFirer,
+        Z: UsesState<State = S>,
+    {
+        if self.history_truncated {
+            log::info!("Cannot confirm the timeout, the history of the child is truncated");
+            return Ok(HangConfirmation::Unconfirmed(ExitKind::Timeout));
+        }
+
+        let history = std::mem::take(&mut self.history);
+        for message in &history {
+            let exit_kind = self.executor.run_target(fuzzer, state, mgr, message)?;
+            if exit_kind != ExitKind::Ok {
+                log::info!(
+                    "Cannot confirm the timeout, replaying the history ended with {exit_kind:?}"
+                );
+                self.reset_target_state()?;
+                self.state_reset_occurred = true;
+                return Ok(HangConfirmation::Unconfirmed(exit_kind));
+            }
+        }
+        self.history = history;
+
+        // the coverage of the replayed history does not belong to the input
+        self.executor.observers_mut().pre_exec_all(state, input)?;
+        // a calibrated timeout of the target state may be longer than the hang timeout
+        let timeout = self.executor.timeout();
+        let hang_timeout = hang_timeout.max(timeout);
+        self.executor.set_timeout(hang_timeout);
+        let result = self.executor.run_target(fuzzer, state, mgr, input);
+        self.executor.set_timeout(timeout);
+
+        let exit_kind = result?;
+        if exit_kind == ExitKind::Timeout {
+            log::info!(
+                "Confirmed the timeout, the input also timed out after {} ms",
+                hang_timeout.as_millis()
+            );
+        } else {
+            log::info!("Flaky timeout, the input ended with {exit_kind:?} the second time");
+        }
+        Ok(HangConfirmation::Rerun(exit_kind))
+    }
+}
+
 impl<OT, S, SP> UsesObservers for StatefulPersistentExecutor<OT, S, SP>
 where
     OT: ObserversTuple<S>,
@@ -0,0 +1,210 @@
+//! Feedbacks for results that are kept apart from the crashes.
+//!
+//! The objective of the fuzzer decides what ends up in `crashes/`. Other noteworthy inputs, such as inputs that make the
+//! target run out of memory or hang, are stored by a [`SeparateSolutionsFeedback`] in their own directory. It is combined with
+//! the objective using `feedback_or!`, and never makes an input a solution itself.
+
+use std::path::PathBuf;
+
+use libafl::{
+    corpus::{Corpus, OnDiskCorpus, Testcase},
+    events::EventFirer,
+    executors::ExitKind,
+    feedbacks::Feedback,
+    inputs::Input,
+    observers::ObserversTuple,
+    state::{HasMetadata, State},
+    Error,
+};
+use libafl_bolts::Named;
+
+use crate::{executor::last_timeout_unconfirmed, state::MultipleStates};
+
+/// Is interesting if the target ran out of memory, i.e., the [`ExitKind`] is [`ExitKind::Oom`], and counts these
+/// executions for each target state, see [`MultipleStates::ooms`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OomFeedback;
+
+impl OomFeedback {
+    /// Create a new [`OomFeedback`]
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Named for OomFeedback {
+    fn name(&self) -> &str {
+        "OomFeedback"
+    }
+}
+
+impl<S> Feedback<S> for OomFeedback
+where
+    S: State + MultipleStates,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        if *exit_kind != ExitKind::Oom {
+            return Ok(false);
+        }
+        *state.ooms() += 1;
+        Ok(true)
+    }
+}
+
+/// Is interesting if the target hangs, i.e., the [`ExitKind`] is [`ExitKind::Timeout`] and the timeout was not left
+/// unconfirmed (see [`last_timeout_unconfirmed`]), and counts the stored hangs of each target state, see
+/// [`MultipleStates::hangs`].
+///
+/// It is opt-in: use it with an executor that confirms timeouts, see
+/// [`crate::executor::StatefulPersistentExecutor::with_hang_confirmation`], and disable it otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct HangFeedback {
+    enabled: bool,
+}
+
+impl HangFeedback {
+    /// Create a new [`HangFeedback`], it never finds anything interesting unless it is `enabled`
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+}
+
+impl Named for HangFeedback {
+    fn name(&self) -> &str {
+        "HangFeedback"
+    }
+}
+
+impl<S> Feedback<S> for HangFeedback
+where
+    S: State + MultipleStates + HasMetadata,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        _observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        Ok(self.enabled && *exit_kind == ExitKind::Timeout && !last_timeout_unconfirmed(state))
+    }
+
+    /// Only called for the hangs that are stored, not for those dropped by the deduplication
+    fn append_metadata<OT>(
+        &mut self,
+        state: &mut S,
+        _observers: &OT,
+        _testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        *state.hangs() += 1;
+        Ok(())
+    }
+}
+
+/// Stores the inputs the wrapped `feedback` finds interesting in a separate [`OnDiskCorpus`].
+///
+/// The wrapped feedback adds its metadata to the stored testcase as usual.
+/// This feedback itself never finds an input interesting, so it does not affect the corpus or the solutions.
+#[derive(Debug)]
+pub struct SeparateSolutionsFeedback<F, I> {
+    name: String,
+    feedback: F,
+    corpus: OnDiskCorpus<I>,
+}
+
+impl<F, I> SeparateSolutionsFeedback<F, I>
+where
+    I: Input,
+{
+    /// Create a new [`SeparateSolutionsFeedback`] storing the inputs `feedback` finds interesting in `dir`.
+    pub fn new(name: &str, feedback: F, dir: PathBuf) -> Result<Self, Error> {
+        Ok(Self {
+            name: name.to_string(),
+            feedback,
+            corpus: OnDiskCorpus::new(dir)?,
+        })
+    }
+
+    /// Number of inputs stored so far
+    pub fn count(&self) -> usize {
+        self.corpus.count()
+    }
+}
+
+impl<F, I> Named for SeparateSolutionsFeedback<F, I> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// The filename of a solution that is about to be added to an [`OnDiskCorpus`].
+///
+/// The corpus uses the filename of the testcase if it is set, so it is set here (if it is not yet) to know where the
+/// solution ends up. Feedbacks use this to store files next to the solution. `None` if the testcase has no input.
+pub fn solution_filename<I>(testcase: &mut Testcase<I>) -> Option<String>
+where
+    I: Input,
+{
+    if let Some(filename) = testcase.filename() {
+        return Some(filename.clone());
+    }
+    let filename = testcase.input().as_ref()?.generate_name(0);
+    *testcase.filename_mut() = Some(filename.clone());
+    Some(filename)
+}
+
+impl<F, S> Feedback<S> for SeparateSolutionsFeedback<F, S::Input>
+where
+    F: Feedback<S>,
+    S: State,
+{
+    fn init_state(&mut self, state: &mut S) -> Result<(), Error> {
+        self.feedback.init_state(state)
+    }
+
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        manager: &mut EM,
+        input: &S::Input,
+        observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        if !self
+            .feedback
+            .is_interesting(state, manager, input, observers, exit_kind)?
+        {
+            self.feedback.discard_metadata(state, input)?;
+            return Ok(false);
+        }
+
+        let mut testcase = Testcase::new(input.clone());
+        self.feedback
+            .append_metadata(state, observers, &mut testcase)?;
+        self.corpus.add(testcase)?;
+        Ok(false)
+    }
+}
@@ -35,6 +35,7 @@ use signal_hook::consts::TERM_SIGNALS;
 
 use crate::{
     executor::ResettableForkserver,
+    replay::TraceTargetState,
     state::{LibAFLStarState, MultipleStates, TargetStateIdx},
     state_scheduler::StateScheduler,
 };
@@ -70,6 +71,8 @@ where
 {
     // best overall coverage
     let mut best_edge_coverage: usize = 0;
+    // restores of the working directory of the target that were reported
+    let mut reported_restores: usize = 0;
 
     // setup signal handling:
     let quitting = Arc::new(AtomicBool::new(false));
@@ -228,6 +231,8 @@ where
             )?;
         }
 
+        report_workdir_restores(executor, state, manager, &mut reported_restores)?;
+
         // have we received a terminating signal?
         if quitting.load(Ordering::Relaxed) {
             break 'outer;
@@ -235,6 +240,7 @@ where
     }
 
     // update executions stats for all states to _ensure_ they're accurate at the end
+    report_workdir_restores(executor, state, manager, &mut reported_restores)?;
     state.for_each(|state| {
         manager.fire(
             state,
@@ -248,6 +254,37 @@ where
     Ok(())
 }
 
+/// Report how often the working directory of the target was restored, if it changed since the `reported` count.
+fn report_workdir_restores<E, EM>(
+    executor: &E,
+    state: &mut EM::State,
+    manager: &mut EM,
+    reported: &mut usize,
+) -> Result<(), Error>
+where
+    E: ResettableForkserver,
+    EM: ProgressReporter,
+{
+    let Some(restores) = executor.workdir_restores() else {
+        return Ok(());
+    };
+    if restores == *reported {
+        return Ok(());
+    }
+    *reported = restores;
+    manager.fire(
+        state,
+        UpdateUserStats {
+            name: "workdir_restores".to_string(),
+            value: UserStats::new(
+                UserStatsValue::Number(restores as u64),
+                libafl::monitors::AggregatorOps::Sum,
+            ),
+            phantom: PhantomData,
+        },
+    )
+}
+
 /// Change the state we are fuzzing.
 ///
 /// This is the core mechanic of the libaflstar fuzzer
@@ -267,6 +304,15 @@ where
     EM: ProgressReporter<State = Z::State>,
 {
     state.switch_state(new_state_id)?;
+    if let Some(timeout) = *state.timeout() {
+        executor.set_timeout(timeout);
+    }
+    let metadata = &state.prefix().metadata;
+    executor.target_state_changed(TraceTargetState {
+        idx: new_state_id.0,
+        name: metadata.name.clone(),
+        prefix_hash: metadata.prefix_hash,
+    });
     executor.reset_target_state()?;
     send_prefix(fuzzer, executor, state, manager)?;
     Ok(())
@@ -1,14 +1,30 @@
 //! LibAFLstar, an extension of LibAFL to fuzz stateful targets, primarily via sockets.
 
+pub mod calibration;
+pub mod child_output;
+pub mod crash_dedup;
+pub mod crash_record;
+pub mod differential;
+pub mod drift;
 pub mod event_manager;
 pub mod executor;
+pub mod feedbacks;
 pub mod fuzzer;
+pub mod minimize;
 pub mod mutator;
+pub mod oracle;
+pub mod pcap;
+pub mod port;
 pub mod http_mutator;
+pub mod log_monitor;
 pub mod rtsp_mutator;
 pub mod replay;
+pub mod response;
+pub mod stability;
 pub mod state;
 pub mod state_scheduler;
+pub mod verify;
+pub mod workdir;
 
 pub mod perf;
 
@@ -0,0 +1,407 @@
+//! Monitoring of the log files of the target.
+//!
+//! Many servers (e.g. proftpd, lighttpd) report assertion failures, internal errors and "should not happen" conditions
+//! only in their log files, not in their responses and without crashing. The [`LogFileObserver`] tails the log files
+//! during each execution and keeps the lines that were added, and the [`LogPatternFeedback`] checks them against
+//! [`LogPatterns`]. Like the oracles in [`crate::oracle`], its findings are stored in `findings/`, with the new log lines
+//! next to them as `<finding>.log`. The fuzzers set both up from their [`LogMonitorArgs`].
+
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use libafl::{
+    corpus::Testcase,
+    events::EventFirer,
+    executors::ExitKind,
+    feedbacks::Feedback,
+    inputs::{HasTargetBytes, UsesInput},
+    observers::{Observer, ObserversTuple},
+    state::{HasExecutions, HasMetadata, State},
+    Error,
+};
+use clap::Args;
+use libafl_bolts::{impl_serdeany, tuples::MatchName, Named};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crash_record::Conversation, feedbacks::solution_filename, oracle::store_finding,
+    state::MultipleStates,
+};
+
+/// Maximum number of bytes read from a log file per execution, a target that floods its log is cut off.
+pub const LOG_READ_MAX: u64 = 64 * 1024;
+
+/// Keeps the lines that were added to the log files of the target during the last execution.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogFileObserver {
+    name: String,
+    paths: Vec<PathBuf>,
+    /// The length of each log file before the execution
+    #[serde(skip)]
+    offsets: Vec<u64>,
+    /// The new lines, prefixed by the name of their log file
+    lines: Vec<String>,
+}
+
+impl LogFileObserver {
+    /// Create a new [`LogFileObserver`] tailing the log files at `paths`, they do not have to exist yet.
+    pub fn new(name: &str, paths: Vec<PathBuf>) -> Self {
+        Self {
+            name: name.to_string(),
+            offsets: vec![0; paths.len()],
+            paths,
+            lines: Vec::new(),
+        }
+    }
+
+    /// The lines that were added to the log files during the last execution.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Read what was added to the log file at `path` since `offset`, and move `offset` to its end.
+    fn read_new(path: &Path, offset: &mut u64) -> Result<Vec<u8>, Error> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                *offset = 0;
+                return Ok(Vec::new());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // the log was truncated or rotated, start over
+        if file.metadata()?.len() < *offset {
+            *offset = 0;
+        }
+        file.seek(SeekFrom::Start(*offset))?;
+        let mut new = Vec::new();
+        file.take(LOG_READ_MAX).read_to_end(&mut new)?;
+        *offset += new.len() as u64;
+        Ok(new)
+    }
+}
+
+impl Named for LogFileObserver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Observer<S> for LogFileObserver
+where
+    S: UsesInput,
+{
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.lines.clear();
+        // only what is logged during the execution belongs to it
+        self.offsets.resize(self.paths.len(), 0);
+        for (path, offset) in self.paths.iter().zip(self.offsets.iter_mut()) {
+            *offset = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        }
+        Ok(())
+    }
+
+    fn post_exec(
+        &mut self,
+        _state: &mut S,
+        _input: &S::Input,
+        _exit_kind: &ExitKind,
+    ) -> Result<(), Error> {
+        for (path, offset) in self.paths.iter().zip(self.offsets.iter_mut()) {
+            let new = Self::read_new(path, offset)?;
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            self.lines.extend(
+                String::from_utf8_lossy(&new)
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| format!("{file_name}: {line}")),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A pattern of log lines that reveal an anomaly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogPattern {
+    /// The name of the pattern, it is stored with the findings
+    pub name: String,
+    /// Regex a log line must match
+    pub regex: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PatternFile {
+    patterns: Vec<LogPattern>,
+}
+
+/// The [`LogPattern`]s of a target, read from a JSON file, e.g.
+///
+/// ```json
+/// {"patterns": [{"name": "assertion", "regex": "(?i)assertion .* failed"}, {"name": "fatal", "regex": "FATAL"}]}
+/// ```
+#[derive(Debug, Clone)]
+pub struct LogPatterns {
+    patterns: Vec<(String, Regex)>,
+}
+
+impl Default for LogPatterns {
+    /// Assertion failures, internal errors, "should not happen" conditions and fatal errors.
+    ///
+    /// Words like "aborted" or "unreachable" alone are no anomaly, servers log them for aborted transfers or unreachable
+    /// clients, so only the phrases that name a bug of the target match.
+    fn default() -> Self {
+        let defaults = [
+            ("assertion", r"(?i)assert(ion)?\b.*fail"),
+            (
+                "should-not-happen",
+                r"(?i)(should|must|can) ?not happen|shouldn't happen|(entered|reached) unreachable (code|state)",
+            ),
+            ("internal-error", r"(?i)internal (server )?error"),
+            ("fatal", r"(?i)\bfatal error\b|\bpanicked at\b|\bpanic: |\bcore dumped\b"),
+        ];
+        Self {
+            patterns: defaults
+                .into_iter()
+                .map(|(name, regex)| (name.to_string(), Regex::new(regex).unwrap()))
+                .collect(),
+        }
+    }
+}
+
+impl LogPatterns {
+    /// Compile `patterns`, fails if a regex is invalid.
+    pub fn new(patterns: Vec<LogPattern>) -> Result<Self, Error> {
+        Ok(Self {
+            patterns: patterns
+                .into_iter()
+                .map(|pattern| {
+                    let regex = Regex::new(&pattern.regex).map_err(|e| {
+                        Error::illegal_argument(format!(
+                            "Invalid regex in log pattern {}: {e}",
+                            pattern.name
+                        ))
+                    })?;
+                    Ok((pattern.name, regex))
+                })
+                .collect::<Result<_, Error>>()?,
+        })
+    }
+
+    /// Read the patterns from a JSON file.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+        let file: PatternFile = serde_json::from_str(&content).map_err(|e| {
+            Error::illegal_argument(format!(
+                "Could not parse log patterns {}: {e}",
+                path.display()
+            ))
+        })?;
+        Self::new(file.patterns)
+    }
+
+    /// The names of the patterns that `lines` match.
+    pub fn matching(&self, lines: &[String]) -> Vec<String> {
+        self.patterns
+            .iter()
+            .filter(|(_, regex)| lines.iter().any(|line| regex.is_match(line)))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
+
+/// The command line arguments of the log monitoring, they are flattened into the command line of the fuzzers.
+#[derive(Debug, Clone, Args)]
+pub struct LogMonitorArgs {
+    #[arg(
+        help = "Log file of the target that is tailed during each execution, can be given several times. Lines matching --log-patterns make the input a finding, stored in findings/",
+        long = "log-file"
+    )]
+    pub log_files: Option<Vec<PathBuf>>,
+
+    #[arg(
+        help = "JSON file with the patterns of log lines that reveal anomalies (see src/log_monitor.rs), by default assertion failures, internal errors, \"should not happen\" conditions and fatal errors",
+        long = "log-patterns"
+    )]
+    pub log_patterns: Option<PathBuf>,
+}
+
+impl LogMonitorArgs {
+    /// Create the [`LogFileObserver`] that tails the log files, and the [`LogPatternFeedback`] that checks its lines
+    /// against the patterns, with `conversation` and `findings_dir` as in [`LogPatternFeedback::new`].
+    ///
+    /// Without `--log-file` the observer tails nothing, so nothing is flagged.
+    pub fn create(
+        &self,
+        conversation: Conversation,
+        findings_dir: PathBuf,
+    ) -> Result<(LogFileObserver, LogPatternFeedback), Error> {
+        let observer = LogFileObserver::new("log_files", self.log_files.clone().unwrap_or_default());
+        let patterns = match &self.log_patterns {
+            Some(path) => LogPatterns::from_file(path)?,
+            None => LogPatterns::default(),
+        };
+        let feedback = LogPatternFeedback::new(&observer, patterns, conversation, findings_dir);
+        Ok((observer, feedback))
+    }
+}
+
+/// Metadata added to a finding by the [`LogPatternFeedback`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogLinesMetadata {
+    /// The lines that were added to the log files during the execution
+    pub lines: Vec<String>,
+}
+
+impl_serdeany!(LogLinesMetadata);
+
+/// Is interesting if a line that was added to the log files during the execution matches one of the [`LogPatterns`].
+///
+/// Stores the new log lines next to the finding, as `<finding>.log`, and why it was flagged and the conversation like
+/// the oracles in [`crate::oracle`].
+#[derive(Debug)]
+pub struct LogPatternFeedback {
+    name: String,
+    observer_name: String,
+    patterns: LogPatterns,
+    conversation: Conversation,
+    findings_dir: PathBuf,
+    /// The patterns the log lines of the last input matched
+    matched: Vec<String>,
+}
+
+impl LogPatternFeedback {
+    /// Create a new [`LogPatternFeedback`] for the `observer`, where `conversation` is the conversation that is
+    /// given to the executor and `findings_dir` the directory the findings are stored in (e.g. `findings/`).
+    pub fn new(
+        observer: &LogFileObserver,
+        patterns: LogPatterns,
+        conversation: Conversation,
+        findings_dir: PathBuf,
+    ) -> Self {
+        Self {
+            name: format!("{}_pattern_feedback", observer.name()),
+            observer_name: observer.name().to_string(),
+            patterns,
+            conversation,
+            findings_dir,
+            matched: Vec::new(),
+        }
+    }
+}
+
+impl Named for LogPatternFeedback {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Feedback<S> for LogPatternFeedback
+where
+    S: State + MultipleStates + HasExecutions,
+    S::Input: HasTargetBytes,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &S::Input,
+        observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        self.matched = observers
+            .match_name::<LogFileObserver>(&self.observer_name)
+            .map(|observer| self.patterns.matching(observer.lines()))
+            .unwrap_or_default();
+        Ok(!self.matched.is_empty())
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        state: &mut S,
+        observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        let patterns = std::mem::take(&mut self.matched);
+        if patterns.is_empty() {
+            return Ok(());
+        }
+        let lines = observers
+            .match_name::<LogFileObserver>(&self.observer_name)
+            .map(|observer| observer.lines().to_vec())
+            .unwrap_or_default();
+        let Some(filename) = solution_filename(testcase) else {
+            return Ok(());
+        };
+
+        fs::create_dir_all(&self.findings_dir)?;
+        fs::write(
+            self.findings_dir.join(format!("{filename}.log")),
+            lines.join("\n") + "\n",
+        )?;
+        store_finding(
+            state,
+            &self.conversation,
+            &self.findings_dir,
+            testcase,
+            patterns.iter().map(|name| format!("log: {name}")).collect(),
+            None,
+        )?;
+        testcase.add_metadata(LogLinesMetadata { lines });
+        Ok(())
+    }
+
+    fn discard_metadata(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.matched.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogPatterns;
+
+    fn matching(line: &str) -> Vec<String> {
+        LogPatterns::default().matching(&[format!("proftpd.log: {line}")])
+    }
+
+    #[test]
+    fn default_patterns_match_bugs() {
+        assert_eq!(
+            matching("mod_ls.c:421: assertion `p != NULL' failed"),
+            ["assertion"]
+        );
+        assert_eq!(
+            matching("this should not happen: session is NULL"),
+            ["should-not-happen"]
+        );
+        assert_eq!(
+            matching("thread 'main' panicked at src/main.rs:3:5"),
+            ["fatal"]
+        );
+        assert_eq!(matching("500 Internal Server Error"), ["internal-error"]);
+        assert_eq!(
+            matching("child 1234 exited: Aborted (core dumped)"),
+            ["fatal"]
+        );
+    }
+
+    #[test]
+    fn default_patterns_ignore_normal_operation() {
+        assert!(matching("Transfer aborted by client").is_empty());
+        assert!(matching("Network is unreachable").is_empty());
+        assert!(matching("ABOR command received, aborting transfer").is_empty());
+        assert!(matching("FTP session closed.").is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+//! Minimize a sequence of messages that makes the target crash, using delta debugging (ddmin).
+//!
+//! The sequence is first minimized over whole messages, then over the bytes of each remaining message.
+//! Each candidate is replayed to a freshly reset target, see [`crate::verify::replay_messages`], and is kept if the
+//! target still ends with the same exit kind.
+
+use libafl::{
+    executors::{Executor, ExitKind},
+    inputs::{BytesInput, UsesInput},
+    state::UsesState,
+    Error,
+};
+
+use crate::{executor::ResettableForkserver, replay::exit_kind_str, verify::replay_messages};
+
+/// Delta debugging: find a 1-minimal subsequence of `items` for which `test` still returns `true`.
+///
+/// `test(items)` is assumed to return `true`.
+pub fn ddmin<T, F>(mut items: Vec<T>, mut test: F) -> Result<Vec<T>, Error>
+where
+    T: Clone,
+    F: FnMut(&[T]) -> Result<bool, Error>,
+{
+    let mut n = 2;
+    while items.len() >= 2 {
+        let chunk_len = items.len().div_ceil(n);
+        let chunks: Vec<(usize, usize)> = (0..items.len())
+            .step_by(chunk_len)
+            .map(|start| (start, (start + chunk_len).min(items.len())))
+            .collect();
+
+        // reduce to a single chunk
+        let mut reduced = None;
+        for &(start, end) in &chunks {
+            let subset = items[start..end].to_vec();
+            if test(&subset)? {
+                reduced = Some((subset, 2));
+                break;
+            }
+        }
+
+        // reduce to the complement of a chunk, with two chunks these are the chunks themselves
+        if reduced.is_none() && chunks.len() > 2 {
+            for &(start, end) in &chunks {
+                let complement: Vec<T> = items[..start]
+                    .iter()
+                    .chain(&items[end..])
+                    .cloned()
+                    .collect();
+                if test(&complement)? {
+                    reduced = Some((complement, (n - 1).max(2)));
+                    break;
+                }
+            }
+        }
+
+        match reduced {
+            Some((smaller, new_n)) => {
+                items = smaller;
+                n = new_n;
+            }
+            None if n >= items.len() => break,
+            None => n = (n * 2).min(items.len()),
+        }
+    }
+    Ok(items)
+}
+
+/// Minimizes message sequences by replaying them to the target.
+#[derive(Debug)]
+pub struct TraceMinimizer {
+    /// The exit kind the sequence should end with, see [`exit_kind_str`]
+    expected: String,
+    /// A candidate still crashes if it does so in at least one of this many attempts
+    attempts: usize,
+    /// Number of candidates replayed so far
+    tests: usize,
+}
+
+impl TraceMinimizer {
+    /// Create a new [`TraceMinimizer`] keeping candidates that end with `expected` (e.g. `Cr`) in at least one of
+    /// `attempts` replays.
+    pub fn new(expected: &str, attempts: usize) -> Self {
+        Self {
+            expected: expected.to_string(),
+            attempts: attempts.max(1),
+            tests: 0,
+        }
+    }
+
+    /// Number of candidates replayed so far
+    pub fn tests(&self) -> usize {
+        self.tests
+    }
+
+    /// Replay `messages` and return the number of messages up to and including the one that ended with the expected
+    /// exit kind, if any.
+    pub fn crashes_after<E, EM, Z>(
+        &mut self,
+        executor: &mut E,
+        fuzzer: &mut Z,
+        state: &mut E::State,
+        mgr: &mut EM,
+        messages: &[Vec<u8>],
+    ) -> Result<Option<usize>, Error>
+    where
+        E: Executor<EM, Z> + ResettableForkserver + UsesInput<Input = BytesInput>,
+        EM: UsesState<State = E::State>,
+        Z: UsesState<State = E::State>,
+    {
+        self.tests += 1;
+        for _ in 0..self.attempts {
+            let (exit_kind, idx) = replay_messages(executor, fuzzer, state, mgr, messages)?;
+            if exit_kind != ExitKind::Ok && exit_kind_str(exit_kind) == self.expected {
+                return Ok(Some(idx + 1));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Minimize `messages`, first over whole messages and then over the bytes within each message.
+    ///
+    /// # Errors:
+    ///
+    /// - `messages` does not end with the expected exit kind to begin with
+    pub fn minimize<E, EM, Z>(
+        &mut self,
+        executor: &mut E,
+        fuzzer: &mut Z,
+        state: &mut E::State,
+        mgr: &mut EM,
+        messages: Vec<Vec<u8>>,
+    ) -> Result<Vec<Vec<u8>>, Error>
+    where
+        E: Executor<EM, Z> + ResettableForkserver + UsesInput<Input = BytesInput>,
+        EM: UsesState<State = E::State>,
+        Z: UsesState<State = E::State>,
+    {
+        let Some(len) = self.crashes_after(executor, fuzzer, state, mgr, &messages)? else {
+            return Err(Error::illegal_argument(format!(
+                "The trace does not end with {} when it is replayed",
+                self.expected
+            )));
+        };
+        let mut messages = messages;
+        messages.truncate(len);
+        log::info!("Minimizing {} messages", messages.len());
+
+        messages = ddmin(messages, |candidate| {
+            Ok(self
+                .crashes_after(executor, fuzzer, state, mgr, candidate)?
+                .is_some())
+        })?;
+        log::info!(
+            "Minimized to {} messages after {} tests",
+            messages.len(),
+            self.tests
+        );
+
+        for idx in 0..messages.len() {
+            let bytes = std::mem::take(&mut messages[idx]);
+            let minimized = ddmin(bytes, |candidate| {
+                let mut with_candidate = messages.clone();
+                with_candidate[idx] = candidate.to_vec();
+                Ok(self
+                    .crashes_after(executor, fuzzer, state, mgr, &with_candidate)?
+                    .is_some())
+            })?;
+            messages[idx] = minimized;
+        }
+
+        // the target may now give up before the last message
+        if let Some(len) = self.crashes_after(executor, fuzzer, state, mgr, &messages)? {
+            messages.truncate(len);
+        }
+        log::info!(
+            "Minimized to {} messages of {} bytes in total after {} tests",
+            messages.len(),
+            messages.iter().map(Vec::len).sum::<usize>(),
+            self.tests
+        );
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libafl::Error;
+
+    use super::ddmin;
+
+    #[test]
+    fn keeps_the_needed_items() {
+        let minimized = ddmin((0..16).collect(), |items| {
+            Ok(items.contains(&3) && items.contains(&11))
+        });
+        assert_eq!(minimized.unwrap(), [3, 11]);
+        let minimized = ddmin((0..16).collect(), |items| Ok(items.contains(&7)));
+        assert_eq!(minimized.unwrap(), [7]);
+    }
+
+    #[test]
+    fn keeps_everything_if_everything_is_needed() {
+        let minimized = ddmin((0..8).collect(), |items| Ok(items.len() == 8));
+        assert_eq!(minimized.unwrap(), (0..8).collect::<Vec<_>>());
+        let minimized = ddmin(vec![1], |_| Ok(true));
+        assert_eq!(minimized.unwrap(), [1]);
+    }
+
+    #[test]
+    fn minimizes_bytes() {
+        let minimized = ddmin(b"USER ftp\r\nSITE CRASH\r\n".to_vec(), |bytes| {
+            Ok(bytes.windows(5).any(|window| window == b"CRASH"))
+        });
+        assert_eq!(minimized.unwrap(), b"CRASH");
+    }
+
+    #[test]
+    fn stops_on_errors() {
+        let minimized = ddmin((0..4).collect::<Vec<u8>>(), |_| {
+            Err(Error::illegal_state("target is gone"))
+        });
+        assert!(minimized.is_err());
+    }
+}
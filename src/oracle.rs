@@ -0,0 +1,806 @@
+//! Oracles for logic bugs that show up in the responses of the target, rather than as crashes.
+//!
+//! Examples are a `230` login success after wrong credentials, `500` internal errors, directory listings outside the
+//! root or leaked memory in a response. They are described by [`ResponseRule`]s in a JSON file, e.g.
+//!
+//! ```json
+//! {"rules": [
+//!     {"name": "login-with-wrong-password", "states": ["USER"], "request": "(?i)^PASS wrong", "codes": [230]},
+//!     {"name": "internal-error", "codes": [500]},
+//!     {"name": "listing-outside-root", "response": "(?m)^(bin|etc|proc)$"}
+//! ]}
+//! ```
+//!
+//! The [`ResponseOracleFeedback`] checks the response kept by a [`ResponseObserver`] against the rules of the current
+//! target state.
+//!
+//! The [`AuthBypassFeedback`] looks for a common bug of protocols with a login: a command that needs authentication
+//! succeeds while the session is not authenticated. It uses the tags of the target states (see
+//! [`crate::state::PrefixMetadata::tags`]), e.g. `authenticated=false` for `0_initial` and `1_username`, and a list of
+//! privileged commands with their success codes, see [`AuthBypassRules`].
+//!
+//! Both are combined with a [`crate::feedbacks::SeparateSolutionsFeedback`], so the findings are stored apart from the
+//! crashes, in `findings/`, together with why they were flagged and the conversation that led to them.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use libafl::{
+    corpus::Testcase,
+    events::EventFirer,
+    executors::ExitKind,
+    feedbacks::Feedback,
+    inputs::HasTargetBytes,
+    observers::ObserversTuple,
+    state::{HasExecutions, HasMetadata, State},
+    Error,
+};
+use libafl_bolts::{impl_serdeany, tuples::MatchName, AsSlice, Named};
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crash_record::{Conversation, ConversationSnapshot, CrashRecord, CRASH_RECORD_SUFFIX},
+    feedbacks::solution_filename,
+    response::{status_code, ResponseObserver},
+    state::MultipleStates,
+};
+
+/// A rule describing a response that reveals a bug. All conditions that are given must hold, at least one must be given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseRule {
+    /// The name of the rule, it is stored with the findings
+    pub name: String,
+    /// The target states the rule applies to, by index or name of their prefix. Empty means all target states
+    #[serde(default)]
+    pub states: Vec<String>,
+    /// Regex the request must match
+    #[serde(default)]
+    pub request: Option<String>,
+    /// Regex the response must match
+    #[serde(default)]
+    pub response: Option<String>,
+    /// Status codes (see [`status_code`]) that reveal a bug
+    #[serde(default)]
+    pub codes: Vec<u16>,
+    /// Status codes that are expected, any other status code reveals a bug
+    #[serde(default)]
+    pub expected_codes: Vec<u16>,
+}
+
+/// A [`ResponseRule`] with its regexes compiled.
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    rule: ResponseRule,
+    request: Option<Regex>,
+    response: Option<Regex>,
+}
+
+impl CompiledRule {
+    fn new(rule: ResponseRule) -> Result<Self, Error> {
+        let compile = |pattern: &Option<String>| {
+            pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| {
+                    Error::illegal_argument(format!("Invalid regex in rule {}: {e}", rule.name))
+                })
+        };
+        let request = compile(&rule.request)?;
+        let response = compile(&rule.response)?;
+        if request.is_none()
+            && response.is_none()
+            && rule.codes.is_empty()
+            && rule.expected_codes.is_empty()
+        {
+            return Err(Error::illegal_argument(format!(
+                "Rule {} has no condition",
+                rule.name
+            )));
+        }
+        Ok(Self {
+            rule,
+            request,
+            response,
+        })
+    }
+
+    fn applies_to(&self, state_idx: usize, state_name: &str) -> bool {
+        self.rule.states.is_empty()
+            || self
+                .rule
+                .states
+                .iter()
+                .any(|state| state == state_name || state.parse() == Ok(state_idx))
+    }
+
+    fn matches(&self, request: &[u8], response: Option<&[u8]>) -> bool {
+        if let Some(regex) = &self.request {
+            if !regex.is_match(request) {
+                return false;
+            }
+        }
+        if let Some(regex) = &self.response {
+            if !response.is_some_and(|response| regex.is_match(response)) {
+                return false;
+            }
+        }
+        let code = response.and_then(status_code);
+        if !self.rule.codes.is_empty()
+            && !code.is_some_and(|code| self.rule.codes.contains(&code))
+        {
+            return false;
+        }
+        if !self.rule.expected_codes.is_empty()
+            && !code.is_some_and(|code| !self.rule.expected_codes.contains(&code))
+        {
+            return false;
+        }
+        true
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleFile {
+    rules: Vec<ResponseRule>,
+}
+
+/// The [`ResponseRule`]s of a target.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseRules {
+    rules: Vec<CompiledRule>,
+}
+
+impl ResponseRules {
+    /// Compile `rules`, fails if a regex is invalid or a rule has no condition.
+    pub fn new(rules: Vec<ResponseRule>) -> Result<Self, Error> {
+        Ok(Self {
+            rules: rules
+                .into_iter()
+                .map(CompiledRule::new)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Read the rules from a JSON file, see the [module documentation](self).
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+        let file: RuleFile = serde_json::from_str(&content).map_err(|e| {
+            Error::illegal_argument(format!(
+                "Could not parse response rules {}: {e}",
+                path.display()
+            ))
+        })?;
+        Self::new(file.rules)
+    }
+
+    /// If there are no rules
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The names of the rules of the target state that `request` and `response` match.
+    pub fn matching(
+        &self,
+        state_idx: usize,
+        state_name: &str,
+        request: &[u8],
+        response: Option<&[u8]>,
+    ) -> Vec<String> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.applies_to(state_idx, state_name))
+            .filter(|rule| rule.matches(request, response))
+            .map(|rule| rule.rule.name.clone())
+            .collect()
+    }
+}
+
+/// Metadata added to a finding by the oracles.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FindingMetadata {
+    /// The names of the matching rules
+    pub rules: Vec<String>,
+}
+
+impl_serdeany!(FindingMetadata);
+
+/// Store a finding of an oracle: append why it was flagged to `<finding>.finding.txt`, so the reasons of several
+/// oracles add up, and write the conversation that led to it to `<finding>.record.cbor`.
+pub(crate) fn store_finding<S>(
+    state: &S,
+    conversation: &Conversation,
+    findings_dir: &Path,
+    testcase: &mut Testcase<S::Input>,
+    rules: Vec<String>,
+    response: Option<&[u8]>,
+) -> Result<(), Error>
+where
+    S: State + MultipleStates + HasExecutions,
+    S::Input: HasTargetBytes,
+{
+    let Some(filename) = solution_filename(testcase) else {
+        return Ok(());
+    };
+
+    let (messages, truncated, signal) = conversation.snapshot();
+    let record = CrashRecord::new(
+        state,
+        &ConversationSnapshot {
+            messages,
+            truncated,
+            signal,
+            exit_kind: ExitKind::Ok,
+        },
+    );
+
+    fs::create_dir_all(findings_dir)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(findings_dir.join(format!("{filename}.finding.txt")))?;
+    write!(
+        file,
+        "rules: {}\ntarget state: {} ({})\nrequest: b\"{}\"\n",
+        rules.join(", "),
+        record.state_idx,
+        record.state_name,
+        record.input.escape_ascii(),
+    )?;
+    if let Some(response) = response {
+        writeln!(file, "response: b\"{}\"", response.escape_ascii())?;
+    }
+    record.to_file(&findings_dir.join(format!("{filename}{CRASH_RECORD_SUFFIX}")))?;
+    log::info!(
+        "Finding {filename} in target state {} ({}): {}",
+        record.state_idx,
+        record.state_name,
+        rules.join(", ")
+    );
+
+    match testcase.metadata_map_mut().get_mut::<FindingMetadata>() {
+        Some(metadata) => metadata.rules.extend(rules),
+        None => testcase.add_metadata(FindingMetadata { rules }),
+    }
+    Ok(())
+}
+
+/// Is interesting if the response kept by the [`ResponseObserver`] matches a [`ResponseRule`] of the current target
+/// state.
+///
+/// Stores the matching rules, the request and the response next to the finding, as `<finding>.finding.txt`, and the
+/// conversation as a [`CrashRecord`], as `<finding>.record.cbor`. The execution ended well, so the conversation is taken
+/// from the [`Conversation`] itself rather than from a [`crate::crash_record::ConversationObserver`].
+#[derive(Debug)]
+pub struct ResponseOracleFeedback {
+    name: String,
+    observer_name: String,
+    rules: ResponseRules,
+    conversation: Conversation,
+    findings_dir: PathBuf,
+    /// The rules the last input matched
+    matched: Vec<String>,
+}
+
+impl ResponseOracleFeedback {
+    /// Create a new [`ResponseOracleFeedback`] for the `observer`, where `conversation` is the conversation that is
+    /// given to the executor and `findings_dir` the directory the findings are stored in (e.g. `findings/`).
+    pub fn new(
+        observer: &ResponseObserver,
+        rules: ResponseRules,
+        conversation: Conversation,
+        findings_dir: PathBuf,
+    ) -> Self {
+        Self {
+            name: format!("{}_oracle_feedback", observer.name()),
+            observer_name: observer.name().to_string(),
+            rules,
+            conversation,
+            findings_dir,
+            matched: Vec::new(),
+        }
+    }
+}
+
+impl Named for ResponseOracleFeedback {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Feedback<S> for ResponseOracleFeedback
+where
+    S: State + MultipleStates + HasExecutions,
+    S::Input: HasTargetBytes,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        input: &S::Input,
+        observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        self.matched.clear();
+        if self.rules.is_empty() {
+            return Ok(false);
+        }
+        let response = observers
+            .match_name::<ResponseObserver>(&self.observer_name)
+            .and_then(ResponseObserver::response);
+        self.matched = self.rules.matching(
+            state.current_state_idx().0,
+            &state.prefix().metadata.name,
+            input.target_bytes().as_slice(),
+            response,
+        );
+        Ok(!self.matched.is_empty())
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        state: &mut S,
+        observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        let rules = std::mem::take(&mut self.matched);
+        if rules.is_empty() {
+            return Ok(());
+        }
+        let response = observers
+            .match_name::<ResponseObserver>(&self.observer_name)
+            .and_then(ResponseObserver::response)
+            .unwrap_or_default();
+        store_finding(
+            state,
+            &self.conversation,
+            &self.findings_dir,
+            testcase,
+            rules,
+            Some(response),
+        )
+    }
+
+    fn discard_metadata(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.matched.clear();
+        Ok(())
+    }
+}
+
+/// A command that needs authentication.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrivilegedCommand {
+    /// The start of the request, e.g. `RETR` or `DELETE /admin`, compared case-insensitively
+    pub command: String,
+    /// The status codes of a successful reply, e.g. `[150, 226]` for `RETR`
+    pub success_codes: Vec<u16>,
+}
+
+/// The privileged commands of a protocol, read from a JSON file, e.g.
+///
+/// ```json
+/// {"tag": "authenticated", "login_commands": ["PASS"],
+///  "commands": [{"command": "RETR", "success_codes": [150, 226]}, {"command": "DELE", "success_codes": [250]}]}
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthBypassRules {
+    /// The tag of the target states that tells if the session is authenticated, only target states where it is
+    /// `false` are checked
+    pub tag: String,
+    /// The commands that need authentication
+    pub commands: Vec<PrivilegedCommand>,
+    /// Commands that may authenticate the session, e.g. `PASS`. Once one of them was sent after the prefix, the
+    /// session is no longer known to be unauthenticated
+    pub login_commands: Vec<String>,
+}
+
+impl Default for AuthBypassRules {
+    fn default() -> Self {
+        Self {
+            tag: "authenticated".to_string(),
+            commands: Vec::new(),
+            login_commands: Vec::new(),
+        }
+    }
+}
+
+/// If the first line of `request` starts with `command`, followed by the end of the line or something other than a
+/// letter or digit.
+fn starts_with_command(request: &[u8], command: &str) -> bool {
+    let command = command.as_bytes();
+    request.len() >= command.len()
+        && request[..command.len()].eq_ignore_ascii_case(command)
+        && request
+            .get(command.len())
+            .map_or(true, |next| !next.is_ascii_alphanumeric())
+}
+
+impl AuthBypassRules {
+    /// Read the rules from a JSON file.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|e| {
+            Error::illegal_argument(format!(
+                "Could not parse authentication rules {}: {e}",
+                path.display()
+            ))
+        })
+    }
+
+    /// If the target state with `tags` is unauthenticated.
+    pub fn is_unauthenticated(&self, tags: &BTreeMap<String, String>) -> bool {
+        tags.get(&self.tag)
+            .is_some_and(|value| value.eq_ignore_ascii_case("false"))
+    }
+
+    /// The privileged command `request` succeeded with, according to the status code of `response`.
+    pub fn succeeded(&self, request: &[u8], response: Option<&[u8]>) -> Option<&PrivilegedCommand> {
+        let code = response.and_then(status_code)?;
+        self.commands.iter().find(|command| {
+            starts_with_command(request, &command.command) && command.success_codes.contains(&code)
+        })
+    }
+
+    /// If one of `messages` may have authenticated the session.
+    pub fn may_have_logged_in<'a>(&self, mut messages: impl Iterator<Item = &'a Vec<u8>>) -> bool {
+        messages.any(|message| {
+            self.login_commands
+                .iter()
+                .any(|command| starts_with_command(message, command))
+        })
+    }
+}
+
+/// Is interesting if a privileged command succeeded in an unauthenticated target state, see [`AuthBypassRules`].
+///
+/// Target states are unauthenticated according to their tags. The inputs before this one may have logged in, so the
+/// conversation since the prefix is checked for login commands before the input is flagged. Like the
+/// [`ResponseOracleFeedback`], it stores its findings with `<finding>.finding.txt` and `<finding>.record.cbor`.
+#[derive(Debug)]
+pub struct AuthBypassFeedback {
+    name: String,
+    observer_name: String,
+    rules: AuthBypassRules,
+    conversation: Conversation,
+    findings_dir: PathBuf,
+    /// The privileged command of the last input, if it bypassed the authentication
+    bypassed: Option<String>,
+}
+
+impl AuthBypassFeedback {
+    /// Create a new [`AuthBypassFeedback`] for the `observer`, where `conversation` is the conversation that is
+    /// given to the executor and `findings_dir` the directory the findings are stored in (e.g. `findings/`).
+    pub fn new(
+        observer: &ResponseObserver,
+        rules: AuthBypassRules,
+        conversation: Conversation,
+        findings_dir: PathBuf,
+    ) -> Self {
+        Self {
+            name: format!("{}_auth_bypass_feedback", observer.name()),
+            observer_name: observer.name().to_string(),
+            rules,
+            conversation,
+            findings_dir,
+            bypassed: None,
+        }
+    }
+}
+
+impl Named for AuthBypassFeedback {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Feedback<S> for AuthBypassFeedback
+where
+    S: State + MultipleStates + HasExecutions,
+    S::Input: HasTargetBytes,
+{
+    fn is_interesting<EM, OT>(
+        &mut self,
+        state: &mut S,
+        _manager: &mut EM,
+        input: &S::Input,
+        observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer<State = S>,
+        OT: ObserversTuple<S>,
+    {
+        self.bypassed = None;
+        if self.rules.commands.is_empty()
+            || !self.rules.is_unauthenticated(&state.prefix().metadata.tags)
+        {
+            return Ok(false);
+        }
+        let response = observers
+            .match_name::<ResponseObserver>(&self.observer_name)
+            .and_then(ResponseObserver::response);
+        let Some(command) = self
+            .rules
+            .succeeded(input.target_bytes().as_slice(), response)
+        else {
+            return Ok(false);
+        };
+
+        // the messages sent after the prefix, without this input, may have logged in
+        let (messages, _, _) = self.conversation.snapshot();
+        let after_prefix = messages
+            .iter()
+            .skip(state.prefix().prefix.len())
+            .take(messages.len().saturating_sub(state.prefix().prefix.len() + 1));
+        if self.rules.may_have_logged_in(after_prefix) {
+            return Ok(false);
+        }
+
+        self.bypassed = Some(command.command.clone());
+        Ok(true)
+    }
+
+    fn append_metadata<OT>(
+        &mut self,
+        state: &mut S,
+        observers: &OT,
+        testcase: &mut Testcase<S::Input>,
+    ) -> Result<(), Error>
+    where
+        OT: ObserversTuple<S>,
+    {
+        let Some(command) = self.bypassed.take() else {
+            return Ok(());
+        };
+        let response = observers
+            .match_name::<ResponseObserver>(&self.observer_name)
+            .and_then(ResponseObserver::response)
+            .unwrap_or_default();
+        store_finding(
+            state,
+            &self.conversation,
+            &self.findings_dir,
+            testcase,
+            vec![format!("auth-bypass: {command}")],
+            Some(response),
+        )
+    }
+
+    fn discard_metadata(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.bypassed = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{AuthBypassRules, ResponseRule, ResponseRules, RuleFile};
+
+    /// The rules of the module documentation
+    fn example_rules() -> ResponseRules {
+        let file: RuleFile = serde_json::from_str(
+            r#"{"rules": [
+                {"name": "login-with-wrong-password", "states": ["USER"], "request": "(?i)^PASS wrong", "codes": [230]},
+                {"name": "internal-error", "codes": [500]},
+                {"name": "listing-outside-root", "response": "(?m)^(bin|etc|proc)$"}
+            ]}"#,
+        )
+        .unwrap();
+        ResponseRules::new(file.rules).unwrap()
+    }
+
+    fn rule(name: &str) -> ResponseRule {
+        ResponseRule {
+            name: name.to_string(),
+            states: Vec::new(),
+            request: None,
+            response: None,
+            codes: Vec::new(),
+            expected_codes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn all_conditions_must_hold() {
+        let rules = example_rules();
+        assert_eq!(
+            rules.matching(
+                1,
+                "USER",
+                b"pass WRONG\r\n",
+                Some(b"230 Logged in.\r\n".as_slice())
+            ),
+            ["login-with-wrong-password"]
+        );
+        // another status code, or another request
+        assert!(rules
+            .matching(
+                1,
+                "USER",
+                b"PASS wrong\r\n",
+                Some(b"530 Login incorrect.\r\n".as_slice())
+            )
+            .is_empty());
+        assert!(rules
+            .matching(
+                1,
+                "USER",
+                b"PASS secret\r\n",
+                Some(b"230 Logged in.\r\n".as_slice())
+            )
+            .is_empty());
+        // no response at all
+        assert!(rules
+            .matching(1, "USER", b"PASS wrong\r\n", None)
+            .is_empty());
+        assert_eq!(
+            rules.matching(
+                0,
+                "0_initial",
+                b"LIST ../..\r\n",
+                Some(b"bin\netc\nhome\n".as_slice())
+            ),
+            ["listing-outside-root"]
+        );
+    }
+
+    #[test]
+    fn rules_apply_to_their_target_states() {
+        let rules = example_rules();
+        // only the rule without states applies outside of `USER`
+        assert_eq!(
+            rules.matching(
+                2,
+                "PASS",
+                b"PASS wrong\r\n",
+                Some(b"500 Internal error\r\n".as_slice())
+            ),
+            ["internal-error"]
+        );
+        assert!(rules
+            .matching(
+                2,
+                "PASS",
+                b"PASS wrong\r\n",
+                Some(b"230 Logged in.\r\n".as_slice())
+            )
+            .is_empty());
+        // target states are given by name or index
+        let rules = ResponseRules::new(vec![ResponseRule {
+            states: vec!["3".to_string()],
+            codes: vec![500],
+            ..rule("by-index")
+        }])
+        .unwrap();
+        assert_eq!(
+            rules.matching(3, "RETR", b"RETR x\r\n", Some(b"500 oops\r\n".as_slice())),
+            ["by-index"]
+        );
+        assert!(rules
+            .matching(2, "RETR", b"RETR x\r\n", Some(b"500 oops\r\n".as_slice()))
+            .is_empty());
+    }
+
+    #[test]
+    fn unexpected_codes() {
+        let rules = ResponseRules::new(vec![ResponseRule {
+            request: Some("^CWD".to_string()),
+            expected_codes: vec![250, 550],
+            ..rule("unexpected-cwd-reply")
+        }])
+        .unwrap();
+        assert_eq!(
+            rules.matching(
+                0,
+                "0_initial",
+                b"CWD /\r\n",
+                Some(b"226 Done\r\n".as_slice())
+            ),
+            ["unexpected-cwd-reply"]
+        );
+        assert!(rules
+            .matching(
+                0,
+                "0_initial",
+                b"CWD /\r\n",
+                Some(b"550 No such directory\r\n".as_slice())
+            )
+            .is_empty());
+        // without a status code nothing is known to be unexpected
+        assert!(rules
+            .matching(0, "0_initial", b"CWD /\r\n", Some(b"garbage".as_slice()))
+            .is_empty());
+        assert!(rules
+            .matching(0, "0_initial", b"CWD /\r\n", None)
+            .is_empty());
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(ResponseRules::new(vec![rule("no-condition")]).is_err());
+        assert!(ResponseRules::new(vec![ResponseRule {
+            request: Some("(unclosed".to_string()),
+            ..rule("invalid-regex")
+        }])
+        .is_err());
+        assert!(ResponseRules::default().is_empty());
+    }
+
+    /// The rules of the documentation of [`AuthBypassRules`]
+    fn auth_rules() -> AuthBypassRules {
+        serde_json::from_str(
+            r#"{"tag": "authenticated", "login_commands": ["PASS"],
+                "commands": [{"command": "RETR", "success_codes": [150, 226]}, {"command": "DELE", "success_codes": [250]}]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn unauthenticated_target_states() {
+        let rules = auth_rules();
+        let tags = |value: &str| BTreeMap::from([("authenticated".to_string(), value.to_string())]);
+        assert!(rules.is_unauthenticated(&tags("false")));
+        assert!(rules.is_unauthenticated(&tags("False")));
+        assert!(!rules.is_unauthenticated(&tags("true")));
+        // untagged target states are not known to be unauthenticated
+        assert!(!rules.is_unauthenticated(&BTreeMap::new()));
+        // the tag defaults to `authenticated`
+        let rules: AuthBypassRules = serde_json::from_str(r#"{"commands": []}"#).unwrap();
+        assert_eq!(rules.tag, "authenticated");
+    }
+
+    #[test]
+    fn privileged_command_succeeded() {
+        let rules = auth_rules();
+        let succeeded = |request: &[u8], response: &[u8]| {
+            rules
+                .succeeded(request, Some(response))
+                .map(|command| command.command.clone())
+        };
+        assert_eq!(
+            succeeded(b"RETR secret.txt\r\n", b"150 Opening data connection\r\n").as_deref(),
+            Some("RETR")
+        );
+        assert_eq!(
+            succeeded(b"dele x\r\n", b"250 Deleted\r\n").as_deref(),
+            Some("DELE")
+        );
+        assert_eq!(
+            succeeded(b"DELE\r\n", b"250 Deleted\r\n").as_deref(),
+            Some("DELE")
+        );
+        // the command failed, or another command with the same start
+        assert_eq!(
+            succeeded(b"RETR secret.txt\r\n", b"530 Not logged in\r\n"),
+            None
+        );
+        assert_eq!(succeeded(b"RETRX secret.txt\r\n", b"150 Opening\r\n"), None);
+        assert_eq!(succeeded(b"RET\r\n", b"150 Opening\r\n"), None);
+        assert_eq!(succeeded(b"RETR x\r\n", b"no status code"), None);
+        assert!(rules.succeeded(b"RETR x\r\n", None).is_none());
+    }
+
+    #[test]
+    fn login_commands() {
+        let rules = auth_rules();
+        let messages = [b"USER anonymous\r\n".to_vec(), b"pass guest\r\n".to_vec()];
+        assert!(rules.may_have_logged_in(messages.iter()));
+        assert!(!rules.may_have_logged_in(messages[..1].iter()));
+        assert!(!rules.may_have_logged_in([b"PASV\r\n".to_vec()].iter()));
+    }
+}
@@ -0,0 +1,857 @@
+//! Reading and writing conversations with the target as packet captures.
+//!
+//! [`PcapWriter`] writes a conversation as a PCAP file, so it can be opened in e.g. Wireshark.
+//! We only know the messages, not how they went over the wire, so the Ethernet, IPv4 and TCP or UDP framing is
+//! synthetic: the client is `10.0.0.1`, the server `10.0.0.2`. A TCP conversation gets a handshake, sequence
+//! numbers that add up and a FIN at the end, so Wireshark can follow the stream.
+//!
+//! [`read_sessions`] goes the other way: it reads a PCAP or PCAPNG capture of real clients talking to a server,
+//! reassembles the TCP streams (or UDP flows) to the server port and splits what each client sent into messages.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    net::IpAddr,
+    path::Path,
+};
+
+use clap::ValueEnum;
+use libafl::Error;
+use libafl_bolts::current_time;
+
+/// Magic number of a (microsecond resolution) PCAP file
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+/// Link type of the packets, Ethernet
+const LINKTYPE_ETHERNET: u32 = 1;
+/// Maximum payload in a single packet, large messages are split
+const MAX_PAYLOAD: usize = 65_000;
+
+const CLIENT_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+const SERVER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+const CLIENT_IP: [u8; 4] = [10, 0, 0, 1];
+const SERVER_IP: [u8; 4] = [10, 0, 0, 2];
+/// The (ephemeral) port of the client
+const CLIENT_PORT: u16 = 40_000;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// The transport protocol the target is spoken to with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// Who sent a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// A request, from the fuzzer to the target
+    ToServer,
+    /// A response, from the target to the fuzzer
+    ToClient,
+}
+
+/// Writes a single conversation between a client and the target as a PCAP file.
+#[derive(Debug)]
+pub struct PcapWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+    transport: Transport,
+    server_port: u16,
+    /// Next TCP sequence number of the client and the server
+    seq: [u32; 2],
+    /// Timestamp of the next packet, in microseconds since the UNIX epoch
+    time_us: u64,
+}
+
+impl<W> PcapWriter<W>
+where
+    W: Write,
+{
+    /// Create a new [`PcapWriter`], writing the PCAP header and, for TCP, the handshake.
+    pub fn new(writer: W, transport: Transport, server_port: u16) -> Result<Self, Error> {
+        let mut pcap = Self {
+            writer,
+            transport,
+            server_port,
+            seq: [1000, 5000],
+            time_us: current_time().as_micros() as u64,
+        };
+        pcap.writer.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        pcap.writer.write_all(&2u16.to_le_bytes())?;
+        pcap.writer.write_all(&4u16.to_le_bytes())?;
+        // timezone offset and timestamp accuracy
+        pcap.writer.write_all(&0i32.to_le_bytes())?;
+        pcap.writer.write_all(&0u32.to_le_bytes())?;
+        // snapshot length
+        pcap.writer.write_all(&65_535u32.to_le_bytes())?;
+        pcap.writer.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+
+        if transport == Transport::Tcp {
+            pcap.tcp_control(Direction::ToServer, TCP_SYN)?;
+            pcap.tcp_control(Direction::ToClient, TCP_SYN | TCP_ACK)?;
+            pcap.tcp_control(Direction::ToServer, TCP_ACK)?;
+        }
+        Ok(pcap)
+    }
+
+    /// Add a message to the conversation. Empty messages are skipped.
+    pub fn write_message(&mut self, direction: Direction, message: &[u8]) -> Result<(), Error> {
+        for payload in message.chunks(MAX_PAYLOAD) {
+            match self.transport {
+                Transport::Tcp => self.tcp_segment(direction, TCP_PSH | TCP_ACK, payload)?,
+                Transport::Udp => self.udp_datagram(direction, payload)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Close the conversation and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.transport == Transport::Tcp {
+            self.tcp_control(Direction::ToServer, TCP_FIN | TCP_ACK)?;
+            self.tcp_control(Direction::ToClient, TCP_FIN | TCP_ACK)?;
+            self.tcp_control(Direction::ToServer, TCP_ACK)?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn tcp_control(&mut self, direction: Direction, flags: u8) -> Result<(), Error> {
+        self.tcp_segment(direction, flags, &[])?;
+        // SYN and FIN take up a sequence number
+        if flags & (TCP_SYN | TCP_FIN) != 0 {
+            self.seq[direction as usize] = self.seq[direction as usize].wrapping_add(1);
+        }
+        Ok(())
+    }
+
+    fn tcp_segment(&mut self, direction: Direction, flags: u8, payload: &[u8]) -> Result<(), Error> {
+        let (src_port, dst_port) = self.ports(direction);
+        let seq = self.seq[direction as usize];
+        let ack = if flags & TCP_ACK != 0 {
+            self.seq[1 - direction as usize]
+        } else {
+            0
+        };
+
+        let mut segment = Vec::with_capacity(20 + payload.len());
+        segment.extend_from_slice(&src_port.to_be_bytes());
+        segment.extend_from_slice(&dst_port.to_be_bytes());
+        segment.extend_from_slice(&seq.to_be_bytes());
+        segment.extend_from_slice(&ack.to_be_bytes());
+        // header length (5 words), flags and window
+        segment.push(5 << 4);
+        segment.push(flags);
+        segment.extend_from_slice(&65_535u16.to_be_bytes());
+        // checksum (filled in below) and urgent pointer
+        segment.extend_from_slice(&[0, 0, 0, 0]);
+        segment.extend_from_slice(payload);
+        let checksum = self.transport_checksum(direction, 6, &segment);
+        segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+
+        self.seq[direction as usize] = seq.wrapping_add(payload.len() as u32);
+        self.write_packet(direction, 6, &segment)
+    }
+
+    fn udp_datagram(&mut self, direction: Direction, payload: &[u8]) -> Result<(), Error> {
+        let (src_port, dst_port) = self.ports(direction);
+        let mut datagram = Vec::with_capacity(8 + payload.len());
+        datagram.extend_from_slice(&src_port.to_be_bytes());
+        datagram.extend_from_slice(&dst_port.to_be_bytes());
+        datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        // checksum (filled in below)
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(payload);
+        let checksum = match self.transport_checksum(direction, 17, &datagram) {
+            // a zero checksum means no checksum for UDP
+            0 => 0xffff,
+            checksum => checksum,
+        };
+        datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+        self.write_packet(direction, 17, &datagram)
+    }
+
+    fn ports(&self, direction: Direction) -> (u16, u16) {
+        match direction {
+            Direction::ToServer => (CLIENT_PORT, self.server_port),
+            Direction::ToClient => (self.server_port, CLIENT_PORT),
+        }
+    }
+
+    fn addresses(direction: Direction) -> ([u8; 4], [u8; 4]) {
+        match direction {
+            Direction::ToServer => (CLIENT_IP, SERVER_IP),
+            Direction::ToClient => (SERVER_IP, CLIENT_IP),
+        }
+    }
+
+    /// Checksum of a TCP segment or UDP datagram, including the IPv4 pseudo header.
+    fn transport_checksum(&self, direction: Direction, protocol: u8, data: &[u8]) -> u16 {
+        let (src, dst) = Self::addresses(direction);
+        let mut pseudo = Vec::with_capacity(12 + data.len());
+        pseudo.extend_from_slice(&src);
+        pseudo.extend_from_slice(&dst);
+        pseudo.push(0);
+        pseudo.push(protocol);
+        pseudo.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        pseudo.extend_from_slice(data);
+        internet_checksum(&pseudo)
+    }
+
+    /// Wrap `data` in IPv4 and Ethernet headers and write it as a packet record.
+    fn write_packet(&mut self, direction: Direction, protocol: u8, data: &[u8]) -> Result<(), Error> {
+        let (src, dst) = Self::addresses(direction);
+        let (src_mac, dst_mac) = match direction {
+            Direction::ToServer => (CLIENT_MAC, SERVER_MAC),
+            Direction::ToClient => (SERVER_MAC, CLIENT_MAC),
+        };
+
+        let mut ip = Vec::with_capacity(20);
+        ip.push(0x45);
+        ip.push(0);
+        ip.extend_from_slice(&((20 + data.len()) as u16).to_be_bytes());
+        // identification, don't fragment
+        ip.extend_from_slice(&[0, 0, 0x40, 0]);
+        // time to live
+        ip.push(64);
+        ip.push(protocol);
+        // checksum (filled in below)
+        ip.extend_from_slice(&[0, 0]);
+        ip.extend_from_slice(&src);
+        ip.extend_from_slice(&dst);
+        let checksum = internet_checksum(&ip);
+        ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        let len = (14 + ip.len() + data.len()) as u32;
+        self.writer
+            .write_all(&((self.time_us / 1_000_000) as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&((self.time_us % 1_000_000) as u32).to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&dst_mac)?;
+        self.writer.write_all(&src_mac)?;
+        self.writer.write_all(&0x0800u16.to_be_bytes())?;
+        self.writer.write_all(&ip)?;
+        self.writer.write_all(data)?;
+
+        // the packets are a millisecond apart
+        self.time_us += 1000;
+        Ok(())
+    }
+}
+
+/// The ones' complement checksum used by IPv4, TCP and UDP.
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// How the data a client sent over TCP is split into messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MessageSplit {
+    /// Everything the client sent until the server responded is one message
+    Response,
+    /// Every TCP segment with data is one message
+    Segment,
+}
+
+/// The messages a single client sent to the server, in order.
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Address and port of the client
+    pub client: (IpAddr, u16),
+    pub messages: Vec<Vec<u8>>,
+}
+
+/// A captured frame and the link type of the interface it was captured on.
+struct Frame {
+    link_type: u32,
+    data: Vec<u8>,
+}
+
+/// A TCP segment or UDP datagram from or to the server port.
+struct Packet {
+    client: (IpAddr, u16),
+    to_server: bool,
+    /// TCP sequence number and flags, `None` for UDP
+    tcp: Option<(u32, u8)>,
+    payload: Vec<u8>,
+}
+
+fn u16_at(data: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?.try_into().ok()?;
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+fn u32_at(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+fn truncated(path: &Path) -> Error {
+    Error::illegal_argument(format!("Capture {} is truncated or corrupt", path.display()))
+}
+
+/// Read all frames of a PCAP or PCAPNG file.
+fn read_frames(path: &Path) -> Result<Vec<Frame>, Error> {
+    parse_frames(path, &fs::read(path)?)
+}
+
+/// Parse all frames of the PCAP or PCAPNG file `path` with the content `data`.
+fn parse_frames(path: &Path, data: &[u8]) -> Result<Vec<Frame>, Error> {
+    let magic = u32_at(data, 0, false).ok_or_else(|| truncated(path))?;
+    match magic {
+        // PCAPNG section header block
+        0x0a0d_0d0a => read_pcapng_frames(path, data),
+        // PCAP with microsecond or nanosecond timestamps, in either byte order
+        0xa1b2_c3d4 | 0xa1b2_3c4d => read_pcap_frames(path, data, false),
+        0xd4c3_b2a1 | 0x4d3c_b2a1 => read_pcap_frames(path, data, true),
+        _ => Err(Error::illegal_argument(format!(
+            "{} is not a PCAP or PCAPNG file",
+            path.display()
+        ))),
+    }
+}
+
+fn read_pcap_frames(path: &Path, data: &[u8], big_endian: bool) -> Result<Vec<Frame>, Error> {
+    let link_type = u32_at(data, 20, big_endian).ok_or_else(|| truncated(path))?;
+    let mut frames = Vec::new();
+    let mut offset = 24;
+    while offset < data.len() {
+        let len = u32_at(data, offset + 8, big_endian).ok_or_else(|| truncated(path))? as usize;
+        let frame = data
+            .get(offset + 16..offset + 16 + len)
+            .ok_or_else(|| truncated(path))?;
+        frames.push(Frame {
+            link_type,
+            data: frame.to_vec(),
+        });
+        offset += 16 + len;
+    }
+    Ok(frames)
+}
+
+fn read_pcapng_frames(path: &Path, data: &[u8]) -> Result<Vec<Frame>, Error> {
+    let mut frames = Vec::new();
+    let mut big_endian = false;
+    // link types of the interfaces of the current section
+    let mut interfaces: Vec<u32> = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let block_type = u32_at(data, offset, big_endian).ok_or_else(|| truncated(path))?;
+        if block_type == 0x0a0d_0d0a {
+            // a new section, which sets the byte order
+            big_endian = u32_at(data, offset + 8, false).ok_or_else(|| truncated(path))? != 0x1a2b_3c4d;
+            interfaces.clear();
+        }
+        let block_len = u32_at(data, offset + 4, big_endian).ok_or_else(|| truncated(path))? as usize;
+        if block_len < 12 {
+            return Err(truncated(path));
+        }
+        let body = data
+            .get(offset + 8..offset + block_len - 4)
+            .ok_or_else(|| truncated(path))?;
+        let (interface, frame) = match block_type {
+            // interface description block
+            1 => {
+                interfaces.push(u32::from(
+                    u16_at(body, 0, big_endian).ok_or_else(|| truncated(path))?,
+                ));
+                (None, None)
+            }
+            // (obsolete) packet block
+            2 => {
+                let len = u32_at(body, 12, big_endian).ok_or_else(|| truncated(path))? as usize;
+                (
+                    u16_at(body, 0, big_endian).map(usize::from),
+                    body.get(20..20 + len),
+                )
+            }
+            // simple packet block
+            3 => (Some(0), body.get(4..)),
+            // enhanced packet block
+            6 => {
+                let len = u32_at(body, 12, big_endian).ok_or_else(|| truncated(path))? as usize;
+                (
+                    u32_at(body, 0, big_endian).map(|idx| idx as usize),
+                    body.get(20..20 + len),
+                )
+            }
+            _ => (None, None),
+        };
+        if let (Some(interface), Some(frame)) = (interface, frame) {
+            let link_type = *interfaces.get(interface).ok_or_else(|| truncated(path))?;
+            frames.push(Frame {
+                link_type,
+                data: frame.to_vec(),
+            });
+        }
+        offset += block_len;
+    }
+    Ok(frames)
+}
+
+/// The IP packet in a frame, if any.
+fn ip_packet(frame: &Frame) -> Option<&[u8]> {
+    let data = frame.data.as_slice();
+    let (ethertype, offset) = match frame.link_type {
+        // BSD loopback, the address family is in host byte order
+        0 | 108 => return data.get(4..),
+        // Ethernet, possibly with a VLAN tag
+        1 => match u16_at(data, 12, true)? {
+            0x8100 => (u16_at(data, 16, true)?, 18),
+            ethertype => (ethertype, 14),
+        },
+        // raw IP
+        101 | 228 | 229 => return Some(data),
+        // Linux cooked capture v1 and v2
+        113 => (u16_at(data, 14, true)?, 16),
+        276 => (u16_at(data, 0, true)?, 20),
+        _ => return None,
+    };
+    match ethertype {
+        0x0800 | 0x86dd => data.get(offset..),
+        _ => None,
+    }
+}
+
+/// Parse the TCP segment or UDP datagram in `ip` if it is from or to `server_port`.
+fn parse_packet(ip: &[u8], transport: Transport, server_port: u16) -> Option<Packet> {
+    let (protocol, src, dst, segment) = match ip.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(ip[0] & 0x0f) * 4;
+            let total_len = usize::from(u16_at(ip, 2, true)?);
+            // fragments are not reassembled
+            if u16_at(ip, 6, true)? & 0x3fff != 0 {
+                return None;
+            }
+            let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (
+                ip[9],
+                IpAddr::from(src),
+                IpAddr::from(dst),
+                ip.get(header_len..total_len.min(ip.len()))?,
+            )
+        }
+        6 => {
+            // extension headers are not supported
+            let payload_len = usize::from(u16_at(ip, 4, true)?);
+            let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            (
+                *ip.get(6)?,
+                IpAddr::from(src),
+                IpAddr::from(dst),
+                ip.get(40..(40 + payload_len).min(ip.len()))?,
+            )
+        }
+        _ => return None,
+    };
+
+    let src_port = u16_at(segment, 0, true)?;
+    let dst_port = u16_at(segment, 2, true)?;
+    let (client, to_server) = if dst_port == server_port {
+        ((src, src_port), true)
+    } else if src_port == server_port {
+        ((dst, dst_port), false)
+    } else {
+        return None;
+    };
+
+    match (transport, protocol) {
+        (Transport::Tcp, 6) => {
+            let header_len = usize::from(segment.get(12)? >> 4) * 4;
+            Some(Packet {
+                client,
+                to_server,
+                tcp: Some((u32_at(segment, 4, true)?, *segment.get(13)?)),
+                payload: segment.get(header_len..)?.to_vec(),
+            })
+        }
+        (Transport::Udp, 17) => Some(Packet {
+            client,
+            to_server,
+            tcp: None,
+            payload: segment.get(8..)?.to_vec(),
+        }),
+        _ => None,
+    }
+}
+
+/// Reassembles what a single client sent.
+#[derive(Debug, Default)]
+struct SessionBuilder {
+    messages: Vec<Vec<u8>>,
+    /// Data of the message that is being collected
+    current: Vec<u8>,
+    /// Next sequence number we expect from the client
+    next_seq: Option<u32>,
+    /// Segments that arrived before the segments preceding them
+    out_of_order: BTreeMap<u32, Vec<u8>>,
+}
+
+impl SessionBuilder {
+    fn finish_message(&mut self) {
+        if !self.current.is_empty() {
+            self.messages.push(std::mem::take(&mut self.current));
+        }
+    }
+
+    /// Add the data of a client segment, dropping retransmitted data and delaying data that arrived out of order.
+    fn client_segment(&mut self, seq: u32, flags: u8, payload: Vec<u8>, split: MessageSplit) {
+        if flags & TCP_SYN != 0 {
+            self.next_seq = Some(seq.wrapping_add(1));
+            return;
+        }
+        if payload.is_empty() {
+            return;
+        }
+        let mut next = *self.next_seq.get_or_insert(seq);
+        self.out_of_order.entry(seq).or_insert(payload);
+
+        // take the segments that continue the stream, in order. Sequence numbers wrap around, so the segments are
+        // ordered by their distance to the next sequence number, not by the sequence number itself
+        while let Some((seq, ahead)) = self
+            .out_of_order
+            .keys()
+            .map(|&seq| (seq, seq.wrapping_sub(next) as i32))
+            .min_by_key(|&(_, ahead)| ahead)
+        {
+            if ahead > 0 {
+                break;
+            }
+            let payload = self.out_of_order.remove(&seq).unwrap();
+            let overlap = (-ahead) as usize;
+            if overlap < payload.len() {
+                self.current.extend_from_slice(&payload[overlap..]);
+                next = next.wrapping_add((payload.len() - overlap) as u32);
+                if split == MessageSplit::Segment {
+                    self.finish_message();
+                }
+            }
+        }
+        self.next_seq = Some(next);
+    }
+}
+
+/// Read the sessions of all clients with the server on `server_port` from a PCAP or PCAPNG capture.
+///
+/// Sessions are returned in the order in which they started. A client that reconnects from the same port (a new SYN)
+/// starts a new session. For UDP, every datagram is a message.
+pub fn read_sessions(
+    path: &Path,
+    transport: Transport,
+    server_port: u16,
+    split: MessageSplit,
+) -> Result<Vec<Session>, Error> {
+    Ok(sessions_of(read_frames(path)?, transport, server_port, split))
+}
+
+/// The sessions with the server on `server_port` in the captured `frames`, see [`read_sessions`].
+fn sessions_of(
+    frames: Vec<Frame>,
+    transport: Transport,
+    server_port: u16,
+    split: MessageSplit,
+) -> Vec<Session> {
+    let mut sessions: Vec<(IpAddr, u16, SessionBuilder)> = Vec::new();
+    // index of the current session of each client
+    let mut current: HashMap<(IpAddr, u16), usize> = HashMap::new();
+
+    for frame in frames {
+        let Some(packet) = ip_packet(&frame).and_then(|ip| parse_packet(ip, transport, server_port))
+        else {
+            continue;
+        };
+        let is_syn = packet.to_server && packet.tcp.is_some_and(|(_, flags)| flags & TCP_SYN != 0);
+        let idx = match current.get(&packet.client) {
+            Some(&idx) if !is_syn || sessions[idx].2.messages.is_empty() => idx,
+            _ => {
+                sessions.push((packet.client.0, packet.client.1, SessionBuilder::default()));
+                current.insert(packet.client, sessions.len() - 1);
+                sessions.len() - 1
+            }
+        };
+        let session = &mut sessions[idx].2;
+
+        match (packet.to_server, packet.tcp) {
+            (true, Some((seq, flags))) => session.client_segment(seq, flags, packet.payload, split),
+            (true, None) => {
+                session.current = packet.payload;
+                session.finish_message();
+            }
+            // a response ends the message of the client
+            (false, _) if !packet.payload.is_empty() => session.finish_message(),
+            (false, _) => {}
+        }
+    }
+
+    sessions
+        .into_iter()
+        .map(|(ip, port, mut session)| {
+            session.finish_message();
+            Session {
+                client: (ip, port),
+                messages: session.messages,
+            }
+        })
+        .filter(|session| !session.messages.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, path::Path};
+
+    use super::{
+        ip_packet, parse_frames, parse_packet, read_pcapng_frames, sessions_of, Direction, Frame,
+        MessageSplit, PcapWriter, SessionBuilder, Transport, CLIENT_IP, CLIENT_PORT, TCP_SYN,
+    };
+
+    const SERVER_PORT: u16 = 21;
+
+    /// A capture of a TCP conversation, written by the [`PcapWriter`].
+    fn capture(messages: &[(Direction, &str)]) -> Vec<u8> {
+        let mut pcap = PcapWriter::new(Vec::new(), Transport::Tcp, SERVER_PORT).unwrap();
+        for (direction, message) in messages {
+            pcap.write_message(*direction, message.as_bytes()).unwrap();
+        }
+        pcap.finish().unwrap()
+    }
+
+    fn ftp_login() -> Vec<u8> {
+        capture(&[
+            (Direction::ToClient, "220 Welcome\r\n"),
+            (Direction::ToServer, "USER ftp\r\n"),
+            (Direction::ToClient, "331 Password required\r\n"),
+            (Direction::ToServer, "PASS ftp\r\n"),
+            (Direction::ToClient, "230 Logged in\r\n"),
+        ])
+    }
+
+    /// The same frames as a PCAPNG file, in the given byte order.
+    fn pcapng(frames: &[Frame], big_endian: bool) -> Vec<u8> {
+        let u16_bytes = |value: u16| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut data = Vec::new();
+        let mut block = |block_type: u32, mut body: Vec<u8>| {
+            body.resize(body.len().div_ceil(4) * 4, 0);
+            let len = 12 + body.len() as u32;
+            data.extend(u32_bytes(block_type));
+            data.extend(u32_bytes(len));
+            data.extend(body);
+            data.extend(u32_bytes(len));
+        };
+
+        let mut section = u32_bytes(0x1a2b_3c4d).to_vec();
+        section.extend(u16_bytes(1));
+        section.extend(u16_bytes(0));
+        section.extend([0xff; 8]);
+        block(0x0a0d_0d0a, section);
+
+        let mut interface = u16_bytes(frames[0].link_type as u16).to_vec();
+        interface.extend(u16_bytes(0));
+        interface.extend(u32_bytes(65_535));
+        block(1, interface);
+
+        for frame in frames {
+            let mut packet = Vec::new();
+            for value in [0, 0, 0, frame.data.len() as u32, frame.data.len() as u32] {
+                packet.extend(u32_bytes(value));
+            }
+            packet.extend(&frame.data);
+            block(6, packet);
+        }
+        data
+    }
+
+    fn messages(frames: Vec<Frame>, split: MessageSplit) -> Vec<Vec<u8>> {
+        let sessions = sessions_of(frames, Transport::Tcp, SERVER_PORT, split);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].client, (IpAddr::from(CLIENT_IP), CLIENT_PORT));
+        sessions[0].messages.clone()
+    }
+
+    #[test]
+    fn pcap_round_trip() {
+        let frames = parse_frames(Path::new("login.pcap"), &ftp_login()).unwrap();
+        assert_eq!(
+            messages(frames, MessageSplit::Response),
+            [b"USER ftp\r\n".to_vec(), b"PASS ftp\r\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn udp_round_trip() {
+        let mut pcap = PcapWriter::new(Vec::new(), Transport::Udp, 5353).unwrap();
+        pcap.write_message(Direction::ToServer, b"first").unwrap();
+        pcap.write_message(Direction::ToServer, b"second").unwrap();
+        let frames = parse_frames(Path::new("udp.pcap"), &pcap.finish().unwrap()).unwrap();
+        let sessions = sessions_of(frames, Transport::Udp, 5353, MessageSplit::Response);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(
+            sessions[0].messages,
+            [b"first".to_vec(), b"second".to_vec()]
+        );
+    }
+
+    #[test]
+    fn truncated_pcap() {
+        let data = ftp_login();
+        let path = Path::new("truncated.pcap");
+        // in the packet data, in the record header, and in the file header
+        for len in [data.len() - 1, 24 + 10, 10] {
+            assert!(parse_frames(path, &data[..len]).is_err(), "{len} bytes");
+        }
+        assert!(parse_frames(path, b"not a capture").is_err());
+    }
+
+    #[test]
+    fn truncated_packets() {
+        let frames = parse_frames(Path::new("login.pcap"), &ftp_login()).unwrap();
+        // the frame with `USER ftp`, after the handshake and the greeting
+        let ip = ip_packet(&frames[4]).unwrap();
+        let packet = parse_packet(ip, Transport::Tcp, SERVER_PORT).unwrap();
+        assert!(packet.to_server);
+        assert_eq!(packet.payload, b"USER ftp\r\n");
+        // IPv4 and TCP header are 40 bytes, anything shorter has no segment
+        for len in 0..40 {
+            assert!(parse_packet(&ip[..len], Transport::Tcp, SERVER_PORT).is_none());
+        }
+        assert!(parse_packet(ip, Transport::Udp, SERVER_PORT).is_none());
+        assert!(parse_packet(ip, Transport::Tcp, 80).is_none());
+        assert!(ip_packet(&Frame {
+            link_type: 1,
+            data: frames[4].data[..13].to_vec(),
+        })
+        .is_none());
+    }
+
+    #[test]
+    fn pcapng_in_both_byte_orders() {
+        let frames = parse_frames(Path::new("login.pcap"), &ftp_login()).unwrap();
+        for big_endian in [false, true] {
+            let data = pcapng(&frames, big_endian);
+            let path = Path::new("login.pcapng");
+            let parsed = parse_frames(path, &data).unwrap();
+            assert_eq!(parsed.len(), frames.len());
+            for (parsed, frame) in parsed.iter().zip(&frames) {
+                assert_eq!(parsed.link_type, frame.link_type);
+                assert_eq!(parsed.data, frame.data);
+            }
+            assert_eq!(
+                messages(parsed, MessageSplit::Response),
+                [b"USER ftp\r\n".to_vec(), b"PASS ftp\r\n".to_vec()]
+            );
+
+            // cut off in the middle of the last packet
+            assert!(read_pcapng_frames(path, &data[..data.len() - 10]).is_err());
+            // a block that is too short to hold its own header
+            let mut corrupt = data;
+            let len = if big_endian {
+                8u32.to_be_bytes()
+            } else {
+                8u32.to_le_bytes()
+            };
+            corrupt[4..8].copy_from_slice(&len);
+            assert!(read_pcapng_frames(path, &corrupt).is_err());
+        }
+    }
+
+    /// A session of a client that sent a SYN with the sequence number `isn`.
+    fn connected(isn: u32) -> SessionBuilder {
+        let mut session = SessionBuilder::default();
+        session.client_segment(isn, TCP_SYN, Vec::new(), MessageSplit::Response);
+        session
+    }
+
+    fn segments(mut session: SessionBuilder, segments: &[(u32, &str)]) -> Vec<Vec<u8>> {
+        for (seq, payload) in segments {
+            session.client_segment(*seq, 0, payload.as_bytes().to_vec(), MessageSplit::Response);
+        }
+        session.finish_message();
+        session.messages
+    }
+
+    #[test]
+    fn retransmitted_segments() {
+        let messages = segments(
+            connected(100),
+            &[(101, "USER"), (101, "USER"), (105, " ftp"), (101, "USER")],
+        );
+        assert_eq!(messages, [b"USER ftp".to_vec()]);
+    }
+
+    #[test]
+    fn overlapping_segments() {
+        let messages = segments(connected(100), &[(101, "USER f"), (105, " ftp")]);
+        assert_eq!(messages, [b"USER ftp".to_vec()]);
+    }
+
+    #[test]
+    fn out_of_order_segments() {
+        let messages = segments(
+            connected(100),
+            &[(109, "\r\n"), (105, " ftp"), (101, "USER")],
+        );
+        assert_eq!(messages, [b"USER ftp\r\n".to_vec()]);
+    }
+
+    #[test]
+    fn sequence_number_wraparound() {
+        // the first data byte is at u32::MAX - 1, the second segment starts after the wraparound
+        let messages = segments(
+            connected(u32::MAX - 2),
+            &[(u32::MAX - 1, "USER"), (2, " ftp")],
+        );
+        assert_eq!(messages, [b"USER ftp".to_vec()]);
+        let messages = segments(
+            connected(u32::MAX - 2),
+            &[(2, " ftp"), (u32::MAX - 1, "USER")],
+        );
+        assert_eq!(messages, [b"USER ftp".to_vec()]);
+    }
+
+    #[test]
+    fn split_by_segment() {
+        let mut session = connected(100);
+        session.client_segment(101, 0, b"USER".to_vec(), MessageSplit::Segment);
+        session.client_segment(105, 0, b" ftp".to_vec(), MessageSplit::Segment);
+        assert_eq!(session.messages, [b"USER".to_vec(), b" ftp".to_vec()]);
+    }
+}
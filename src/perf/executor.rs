@@ -12,7 +12,10 @@ use libafl::{
     state::UsesState,
 };
 
-use crate::executor::ResettableForkserver;
+use crate::{
+    executor::{ResettableForkserver, RestartReason},
+    replay::TraceTargetState,
+};
 
 pub struct ExecutorPerf<B> {
     base: B,
@@ -91,6 +94,26 @@ where
         log::info!("Scheduler state_reset_occurred(): {:?}", elapsed);
         r
     }
+
+    fn target_state_changed(&mut self, target_state: TraceTargetState) {
+        self.base.target_state_changed(target_state);
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.base.set_timeout(timeout);
+    }
+
+    fn has_running_child(&self) -> bool {
+        self.base.has_running_child()
+    }
+
+    fn restart_due(&self) -> Option<RestartReason> {
+        self.base.restart_due()
+    }
+
+    fn workdir_restores(&self) -> Option<usize> {
+        self.base.workdir_restores()
+    }
 }
 
 impl<B> UsesObservers for ExecutorPerf<B>
@@ -0,0 +1,59 @@
+//! Automatic selection of the port the target uses.
+//!
+//! Running several campaigns on one machine means each target needs its own port, and the same port has to be repeated
+//! in the arguments of the target, its environment and its configuration file. Instead, the fuzzer can pick a free port
+//! and fill it in wherever the [`PORT_PLACEHOLDER`] is used.
+
+use std::{
+    fs,
+    net::{Ipv4Addr, TcpListener},
+    path::Path,
+};
+
+use libafl::Error;
+
+/// Placeholder that is replaced by the port the target uses.
+pub const PORT_PLACEHOLDER: &str = "@@PORT@@";
+
+/// Name of the file in the output directory that records the port the target used.
+pub const PORT_FILE: &str = "target_port";
+
+/// Find a port that is currently free on the loopback interface.
+///
+/// The port is found by letting the OS assign one, after which it is immediately released again.
+/// Nothing prevents another process from taking it before the target binds it, but the OS cycles
+/// through the ephemeral range, so this is unlikely.
+pub fn find_free_port() -> Result<u16, Error> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// Replace every occurrence of the [`PORT_PLACEHOLDER`] in `s` by `port`.
+pub fn substitute_port(s: &str, port: u16) -> String {
+    s.replace(PORT_PLACEHOLDER, &port.to_string())
+}
+
+/// Read the (configuration) file `template`, substitute the port, and write it to `destination`.
+///
+/// `destination` is overwritten if it exists.
+pub fn render_template(template: &Path, destination: &Path, port: u16) -> Result<(), Error> {
+    let content = fs::read_to_string(template).map_err(|e| {
+        Error::illegal_argument(format!(
+            "Could not read config template [{}]: {e}",
+            template.display()
+        ))
+    })?;
+    fs::write(destination, substitute_port(&content, port))?;
+    log::info!(
+        "Rendered config template {} to {} with port {port}",
+        template.display(),
+        destination.display()
+    );
+    Ok(())
+}
+
+/// Record the port the target used in the output directory, see [`PORT_FILE`].
+pub fn record_port(out_dir: &Path, port: u16) -> Result<(), Error> {
+    fs::write(out_dir.join(PORT_FILE), format!("{port}\n"))?;
+    Ok(())
+}
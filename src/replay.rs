@@ -1,61 +1,429 @@
 //! Functionality that helps store and retrace what happened. Which messages were sent, in what order.
 //! 
 //! But, there is nothing smart about it.
+//!
+//! A trace is a CBOR file holding a [`TraceHeader`] followed by a [`TraceMessage`] for every message that was sent to
+//! the target. Use [`TraceReader`] and [`TraceWriter`] to read and write them. Traces written before the header existed
+//! (format version 0) consist of messages only, without timestamps, execution numbers and latencies; they can still be
+//! read by [`TraceReader`]. Traces may be gzip compressed (`.cbor.gz`), see [`TraceStoragePolicy`].
 
 use std::{
-    fs::{File, OpenOptions},
-    io::BufWriter,
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
     path::{Path, PathBuf},
 };
 
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use libafl::{executors::ExitKind, Error};
+use libafl_bolts::{current_time, hash_std};
 use serde::{Deserialize, Serialize};
 
-/// Request response pair that just handles bytes (u8) which can be serialized.
+use crate::crash_record::is_crash_record;
+
+/// Identifies a trace file
+pub const TRACE_MAGIC: &str = "LibAFLstar trace";
+/// Version of the trace format that is written. Version 0 is the format without header.
+pub const TRACE_FORMAT_VERSION: u32 = 1;
+/// What is stored as response when the response of the target could not be read
+pub const RESPONSE_ERROR: &[u8] = b"LibAFLStar_err";
+
+/// The target state a trace was recorded in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TraceTargetState {
+    /// Index of the target state
+    pub idx: usize,
+    /// Name of the target state, i.e., the name of its prefix directory
+    pub name: String,
+    /// Hash of the prefix messages of the target state, see [`hash_messages`]
+    pub prefix_hash: u64,
+}
+
+/// The first item of a trace file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TraceHeader {
+    /// Always [`TRACE_MAGIC`]
+    pub magic: String,
+    /// The format version the trace was written with
+    pub version: u32,
+    /// Identifies the fuzzing campaign (or tool run) the trace belongs to
+    #[serde(default)]
+    pub campaign_id: String,
+    /// The target state the trace was recorded in, if known
+    #[serde(default)]
+    pub target_state: Option<TraceTargetState>,
+    /// When the trace was started, in milliseconds since the UNIX epoch
+    #[serde(default)]
+    pub start_time: u64,
+}
+
+impl TraceHeader {
+    /// Create a header for a trace that starts now.
+    pub fn new(campaign_id: &str, target_state: Option<TraceTargetState>) -> Self {
+        Self {
+            magic: TRACE_MAGIC.to_string(),
+            version: TRACE_FORMAT_VERSION,
+            campaign_id: campaign_id.to_string(),
+            target_state,
+            start_time: current_time().as_millis() as u64,
+        }
+    }
+}
+
+/// A message that was sent to the target, the response and how the execution ended.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TraceMessage {
+    /// Exit kind, see [`exit_kind_str`]
+    pub ek: String,
+    /// Request
+    pub req: Vec<u8>,
+    /// Response
+    pub resp: Vec<u8>,
+    /// When the message was sent, in milliseconds since the UNIX epoch, 0 if unknown
+    #[serde(default)]
+    pub time: u64,
+    /// The number of executions of the fuzzer when the message was sent, 0 if unknown
+    #[serde(default)]
+    pub execution: u64,
+    /// Time from sending the message until the target finished handling it, in microseconds, 0 if unknown
+    #[serde(default)]
+    pub latency_us: u64,
+}
+
+impl TraceMessage {
+    /// Create a message without metadata.
+    pub fn new(exit_kind: ExitKind, request: &[u8], response: &[u8]) -> Self {
+        Self {
+            ek: exit_kind_str(exit_kind).to_string(),
+            req: request.to_vec(),
+            resp: response.to_vec(),
+            ..Self::default()
+        }
+    }
+}
+
+/// An item of a trace file, traces without header only contain messages.
 #[derive(Serialize, Deserialize, Debug)]
-pub struct RequestResponsePair<'a> {
-    // exit kind
-    ek: String,
-    // request
-    req: &'a [u8],
-    // response
-    resp: &'a [u8],
-}
-
-impl<'a> RequestResponsePair<'a> {
-    pub fn new(exit_kind: ExitKind, request: &'a [u8], response: &'a [u8]) -> Self {
-        let ek = match exit_kind {
-            ExitKind::Ok => "Ok",
-            ExitKind::Crash => "Cr",
-            ExitKind::Oom => "Oo",
-            ExitKind::Timeout => "Tm",
-            ExitKind::Diff {
-                primary: _,
-                secondary: _,
-            } => "Diff",
+#[serde(untagged)]
+enum TraceItem {
+    Header(TraceHeader),
+    Message(TraceMessage),
+}
+
+/// Hash of a sequence of messages, e.g. a prefix. The empty sequence hashes to 0.
+pub fn hash_messages<'a, I>(messages: I) -> u64
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    messages.into_iter().fold(0, |hash, message| {
+        hash_std(&[hash.to_le_bytes(), hash_std(message).to_le_bytes()].concat())
+    })
+}
+
+/// Reads a trace file, iterating over its messages.
+///
+/// A message that was only partially written, e.g. because the fuzzer was killed, ends the trace.
+#[derive(Debug)]
+pub struct TraceReader<R> {
+    reader: R,
+    header: Option<TraceHeader>,
+    /// The first message of a trace without header
+    first: Option<TraceMessage>,
+}
+
+impl TraceReader<Box<dyn Read>> {
+    /// Open the trace file at `path`, which may be gzip compressed.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut file = BufReader::new(File::open(path)?);
+        let reader: Box<dyn Read> = if file.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
         };
-        Self {
-            ek: ek.to_string(),
-            req: request,
-            resp: response,
+        Self::new(reader).map_err(|e| {
+            Error::illegal_argument(format!("Could not read trace {}: {e}", path.display()))
+        })
+    }
+}
+
+impl<R> TraceReader<R>
+where
+    R: Read,
+{
+    /// Read the trace from `reader`.
+    ///
+    /// # Errors:
+    ///
+    /// - The trace was written with a newer format version
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let (header, first) = match Self::read_item(&mut reader)? {
+            Some(TraceItem::Header(header)) => {
+                if header.magic != TRACE_MAGIC {
+                    return Err(Error::illegal_argument("Not a LibAFLstar trace"));
+                }
+                if header.version > TRACE_FORMAT_VERSION {
+                    return Err(Error::illegal_argument(format!(
+                        "Trace format version {} is not supported, the latest supported version is {TRACE_FORMAT_VERSION}",
+                        header.version
+                    )));
+                }
+                (Some(header), None)
+            }
+            Some(TraceItem::Message(message)) => (None, Some(message)),
+            None => (None, None),
+        };
+        Ok(Self {
+            reader,
+            header,
+            first,
+        })
+    }
+
+    /// The header of the trace, `None` for traces without header (format version 0).
+    pub fn header(&self) -> Option<&TraceHeader> {
+        self.header.as_ref()
+    }
+
+    /// The format version of the trace.
+    pub fn version(&self) -> u32 {
+        self.header.as_ref().map_or(0, |header| header.version)
+    }
+
+    fn read_item(reader: &mut R) -> Result<Option<TraceItem>, Error> {
+        match ciborium::from_reader(reader) {
+            Ok(item) => Ok(Some(item)),
+            Err(ciborium::de::Error::Io(e)) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(Error::serialize(format!("Corrupt trace item: {e}"))),
+        }
+    }
+}
+
+impl<R> Iterator for TraceReader<R>
+where
+    R: Read,
+{
+    type Item = Result<TraceMessage, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(first) = self.first.take() {
+            return Some(Ok(first));
+        }
+        match Self::read_item(&mut self.reader) {
+            Ok(Some(TraceItem::Message(message))) => Some(Ok(message)),
+            Ok(Some(TraceItem::Header(_))) => Some(Err(Error::serialize(
+                "Unexpected header in the middle of a trace",
+            ))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Writes a trace file.
+#[derive(Debug)]
+pub struct TraceWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+}
+
+impl TraceWriter<BufWriter<File>> {
+    /// Create (or overwrite) the trace file at `path`, starting with `header`.
+    pub fn create(path: &Path, header: &TraceHeader) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)?;
+        Self::new(BufWriter::new(file), header)
+    }
+}
+
+impl<W> TraceWriter<W>
+where
+    W: Write,
+{
+    /// Start a trace in `writer` with `header`.
+    pub fn new(writer: W, header: &TraceHeader) -> Result<Self, Error> {
+        let mut trace = Self { writer };
+        trace.write_item(header)?;
+        Ok(trace)
+    }
+
+    /// Append a message to the trace.
+    pub fn write_message(&mut self, message: &TraceMessage) -> Result<(), Error> {
+        self.write_item(message)
+    }
+
+    /// Flush the buffered messages to the underlying writer.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Flush the trace and return the underlying writer.
+    pub fn into_inner(mut self) -> Result<W, Error> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_item<T: Serialize>(&mut self, item: &T) -> Result<(), Error> {
+        ciborium::into_writer(item, &mut self.writer)
+            .map_err(|e| Error::serialize(format!("Could not write to trace: {e}")))
+    }
+}
+
+/// Short name of the exit kind, as it is stored in the traces.
+pub fn exit_kind_str(exit_kind: ExitKind) -> &'static str {
+    match exit_kind {
+        ExitKind::Ok => "Ok",
+        ExitKind::Crash => "Cr",
+        ExitKind::Oom => "Oo",
+        ExitKind::Timeout => "Tm",
+        ExitKind::Diff {
+            primary: _,
+            secondary: _,
+        } => "Diff",
+    }
+}
+
+/// If `path` is named like a trace, compressed or not. Crash records are no traces.
+pub fn is_trace(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    (name.ends_with(".cbor") || name.ends_with(".cbor.gz")) && !is_crash_record(path)
+}
+
+/// The files a tool works on: `input` itself if it is a file, otherwise the files in the directory `input` that
+/// `include` accepts (e.g. [`is_trace`] or [`is_crash_record`]), sorted by name.
+///
+/// # Errors:
+///
+/// - `input` does not exist
+/// - Any IO errors
+pub fn input_files<F>(input: PathBuf, include: F) -> Result<Vec<PathBuf>, Error>
+where
+    F: Fn(&Path) -> bool,
+{
+    if !input.exists() {
+        return Err(Error::illegal_argument(format!(
+            "Input [{}] does not exist",
+            input.display()
+        )));
+    }
+    if input.is_file() {
+        return Ok(vec![input]);
+    }
+    let mut files: Vec<PathBuf> = input
+        .read_dir()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| include(path))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// The first bytes of a gzip file
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Which traces the [`RequestResponseCollector`] keeps on disk.
+///
+/// Traces that end with a crash are always kept. Of the other traces the `keep_recent` most recent are kept, as long
+/// as all kept traces fit in `max_total_size`, the rest is overwritten by the next trace. The default keeps only
+/// crashing traces, uncompressed and without size limit.
+#[derive(Debug, Clone, Default)]
+pub struct TraceStoragePolicy {
+    /// Number of finished traces without a crash to keep
+    pub keep_recent: usize,
+    /// The oldest traces without a crash are deleted when the finished traces take more than this many bytes.
+    /// Crashing traces are never deleted
+    pub max_total_size: Option<u64>,
+    /// Compress the traces with gzip
+    pub compress: bool,
+}
+
+/// The file a trace is written to.
+#[derive(Debug)]
+enum TraceFile {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl TraceFile {
+    fn create(path: &Path, compress: bool) -> Result<Self, Error> {
+        let file = BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(path)?,
+        );
+        Ok(if compress {
+            TraceFile::Gzip(GzEncoder::new(file, Compression::default()))
+        } else {
+            TraceFile::Plain(file)
+        })
+    }
+
+    /// Complete the file, and make sure it is on disk if `sync` is set.
+    fn finish(self, sync: bool) -> Result<(), Error> {
+        let file = match self {
+            TraceFile::Plain(file) => file,
+            TraceFile::Gzip(encoder) => encoder.finish()?,
+        };
+        let file = file.into_inner().map_err(io::IntoInnerError::into_error)?;
+        if sync {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for TraceFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            TraceFile::Plain(file) => file.write(buf),
+            TraceFile::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TraceFile::Plain(file) => file.flush(),
+            TraceFile::Gzip(encoder) => encoder.flush(),
         }
     }
 }
 
 /// Struct that helps to write request-response pairs from the target to file, collecting them per trace.
 /// This way, all pairs that belong to a single trace are stored together, in order.
+///
+/// Traces are flushed when they end and as soon as they crash, so they survive the fuzzer being killed.
 #[derive(Debug)]
 pub struct RequestResponseCollector {
     /// Directory to save the traces to
     traces_dir: PathBuf,
     // current open trace file
-    writer: BufWriter<File>,
+    writer: Option<TraceWriter<TraceFile>>,
     /// the number of the trace we are currently collecting
     trace_no: usize,
+    /// If the current trace ended with a crash
+    crashed: bool,
+    /// Identifies this campaign in the trace headers
+    campaign_id: String,
+    /// The target state new traces are recorded in
+    target_state: Option<TraceTargetState>,
+    /// Which traces to keep
+    policy: TraceStoragePolicy,
+    /// Finished traces without a crash that are kept, oldest first, with their size
+    recent: VecDeque<(PathBuf, u64)>,
+    /// Finished traces with a crash, oldest first, with their size
+    crashing: VecDeque<(PathBuf, u64)>,
 }
 
 impl RequestResponseCollector {
-    /// Creates a new [`RequestResponseCollector`].
+    /// Creates a new [`RequestResponseCollector`] that only keeps crashing traces.
     ///
     /// If any of the trace files that will be created already exists, they will be overwritten.
     ///
@@ -68,8 +436,14 @@ impl RequestResponseCollector {
     /// - Any IO errors
     /// - Path exists but is not a directory
     ///
-    pub fn new(path: &Path) -> Result<Self, Error>
-where {
+    pub fn new(path: &Path) -> Result<Self, Error> {
+        Self::with_policy(path, TraceStoragePolicy::default())
+    }
+
+    /// Creates a new [`RequestResponseCollector`] that keeps traces according to `policy`.
+    ///
+    /// See [`RequestResponseCollector::new`].
+    pub fn with_policy(path: &Path, policy: TraceStoragePolicy) -> Result<Self, Error> {
         // make sure the directory exists.
         match path.exists() {
             true if path.is_dir() => {}
@@ -82,50 +456,258 @@ where {
             _ => unreachable!("All match arms are covered"),
         }
 
-        let trace_no = 0;
-        let file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(path.join(Self::get_filename(trace_no)))?;
-
-        let writer = BufWriter::new(file);
+        // the campaign is named after the output directory the traces are stored in, and when it started
+        let out_dir_name = path
+            .canonicalize()?
+            .parent()
+            .and_then(Path::file_name)
+            .map_or("libaflstar".to_string(), |name| {
+                name.to_string_lossy().to_string()
+            });
+        let campaign_id = format!("{out_dir_name}-{}", current_time().as_secs());
 
-        Ok(Self {
+        let mut collector = Self {
             traces_dir: path.to_path_buf(),
-            writer,
-            trace_no,
-        })
+            writer: None,
+            trace_no: 0,
+            crashed: false,
+            campaign_id,
+            target_state: None,
+            policy,
+            recent: VecDeque::new(),
+            crashing: VecDeque::new(),
+        };
+        collector.open_trace()?;
+        Ok(collector)
     }
 
-    /// Write the request response pair to the current trace, i.e., the open file, serializing it to CBOR.
-    pub fn write_pair(&mut self, pair: &RequestResponsePair) -> Result<(), Error> {
-        // todo remove unwrap and instead bubble up error
-        ciborium::into_writer(pair, &mut self.writer).unwrap();
+    /// Set the target state that the traces started from now on are recorded in.
+    pub fn set_target_state(&mut self, target_state: TraceTargetState) {
+        self.target_state = Some(target_state);
+    }
 
-        Ok(())
+    /// Write the message to the current trace, i.e., the open file, serializing it to CBOR.
+    pub fn write_message(&mut self, message: &TraceMessage) -> Result<(), Error> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.write_message(message),
+            None => Err(Error::illegal_state("No trace is open")),
+        }
     }
 
-    /// Save the trace.
-    /// In actuality, the next time a new trace is started, the current file isn't overwritten
-    /// and therefore saved
-    pub fn save_this_trace(&mut self) {
-        self.trace_no = self.trace_no + 1;
+    /// Save the trace, it ended with a crash.
+    /// The trace is flushed right away, and kept when the next trace is started.
+    pub fn save_this_trace(&mut self) -> Result<(), Error> {
+        self.crashed = true;
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
     }
 
-    /// Start a new trace. If the trace number has no been changed,
-    /// the trace file is overwritten
+    /// Start a new trace. The current trace is overwritten by it, unless it is kept
+    /// according to the [`TraceStoragePolicy`].
     pub fn start_new_trace(&mut self) -> Result<(), Error> {
-        let new_file = OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(self.traces_dir.join(Self::get_filename(self.trace_no)))?;
-        self.writer = BufWriter::new(new_file);
+        self.finish_trace()?;
+        self.open_trace()
+    }
+
+    /// Complete the current trace and decide whether to keep it.
+    fn finish_trace(&mut self) -> Result<(), Error> {
+        let Some(writer) = self.writer.take() else {
+            return Ok(());
+        };
+        // only crashing traces are worth the fsync, the others are likely overwritten soon
+        writer.into_inner()?.finish(self.crashed)?;
+
+        let path = self.trace_path(self.trace_no);
+        if self.crashed {
+            let size = fs::metadata(&path)?.len();
+            self.crashing.push_back((path, size));
+            self.trace_no += 1;
+        } else if self.policy.keep_recent > 0 {
+            let size = fs::metadata(&path)?.len();
+            self.recent.push_back((path, size));
+            self.trace_no += 1;
+            while self.recent.len() > self.policy.keep_recent {
+                if let Some((oldest, _)) = self.recent.pop_front() {
+                    fs::remove_file(oldest)?;
+                }
+            }
+        }
+        self.crashed = false;
+        self.enforce_max_total_size()
+    }
+
+    /// Delete the oldest traces without a crash until the kept traces fit in the maximum total size.
+    /// Crashing traces are never deleted, once they alone exceed the maximum no other traces are kept.
+    fn enforce_max_total_size(&mut self) -> Result<(), Error> {
+        let Some(max_total_size) = self.policy.max_total_size else {
+            return Ok(());
+        };
+        let mut total: u64 = self
+            .recent
+            .iter()
+            .chain(&self.crashing)
+            .map(|(_, size)| size)
+            .sum();
+        while total > max_total_size {
+            let Some((oldest, size)) = self.recent.pop_front() else {
+                break;
+            };
+            fs::remove_file(oldest)?;
+            total -= size;
+        }
         Ok(())
     }
 
-    fn get_filename(trace_no: usize) -> String {
-        format!("trace_{trace_no}.cbor")
+    fn open_trace(&mut self) -> Result<(), Error> {
+        let file = TraceFile::create(&self.trace_path(self.trace_no), self.policy.compress)?;
+        self.writer = Some(TraceWriter::new(
+            file,
+            &TraceHeader::new(&self.campaign_id, self.target_state.clone()),
+        )?);
+        Ok(())
+    }
+
+    fn trace_path(&self, trace_no: usize) -> PathBuf {
+        let extension = if self.policy.compress { ".gz" } else { "" };
+        self.traces_dir
+            .join(format!("trace_{trace_no}.cbor{extension}"))
+    }
+}
+
+impl Drop for RequestResponseCollector {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish_trace() {
+            log::warn!("Could not finish the last replay trace: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libafl::executors::ExitKind;
+    use serde::Serialize;
+
+    use super::{
+        TraceHeader, TraceMessage, TraceReader, TraceTargetState, TraceWriter, TRACE_FORMAT_VERSION,
+    };
+
+    /// A message as it was written before the header existed (format version 0)
+    #[derive(Serialize)]
+    struct V0Pair<'a> {
+        ek: String,
+        req: &'a [u8],
+        resp: &'a [u8],
+    }
+
+    fn read_all(trace: &[u8]) -> Vec<TraceMessage> {
+        TraceReader::new(trace)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_traces_without_header() {
+        let mut trace = Vec::new();
+        for (ek, req, resp) in [
+            (
+                "Ok",
+                b"USER anonymous\r\n".as_slice(),
+                b"331 Password required\r\n".as_slice(),
+            ),
+            ("Cr", b"PASS x\r\n".as_slice(), b"".as_slice()),
+        ] {
+            let pair = V0Pair {
+                ek: ek.to_string(),
+                req,
+                resp,
+            };
+            ciborium::into_writer(&pair, &mut trace).unwrap();
+        }
+
+        let reader = TraceReader::new(trace.as_slice()).unwrap();
+        assert!(reader.header().is_none());
+        assert_eq!(reader.version(), 0);
+        let messages = read_all(&trace);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].ek, "Ok");
+        assert_eq!(messages[0].req, b"USER anonymous\r\n");
+        assert_eq!(messages[0].resp, b"331 Password required\r\n");
+        assert_eq!(messages[1].ek, "Cr");
+        assert_eq!(messages[1].req, b"PASS x\r\n");
+        assert!(messages[1].resp.is_empty());
+        // the metadata of later versions is unknown
+        assert_eq!(
+            (
+                messages[0].time,
+                messages[0].execution,
+                messages[0].latency_us
+            ),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn empty_trace_without_header() {
+        let reader = TraceReader::new(b"".as_slice()).unwrap();
+        assert!(reader.header().is_none());
+        assert_eq!(reader.count(), 0);
+    }
+
+    #[test]
+    fn round_trip_with_header() {
+        let target_state = TraceTargetState {
+            idx: 1,
+            name: "1_username".to_string(),
+            prefix_hash: 42,
+        };
+        let mut writer = TraceWriter::new(
+            Vec::new(),
+            &TraceHeader::new("campaign", Some(target_state.clone())),
+        )
+        .unwrap();
+        let mut message = TraceMessage::new(ExitKind::Timeout, b"LIST\r\n", b"");
+        message.execution = 7;
+        writer.write_message(&message).unwrap();
+        let trace = writer.into_inner().unwrap();
+
+        let reader = TraceReader::new(trace.as_slice()).unwrap();
+        assert_eq!(reader.version(), TRACE_FORMAT_VERSION);
+        let header = reader.header().unwrap();
+        assert_eq!(header.campaign_id, "campaign");
+        assert_eq!(header.target_state, Some(target_state));
+        let messages = read_all(&trace);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].ek, "Tm");
+        assert_eq!(messages[0].req, b"LIST\r\n");
+        assert_eq!(messages[0].execution, 7);
+    }
+
+    #[test]
+    fn partially_written_message_ends_the_trace() {
+        let mut trace = Vec::new();
+        for req in [b"USER a\r\n", b"PASS b\r\n"] {
+            let pair = V0Pair {
+                ek: "Ok".to_string(),
+                req,
+                resp: b"",
+            };
+            ciborium::into_writer(&pair, &mut trace).unwrap();
+        }
+        trace.truncate(trace.len() - 3);
+        let messages = read_all(&trace);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].req, b"USER a\r\n");
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let mut header = TraceHeader::new("campaign", None);
+        header.version = TRACE_FORMAT_VERSION + 1;
+        let mut trace = Vec::new();
+        ciborium::into_writer(&header, &mut trace).unwrap();
+        assert!(TraceReader::new(trace.as_slice()).is_err());
     }
 }
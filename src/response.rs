@@ -0,0 +1,157 @@
+//! Look at the responses of the target, e.g. at their status codes.
+//!
+//! Executors that read the responses expose them through [`HasLastResponse`]. Feedbacks only get to see the observers,
+//! so the [`crate::executor::forkserver::ForkserverExecutor`] can also keep the response in a [`SharedResponse`],
+//! from which the [`ResponseObserver`] takes it after each execution.
+
+use std::sync::{Arc, Mutex};
+
+use libafl::{executors::ExitKind, inputs::UsesInput, observers::Observer, Error};
+use libafl_bolts::Named;
+use serde::{Deserialize, Serialize};
+
+/// Executors that can tell what the target responded to the last input.
+pub trait HasLastResponse {
+    /// The response to the last input, `None` if there was none or it was not read.
+    fn last_response(&self) -> Option<&[u8]>;
+}
+
+/// The status code of a response of the text based protocols of the case studies.
+///
+/// Recognizes HTTP and RTSP status lines (`HTTP/1.1 200 OK`) and FTP (or SMTP) replies (`230 Login successful`),
+/// for multi-line replies the code of the first line is returned.
+pub fn status_code(response: &[u8]) -> Option<u16> {
+    let first_line = response.split(|&byte| byte == b'\n').next()?;
+    let first_line = first_line.strip_suffix(b"\r").unwrap_or(first_line);
+
+    let code = if first_line.starts_with(b"HTTP/") || first_line.starts_with(b"RTSP/") {
+        first_line.split(|&byte| byte == b' ').nth(1)?
+    } else {
+        let code = first_line.get(..3)?;
+        match first_line.get(3) {
+            None | Some(b' ' | b'-') => code,
+            Some(_) => return None,
+        }
+    };
+    if code.len() != 3 || !code.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(code).ok()?.parse().ok()
+}
+
+/// The response of the target to the last input.
+///
+/// Cloning gives a handle to the same response.
+#[derive(Debug, Clone, Default)]
+pub struct SharedResponse {
+    inner: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+impl SharedResponse {
+    /// Replace the response, `None` if there was none or it could not be read.
+    pub fn set(&self, response: Option<Vec<u8>>) {
+        *self.inner.lock().unwrap() = response;
+    }
+
+    /// A copy of the response.
+    pub fn get(&self) -> Option<Vec<u8>> {
+        self.inner.lock().unwrap().clone()
+    }
+}
+
+/// Keeps the response of the target to the last input.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseObserver {
+    name: String,
+    #[serde(skip)]
+    shared_response: SharedResponse,
+    response: Option<Vec<u8>>,
+}
+
+impl ResponseObserver {
+    /// Create a new [`ResponseObserver`] reading from `shared_response`, which should be the response that is given
+    /// to the executor.
+    pub fn new(name: &str, shared_response: SharedResponse) -> Self {
+        Self {
+            name: name.to_string(),
+            shared_response,
+            response: None,
+        }
+    }
+
+    /// The response to the last input, `None` if there was none or it was not read.
+    pub fn response(&self) -> Option<&[u8]> {
+        self.response.as_deref()
+    }
+}
+
+impl Named for ResponseObserver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<S> Observer<S> for ResponseObserver
+where
+    S: UsesInput,
+{
+    fn pre_exec(&mut self, _state: &mut S, _input: &S::Input) -> Result<(), Error> {
+        self.response = None;
+        Ok(())
+    }
+
+    fn post_exec(
+        &mut self,
+        _state: &mut S,
+        _input: &S::Input,
+        _exit_kind: &ExitKind,
+    ) -> Result<(), Error> {
+        self.response = self.shared_response.get();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::status_code;
+
+    #[test]
+    fn status_lines() {
+        assert_eq!(
+            status_code(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"),
+            Some(200)
+        );
+        assert_eq!(
+            status_code(b"RTSP/1.0 454 Session Not Found\r\n"),
+            Some(454)
+        );
+        assert_eq!(status_code(b"HTTP/1.0 404\r\n"), Some(404));
+    }
+
+    #[test]
+    fn ftp_replies() {
+        assert_eq!(status_code(b"230 Login successful.\r\n"), Some(230));
+        assert_eq!(status_code(b"220\r\n"), Some(220));
+        assert_eq!(status_code(b"220"), Some(220));
+        // multi-line replies have the code of their first line
+        assert_eq!(
+            status_code(b"211-Features:\r\n MDTM\r\n211 End\r\n"),
+            Some(211)
+        );
+        assert_eq!(status_code(b"250 OK\n"), Some(250));
+    }
+
+    #[test]
+    fn no_status_code() {
+        assert_eq!(status_code(b""), None);
+        assert_eq!(status_code(b"\r\n"), None);
+        assert_eq!(status_code(b"23"), None);
+        assert_eq!(status_code(b"2301 too long\r\n"), None);
+        assert_eq!(status_code(b"abc def\r\n"), None);
+        assert_eq!(status_code(b"HTTP/1.1 OK\r\n"), None);
+        assert_eq!(status_code(b"HTTP/1.1 2000 OK\r\n"), None);
+        assert_eq!(status_code(b"HTTP/1.1"), None);
+        // the code must be at the start of the response
+        assert_eq!(status_code(b"\r\n200 OK\r\n"), None);
+    }
+}
@@ -0,0 +1,396 @@
+//! Stability of the coverage in persistent stateful execution.
+//!
+//! In persistent mode, the session state and the global state of the child depend on everything it received before,
+//! so the same input does not always cover the same edges. Such unstable edges make the [`MaxMapFeedback`] add inputs
+//! to the corpus that did nothing new. The [`StabilityStage`] executes each new corpus entry a few times in its target
+//! state, each time after a fresh prefix, finds the edges whose hit counts differ between the runs, and masks them out
+//! of the history of the feedback. The [`Stability`] of each target state is reported like AFL++'s, as the share of
+//! covered edges that are stable.
+//!
+//! Long-running children also accumulate global state and leak memory. The [`ChildRestartStage`] restarts the child
+//! when the [`RestartPolicy`] of the executor says so, and compares the coverage of a corpus entry in the old child
+//! with its coverage in the fresh one.
+//!
+//! [`MaxMapFeedback`]: libafl::feedbacks::MaxMapFeedback
+//! [`RestartPolicy`]: crate::executor::RestartPolicy
+
+use std::{collections::HashSet, marker::PhantomData};
+
+use libafl::{
+    corpus::{Corpus, CorpusId, HasCurrentCorpusIdx},
+    events::{Event, EventFirer},
+    executors::{ExitKind, HasObservers},
+    feedbacks::MapFeedbackMetadata,
+    inputs::UsesInput,
+    monitors::{AggregatorOps, UserStats, UserStatsValue},
+    observers::MapObserver,
+    stages::Stage,
+    state::{HasCorpus, HasExecutions, HasNamedMetadata, State, UsesState},
+    Error, ExecutesInput,
+};
+use libafl_bolts::{tuples::MatchName, Named};
+use serde::{Deserialize, Serialize};
+
+use crate::{executor::ResettableForkserver, state::MultipleStates};
+
+/// Prefix of the name of the metadata of a `MapFeedback`, followed by the name of its observer.
+const MAPFEEDBACK_PREFIX: &str = "mapfeedback_metadata_";
+
+/// The stability of the coverage in a target state, see [`MultipleStates::stability`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stability {
+    /// The corpus entries that were executed repeatedly in this target state
+    pub calibrated: HashSet<CorpusId>,
+    /// The edges covered by these executions
+    pub covered_edges: HashSet<usize>,
+    /// The covered edges whose hit counts differed between executions of the same input
+    pub unstable_edges: HashSet<usize>,
+    /// Restarts of the child in this target state, see [`ChildRestartStage`]
+    #[serde(default)]
+    pub restarts: usize,
+    /// Restarts where a corpus entry ended well in both the old and the fresh child, so their coverage was compared
+    #[serde(default)]
+    pub restarts_compared: usize,
+    /// Compared restarts where the corpus entry covered the same stable edges in the old and in the fresh child
+    #[serde(default)]
+    pub restarts_same_coverage: usize,
+}
+
+impl Stability {
+    /// The number of covered edges that are stable.
+    pub fn stable_edges(&self) -> usize {
+        self.covered_edges.len() - self.unstable_edges.len()
+    }
+
+    /// The share of covered edges that are stable, 1 if nothing was covered yet.
+    pub fn ratio(&self) -> f64 {
+        if self.covered_edges.is_empty() {
+            1.0
+        } else {
+            self.stable_edges() as f64 / self.covered_edges.len() as f64
+        }
+    }
+
+    /// If `before` and `after` have the same hit counts, apart from the edges that are known to be unstable.
+    fn same_stable_coverage(&self, before: &[u8], after: &[u8]) -> bool {
+        (0..before.len().max(after.len())).all(|idx| {
+            before.get(idx) == after.get(idx) || self.unstable_edges.contains(&idx)
+        })
+    }
+}
+
+/// The inputs of the prefix of the current target state.
+fn prefix_inputs<S>(state: &S) -> Vec<S::Input>
+where
+    S: MultipleStates,
+{
+    state
+        .prefix()
+        .prefix
+        .iter()
+        .map(|testcase| {
+            testcase
+                .input()
+                .clone()
+                .expect("Prefix testcases should always have input")
+        })
+        .collect()
+}
+
+/// The input of the corpus entry `corpus_idx`.
+fn corpus_input<S>(state: &S, corpus_idx: CorpusId) -> Result<S::Input, Error>
+where
+    S: HasCorpus,
+{
+    Ok(state
+        .corpus()
+        .get(corpus_idx)?
+        .borrow_mut()
+        .load_input(state.corpus())?
+        .clone())
+}
+
+/// Reset the target and send the `prefix`.
+fn restart<E, EM, Z>(
+    fuzzer: &mut Z,
+    executor: &mut E,
+    state: &mut Z::State,
+    manager: &mut EM,
+    prefix: &[<Z::State as UsesInput>::Input],
+) -> Result<(), Error>
+where
+    E: UsesState<State = Z::State> + ResettableForkserver,
+    EM: UsesState<State = Z::State>,
+    Z: ExecutesInput<E, EM>,
+{
+    executor.reset_target_state()?;
+    for message in prefix {
+        fuzzer.execute_input(state, executor, manager, message)?;
+    }
+    Ok(())
+}
+
+/// Execute `input` and return the map of the map observer `map_observer_name`, if the execution ended well.
+fn execute_for_map<E, EM, Z, O>(
+    fuzzer: &mut Z,
+    executor: &mut E,
+    state: &mut Z::State,
+    manager: &mut EM,
+    input: &<Z::State as UsesInput>::Input,
+    map_observer_name: &str,
+) -> Result<Option<Vec<u8>>, Error>
+where
+    E: UsesState<State = Z::State> + HasObservers,
+    EM: UsesState<State = Z::State>,
+    Z: ExecutesInput<E, EM>,
+    O: MapObserver<Entry = u8>,
+{
+    if fuzzer.execute_input(state, executor, manager, input)? != ExitKind::Ok {
+        return Ok(None);
+    }
+    let map = executor
+        .observers()
+        .match_name::<O>(map_observer_name)
+        .ok_or_else(|| Error::key_not_found("MapObserver not found"))?
+        .to_vec();
+    Ok(Some(map))
+}
+
+/// Executes each new corpus entry `runs` times in the current target state, each time after resetting the target and
+/// sending the prefix, and masks the edges whose hit counts differ between the runs out of the history of the
+/// `MaxMapFeedback` of the map observer.
+///
+/// Afterwards, the target is reset and the prefix is sent again, so the next stages start in the target state.
+#[derive(Debug, Clone)]
+pub struct StabilityStage<S, O> {
+    map_observer_name: String,
+    feedback_name: String,
+    runs: usize,
+    phantom: PhantomData<(S, O)>,
+}
+
+impl<S, O> StabilityStage<S, O>
+where
+    O: MapObserver<Entry = u8>,
+{
+    /// Create a new [`StabilityStage`] for the `map_observer` of the `MaxMapFeedback` (created with
+    /// `MaxMapFeedback::tracking` or `MaxMapFeedback::new`), that executes each new corpus entry `runs` times.
+    /// With fewer than 2 runs, it does nothing.
+    pub fn new(map_observer: &O, runs: usize) -> Self {
+        Self {
+            map_observer_name: map_observer.name().to_string(),
+            feedback_name: format!("{MAPFEEDBACK_PREFIX}{}", map_observer.name()),
+            runs,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<S, O> UsesState for StabilityStage<S, O>
+where
+    S: State,
+{
+    type State = S;
+}
+
+impl<E, EM, Z, S, O> Stage<E, EM, Z> for StabilityStage<S, O>
+where
+    E: UsesState<State = S> + HasObservers + ResettableForkserver,
+    EM: UsesState<State = S> + EventFirer,
+    Z: ExecutesInput<E, EM, State = S>,
+    S: State + MultipleStates + HasCorpus + HasCurrentCorpusIdx + HasNamedMetadata + HasExecutions,
+    O: MapObserver<Entry = u8>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut S,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        let Some(corpus_idx) = state.current_corpus_idx()? else {
+            return Err(Error::illegal_state(
+                "state is not currently processing a corpus index",
+            ));
+        };
+        if self.runs < 2 || !state.stability().calibrated.insert(corpus_idx) {
+            return Ok(());
+        }
+
+        let input = corpus_input(state, corpus_idx)?;
+        let prefix = prefix_inputs(state);
+
+        let mut maps: Vec<Vec<u8>> = Vec::with_capacity(self.runs);
+        for _ in 0..self.runs {
+            restart(fuzzer, executor, state, manager, &prefix)?;
+            // only runs that end like the first one (as the input was added to the corpus) are comparable
+            let Some(map) = execute_for_map::<E, EM, Z, O>(
+                fuzzer,
+                executor,
+                state,
+                manager,
+                &input,
+                &self.map_observer_name,
+            )?
+            else {
+                log::debug!("Stopped measuring the stability of {corpus_idx}, it did not end with Ok");
+                break;
+            };
+            maps.push(map);
+        }
+        restart(fuzzer, executor, state, manager, &prefix)?;
+
+        let Some((first, others)) = maps.split_first() else {
+            return Ok(());
+        };
+        let mut unstable = Vec::new();
+        for (idx, &hits) in first.iter().enumerate() {
+            let varies = others.iter().any(|map| map.get(idx).copied().unwrap_or(0) != hits);
+            if hits != 0 || varies {
+                state.stability().covered_edges.insert(idx);
+            }
+            if varies && state.stability().unstable_edges.insert(idx) {
+                unstable.push(idx);
+            }
+        }
+
+        // an unstable edge never counts as new coverage again. An edge that is not in the history yet was only reached
+        // by some of these runs, it is masked without counting it as covered, so the `edges` stat only counts edges
+        // that the feedback saw
+        if !unstable.is_empty() {
+            log::debug!("Found {} new unstable edges with {corpus_idx}", unstable.len());
+            if let Ok(metadata) =
+                state.named_metadata_mut::<MapFeedbackMetadata<u8>>(&self.feedback_name)
+            {
+                for idx in unstable {
+                    if idx >= metadata.history_map.len() {
+                        metadata.history_map.resize(idx + 1, 0);
+                    }
+                    metadata.history_map[idx] = u8::MAX;
+                }
+            }
+        }
+
+        let stability = state.stability();
+        let (stable, covered) = (stability.stable_edges(), stability.covered_edges.len());
+        manager.fire(
+            state,
+            Event::UpdateUserStats {
+                name: "stability".to_string(),
+                value: UserStats::new(
+                    UserStatsValue::Ratio(stable as u64, covered as u64),
+                    AggregatorOps::Avg,
+                ),
+                phantom: PhantomData,
+            },
+        )?;
+        Ok(())
+    }
+}
+
+/// Restarts the child when the [`RestartPolicy`](crate::executor::RestartPolicy) of the executor says so (see
+/// [`ResettableForkserver::restart_due`]), and sends the prefix again.
+///
+/// Before the restart, the current corpus entry is executed in the old child, and afterwards in the fresh one. If it
+/// covers the same edges in both, apart from the known unstable edges, the old child had not drifted away from a fresh
+/// one. How often that is the case is reported per target state as `restart_stability`, next to `restarts`.
+/// Put it before the other stages.
+#[derive(Debug, Clone)]
+pub struct ChildRestartStage<S, O> {
+    map_observer_name: String,
+    phantom: PhantomData<(S, O)>,
+}
+
+impl<S, O> ChildRestartStage<S, O>
+where
+    O: MapObserver<Entry = u8>,
+{
+    /// Create a new [`ChildRestartStage`] comparing the coverage of the `map_observer`.
+    pub fn new(map_observer: &O) -> Self {
+        Self {
+            map_observer_name: map_observer.name().to_string(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<S, O> UsesState for ChildRestartStage<S, O>
+where
+    S: State,
+{
+    type State = S;
+}
+
+impl<E, EM, Z, S, O> Stage<E, EM, Z> for ChildRestartStage<S, O>
+where
+    E: UsesState<State = S> + HasObservers + ResettableForkserver,
+    EM: UsesState<State = S> + EventFirer,
+    Z: ExecutesInput<E, EM, State = S>,
+    S: State + MultipleStates + HasCorpus + HasCurrentCorpusIdx + HasExecutions,
+    O: MapObserver<Entry = u8>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut S,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        let Some(reason) = executor.restart_due() else {
+            return Ok(());
+        };
+        let Some(corpus_idx) = state.current_corpus_idx()? else {
+            return Err(Error::illegal_state(
+                "state is not currently processing a corpus index",
+            ));
+        };
+        log::debug!("Restarting the child: {reason:?}");
+        let input = corpus_input(state, corpus_idx)?;
+        let prefix = prefix_inputs(state);
+
+        let name = &self.map_observer_name;
+        let before = execute_for_map::<E, EM, Z, O>(fuzzer, executor, state, manager, &input, name)?;
+        restart(fuzzer, executor, state, manager, &prefix)?;
+        let after = execute_for_map::<E, EM, Z, O>(fuzzer, executor, state, manager, &input, name)?;
+        restart(fuzzer, executor, state, manager, &prefix)?;
+
+        let stability = state.stability();
+        stability.restarts += 1;
+        if let (Some(before), Some(after)) = (before, after) {
+            stability.restarts_compared += 1;
+            if stability.same_stable_coverage(&before, &after) {
+                stability.restarts_same_coverage += 1;
+            } else {
+                log::debug!("{corpus_idx} covered different edges before and after the restart");
+            }
+        }
+        let (restarts, compared, same) = (
+            stability.restarts,
+            stability.restarts_compared,
+            stability.restarts_same_coverage,
+        );
+
+        manager.fire(
+            state,
+            Event::UpdateUserStats {
+                name: "restarts".to_string(),
+                value: UserStats::new(UserStatsValue::Number(restarts as u64), AggregatorOps::Sum),
+                phantom: PhantomData,
+            },
+        )?;
+        if compared > 0 {
+            manager.fire(
+                state,
+                Event::UpdateUserStats {
+                    name: "restart_stability".to_string(),
+                    value: UserStats::new(
+                        UserStatsValue::Ratio(same as u64, compared as u64),
+                        AggregatorOps::Avg,
+                    ),
+                    phantom: PhantomData,
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
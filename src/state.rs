@@ -9,6 +9,7 @@
 use std::{
     any::type_name,
     cell::{Ref, RefMut},
+    collections::BTreeMap,
     fs::{self, OpenOptions},
     io::{BufWriter, Write},
     marker::PhantomData,
@@ -35,7 +36,7 @@ use libafl_bolts::{
 };
 use serde::{Deserialize, Serialize};
 
-use crate::{executor::ResettableForkserver, fuzzer};
+use crate::{executor::ResettableForkserver, fuzzer, replay, stability::Stability};
 
 /// Depending on the mode, components accessing this state get different information.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -79,7 +80,9 @@ where
         log::info!("Loading prefix with dir name: {:?}", dir.path());
 
         let mut prefix = Vec::new();
+        let mut raw_messages = Vec::new();
         let mut metadata = None;
+        let mut tags = BTreeMap::new();
 
         let mut prefix_files = dir.path().read_dir()?.collect::<Result<Vec<_>, _>>()?;
         prefix_files.sort_by_key(|f| f.path());
@@ -97,7 +100,19 @@ where
                     ))
                 })?;
 
-                metadata = Some(PrefixMetadata { outgoing_edges });
+                metadata = Some(PrefixMetadata {
+                    outgoing_edges,
+                    name: dir.file_name().to_string_lossy().to_string(),
+                    prefix_hash: 0,
+                    tags: BTreeMap::new(),
+                });
+            } else if file.file_name() == "tags" {
+                tags = parse_tags(&fs::read_to_string(file.path())?).map_err(|e| {
+                    Error::illegal_state(format!(
+                        "Could not parse prefix tags in {}: {e}",
+                        dir.path().to_string_lossy()
+                    ))
+                })?;
             } else {
                 match <C::Input>::from_file(file.path()) {
                     Ok(input) => {
@@ -107,6 +122,7 @@ where
                         );
                         log::debug!("Loaded prefix input: {:?}", testcase);
                         prefix.push(testcase);
+                        raw_messages.push(fs::read(file.path())?);
                     }
                     Err(e) => {
                         return Err(Error::illegal_state(format!(
@@ -116,7 +132,9 @@ where
                 };
             }
         }
-        if let Some(meta) = metadata {
+        if let Some(mut meta) = metadata {
+            meta.prefix_hash = replay::hash_messages(raw_messages.iter().map(Vec::as_slice));
+            meta.tags = tags;
             prefixes.push(Prefix {
                 prefix,
                 metadata: meta,
@@ -207,6 +225,31 @@ where
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PrefixMetadata {
     pub outgoing_edges: usize,
+    /// Name of the target state, i.e., the name of the prefix directory
+    #[serde(default)]
+    pub name: String,
+    /// Hash of the prefix messages, see [`crate::replay::hash_messages`]
+    #[serde(default)]
+    pub prefix_hash: u64,
+    /// Labels of the target state, e.g. `authenticated=false`, read from the optional `tags` file of the prefix
+    /// directory, see [`parse_tags`]
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Parse the tags of a target state: one `key=value` pair per line, empty lines and lines starting with `#` are
+/// skipped.
+pub fn parse_tags(content: &str) -> Result<BTreeMap<String, String>, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.split_once('=')
+                .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                .ok_or_else(|| format!("invalid key=value: no `=` found in `{line}`"))
+        })
+        .collect()
 }
 
 /// Modified version of the LibAFL state, extended to work with stateful targets.
@@ -269,6 +312,21 @@ struct InnerState<C> {
     pub executions: usize,
     /// Number of times this state is chosen to fuzz
     pub fuzz_cycles: usize,
+    /// Number of confirmed hangs in this state
+    #[serde(default)]
+    pub hangs: usize,
+    /// Number of executions in this state where the target ran out of memory
+    #[serde(default)]
+    pub ooms: usize,
+    /// Timeout of the executions in this state, if it was calibrated
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    /// Stability of the coverage in this state
+    #[serde(default)]
+    pub stability: Stability,
+    /// Number of times an input moved the target out of this state
+    #[serde(default)]
+    pub drifts: usize,
     /// Number of outgoing edges in the State Machine.
     /// Used for restarting
     pub corpus_idx: Option<CorpusId>,
@@ -294,6 +352,11 @@ impl<C> InnerState<C> {
             imported: 0,
             executions: 0,
             fuzz_cycles: 0,
+            hangs: 0,
+            ooms: 0,
+            timeout: None,
+            stability: Stability::default(),
+            drifts: 0,
             corpus_idx: None,
             stage_idx_stack: Vec::new(),
             stage_depth: 0,
@@ -444,6 +507,18 @@ pub trait MultipleStates: State + HasCorpus {
     fn states_len(&self) -> usize;
     /// Variable keeping track of how often the current target state has been fuzzed
     fn fuzz_cycles(&mut self) -> &mut usize;
+    /// Variable keeping track of how many confirmed hangs were stored for the current target state
+    fn hangs(&mut self) -> &mut usize;
+    /// Variable keeping track of how often the target ran out of memory in the current target state
+    fn ooms(&mut self) -> &mut usize;
+    /// The timeout of the executions in the current target state, if it was calibrated.
+    /// Applied to the executor when the fuzzer switches to the target state, see [`fuzzer::change_target_state`].
+    fn timeout(&mut self) -> &mut Option<Duration>;
+    /// The stability of the coverage in the current target state, see [`crate::stability::StabilityStage`]
+    fn stability(&mut self) -> &mut Stability;
+    /// Variable keeping track of how often an input moved the target out of the current target state, see
+    /// [`crate::drift::DriftStage`]
+    fn drifts(&mut self) -> &mut usize;
     /// Get the number of outgoing edges of this state in the state machine of the SUT.
     /// Arguably, this should be in its own trait. But, meh. Will be refactored if states get more initial metadata
     fn outgoing_edges(&self) -> usize;
@@ -753,6 +828,31 @@ where
         &mut self.inner_mut().fuzz_cycles
     }
 
+    #[inline]
+    fn hangs(&mut self) -> &mut usize {
+        &mut self.inner_mut().hangs
+    }
+
+    #[inline]
+    fn ooms(&mut self) -> &mut usize {
+        &mut self.inner_mut().ooms
+    }
+
+    #[inline]
+    fn timeout(&mut self) -> &mut Option<Duration> {
+        &mut self.inner_mut().timeout
+    }
+
+    #[inline]
+    fn stability(&mut self) -> &mut Stability {
+        &mut self.inner_mut().stability
+    }
+
+    #[inline]
+    fn drifts(&mut self) -> &mut usize {
+        &mut self.inner_mut().drifts
+    }
+
     fn outgoing_edges(&self) -> usize {
         self.prefix().metadata.outgoing_edges
     }
@@ -1124,6 +1224,82 @@ where
             .map(|(id, inner)| (id, inner.fuzz_cycles))
             .collect::<Vec<_>>();
         writer.write_all(format!("cycles_per_state (id, #cycles): {:?}\n", cycles).as_bytes())?;
+        let hangs = self
+            .inner
+            .iter()
+            .enumerate()
+            .map(|(id, inner)| (id, inner.hangs))
+            .collect::<Vec<_>>();
+        writer.write_all(format!("hangs_per_state (id, #hangs): {:?}\n", hangs).as_bytes())?;
+        let ooms = self
+            .inner
+            .iter()
+            .enumerate()
+            .map(|(id, inner)| (id, inner.ooms))
+            .collect::<Vec<_>>();
+        writer.write_all(format!("ooms_per_state (id, #ooms): {:?}\n", ooms).as_bytes())?;
+        let drifts = self
+            .inner
+            .iter()
+            .enumerate()
+            .map(|(id, inner)| (id, inner.drifts))
+            .collect::<Vec<_>>();
+        writer.write_all(format!("drifts_per_state (id, #drifts): {:?}\n", drifts).as_bytes())?;
+        let stability = self
+            .inner
+            .iter()
+            .enumerate()
+            .filter(|(_, inner)| !inner.stability.calibrated.is_empty())
+            .map(|(id, inner)| {
+                (
+                    id,
+                    format!("{:.2}%", inner.stability.ratio() * 100.0),
+                    inner.stability.unstable_edges.len(),
+                )
+            })
+            .collect::<Vec<_>>();
+        if !stability.is_empty() {
+            writer.write_all(
+                format!("stability_per_state (id, stability, #unstable edges): {:?}\n", stability)
+                    .as_bytes(),
+            )?;
+        }
+        let restarts = self
+            .inner
+            .iter()
+            .enumerate()
+            .filter(|(_, inner)| inner.stability.restarts > 0)
+            .map(|(id, inner)| {
+                (
+                    id,
+                    inner.stability.restarts,
+                    format!(
+                        "{}/{}",
+                        inner.stability.restarts_same_coverage, inner.stability.restarts_compared
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+        if !restarts.is_empty() {
+            writer.write_all(
+                format!(
+                    "restarts_per_state (id, #restarts, same coverage before and after/compared): {:?}\n",
+                    restarts
+                )
+                .as_bytes(),
+            )?;
+        }
+        let timeouts = self
+            .inner
+            .iter()
+            .enumerate()
+            .filter_map(|(id, inner)| inner.timeout.map(|timeout| (id, timeout.as_millis())))
+            .collect::<Vec<_>>();
+        if !timeouts.is_empty() {
+            writer.write_all(
+                format!("timeout_per_state (id, ms): {:?}\n", timeouts).as_bytes(),
+            )?;
+        }
 
         writer.write_all(format!("type_names: {:#?}\n", type_names).as_bytes())?;
 
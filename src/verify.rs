@@ -0,0 +1,203 @@
+//! Check if a solution can be reproduced, and if it needs its history to be reproduced.
+//!
+//! In stateful persistent mode a crash may depend on everything the child received before the crashing input, or on
+//! things outside of our control (timing, other processes). A [`CrashRecord`] holds the conversation that led to a
+//! solution. [`verify_record`] replays it to a freshly reset target a number of times, once with the full history and
+//! once with only the prefix of the target state followed by the input, and classifies both as [`Reproducibility`].
+
+use std::fmt::{self, Display, Formatter};
+
+use libafl::{
+    executors::{Executor, ExitKind},
+    inputs::{BytesInput, UsesInput},
+    state::UsesState,
+    Error,
+};
+
+use crate::{crash_record::CrashRecord, executor::ResettableForkserver, replay::exit_kind_str};
+
+/// How reliably a solution is reproduced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reproducibility {
+    /// Every attempt reproduced the solution
+    Reproducible,
+    /// Some attempts reproduced the solution
+    Flaky,
+    /// No attempt reproduced the solution
+    NonReproducible,
+}
+
+impl Display for Reproducibility {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Reproducibility::Reproducible => write!(f, "reproducible"),
+            Reproducibility::Flaky => write!(f, "flaky"),
+            Reproducibility::NonReproducible => write!(f, "non-reproducible"),
+        }
+    }
+}
+
+/// The outcome of replaying the same messages a number of times.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Attempts {
+    /// Number of attempts
+    pub total: usize,
+    /// Attempts where the last message ended with the expected exit kind
+    pub reproduced: usize,
+    /// Attempts where the target did not survive until the last message
+    pub ended_early: usize,
+}
+
+impl Attempts {
+    /// Classify the attempts.
+    pub fn reproducibility(&self) -> Reproducibility {
+        match self.reproduced {
+            0 => Reproducibility::NonReproducible,
+            n if n == self.total => Reproducibility::Reproducible,
+            _ => Reproducibility::Flaky,
+        }
+    }
+}
+
+impl Display for Attempts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}/{}",
+            self.reproducibility(),
+            self.reproduced,
+            self.total
+        )?;
+        if self.ended_early > 0 {
+            write!(f, ", {} ended early", self.ended_early)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// The outcome of [`verify_record`].
+#[derive(Debug, Clone, Copy)]
+pub struct Verification {
+    /// Replaying the prefix, the history and the input
+    pub with_history: Attempts,
+    /// Replaying only the prefix and the input
+    pub bare: Attempts,
+}
+
+impl Verification {
+    /// If the history is needed, i.e., the solution is reproduced with the history but never without it.
+    pub fn history_needed(&self) -> bool {
+        self.with_history.reproduced > 0 && self.bare.reproduced == 0
+    }
+}
+
+/// Reset the target and send it `messages` in order, until one of them does not end with [`ExitKind::Ok`].
+///
+/// Returns how the last execution ended, and the index of the message that ended it.
+pub fn replay_messages<E, EM, Z>(
+    executor: &mut E,
+    fuzzer: &mut Z,
+    state: &mut E::State,
+    mgr: &mut EM,
+    messages: &[Vec<u8>],
+) -> Result<(ExitKind, usize), Error>
+where
+    E: Executor<EM, Z> + ResettableForkserver + UsesInput<Input = BytesInput>,
+    EM: UsesState<State = E::State>,
+    Z: UsesState<State = E::State>,
+{
+    executor.reset_target_state()?;
+    let mut last = (ExitKind::Ok, 0);
+    for (idx, message) in messages.iter().enumerate() {
+        let input = BytesInput::new(message.clone());
+        let exit_kind = executor.run_target(fuzzer, state, mgr, &input)?;
+        last = (exit_kind, idx);
+        if exit_kind != ExitKind::Ok {
+            break;
+        }
+    }
+    Ok(last)
+}
+
+/// Replay `messages` `attempts` times, expecting the last message to end with `expected` (see [`exit_kind_str`]).
+pub fn replay_attempts<E, EM, Z>(
+    executor: &mut E,
+    fuzzer: &mut Z,
+    state: &mut E::State,
+    mgr: &mut EM,
+    messages: &[Vec<u8>],
+    expected: &str,
+    attempts: usize,
+) -> Result<Attempts, Error>
+where
+    E: Executor<EM, Z> + ResettableForkserver + UsesInput<Input = BytesInput>,
+    EM: UsesState<State = E::State>,
+    Z: UsesState<State = E::State>,
+{
+    let mut result = Attempts {
+        total: attempts,
+        ..Attempts::default()
+    };
+    for _ in 0..attempts {
+        let (exit_kind, idx) = replay_messages(executor, fuzzer, state, mgr, messages)?;
+        if idx + 1 < messages.len() {
+            result.ended_early += 1;
+        } else if exit_kind_str(exit_kind) == expected {
+            result.reproduced += 1;
+        }
+    }
+    Ok(result)
+}
+
+/// The prefix, the history and the input of the record, without sending the prefix twice.
+///
+/// The history already starts with the prefix if it was sent to the child the solution was found in.
+pub fn full_conversation(record: &CrashRecord) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    if !record.history.starts_with(&record.prefix) {
+        messages.extend(record.prefix.iter().cloned());
+    }
+    messages.extend(record.history.iter().cloned());
+    messages.push(record.input.clone());
+    messages
+}
+
+/// Replay `record` `attempts` times with and without its history.
+pub fn verify_record<E, EM, Z>(
+    executor: &mut E,
+    fuzzer: &mut Z,
+    state: &mut E::State,
+    mgr: &mut EM,
+    record: &CrashRecord,
+    attempts: usize,
+) -> Result<Verification, Error>
+where
+    E: Executor<EM, Z> + ResettableForkserver + UsesInput<Input = BytesInput>,
+    EM: UsesState<State = E::State>,
+    Z: UsesState<State = E::State>,
+{
+    let full = full_conversation(record);
+    let with_history = replay_attempts(
+        executor,
+        fuzzer,
+        state,
+        mgr,
+        &full,
+        &record.exit_kind,
+        attempts,
+    )?;
+
+    let mut bare_messages = record.prefix.clone();
+    bare_messages.push(record.input.clone());
+    let bare = replay_attempts(
+        executor,
+        fuzzer,
+        state,
+        mgr,
+        &bare_messages,
+        &record.exit_kind,
+        attempts,
+    )?;
+
+    Ok(Verification { with_history, bare })
+}
@@ -0,0 +1,187 @@
+//! Snapshot and restore of the directory the target works in.
+//!
+//! Targets change the file system while they are being fuzzed, e.g., an FTP server creates, renames and deletes files
+//! through `STOR`, `RNFR`/`RNTO`, `MKD` and `DELE`. Over a campaign the served directory drifts away from what the prefixes
+//! expect, and the prefixes stop reaching their target states.
+//!
+//! A [`WorkdirSnapshot`] keeps a template directory and copies it over the working directory of the target
+//! whenever it is restored. When it is restored is decided by the [`RestorePoint`], the executor
+//! ([`crate::executor::StatefulPersistentExecutor`]) takes care of calling [`WorkdirSnapshot::restore`] at the right moments.
+
+use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use libafl::Error;
+
+/// Directory in which working directories are created when they should live on a tmpfs.
+const TMPFS_DIR: &str = "/dev/shm";
+
+/// When the working directory is restored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RestorePoint {
+    /// Each time a (new) target state is selected, right after the child is killed.
+    StateSwitch,
+    /// Each time a new child is started, which includes each state switch,
+    /// but also restarts after a crash or timeout.
+    ChildRestart,
+}
+
+/// Copy of a template directory that is restored onto the working directory of the target.
+#[derive(Debug)]
+pub struct WorkdirSnapshot {
+    /// The pristine directory
+    template: PathBuf,
+    /// The directory the target works in, that is, the directory that is restored.
+    workdir: PathBuf,
+    /// The actual directory on the tmpfs, if any. `workdir` is a symlink to it.
+    tmpfs_dir: Option<PathBuf>,
+    /// When to restore
+    restore_point: RestorePoint,
+    /// Number of times the working directory was restored
+    restores: usize,
+}
+
+impl WorkdirSnapshot {
+    /// Create a new [`WorkdirSnapshot`] and restore the working directory a first time.
+    ///
+    /// - `template`: Directory that is copied to the working directory on each restore. It is never modified.
+    /// - `workdir`: The working directory of the target, this is the path the target is configured with.
+    /// - `restore_point`: When to restore the working directory.
+    /// - `tmpfs`: Keep the working directory on a tmpfs. The directory is created in `/dev/shm` and
+    ///            `workdir` becomes a symlink to it, so the target configuration does not have to change.
+    ///            `workdir` must then either not exist, or already be a symlink.
+    ///
+    /// # Errors:
+    ///
+    /// - Any IO errors
+    /// - `template` is not a directory
+    /// - `workdir` exists but is not a directory (or a symlink when using a tmpfs)
+    pub fn new(
+        template: &Path,
+        workdir: &Path,
+        restore_point: RestorePoint,
+        tmpfs: bool,
+    ) -> Result<Self, Error> {
+        if !template.is_dir() {
+            return Err(Error::illegal_argument(format!(
+                "Working directory template [{}] is not a directory.",
+                template.display()
+            )));
+        }
+
+        let tmpfs_dir = if tmpfs {
+            match fs::symlink_metadata(workdir) {
+                Ok(meta) if meta.file_type().is_symlink() => fs::remove_file(workdir)?,
+                Ok(_) => {
+                    return Err(Error::illegal_argument(format!(
+                        "Working directory [{}] already exists, it must not exist (or be a symlink) to place it on a tmpfs.",
+                        workdir.display()
+                    )))
+                }
+                Err(_) => {}
+            }
+            let name = workdir
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| "workdir".to_string());
+            let dir =
+                Path::new(TMPFS_DIR).join(format!("libaflstar-{}-{name}", std::process::id()));
+            fs::create_dir_all(&dir)?;
+            symlink(&dir, workdir)?;
+            Some(dir)
+        } else {
+            match workdir.exists() {
+                true if workdir.is_dir() => {}
+                true => {
+                    return Err(Error::illegal_argument(format!(
+                        "Working directory [{}] is a file that already exists.",
+                        workdir.display()
+                    )))
+                }
+                false => fs::create_dir_all(workdir)?,
+            }
+            None
+        };
+
+        let mut snapshot = Self {
+            template: template.to_path_buf(),
+            workdir: workdir.to_path_buf(),
+            tmpfs_dir,
+            restore_point,
+            restores: 0,
+        };
+        snapshot.restore()?;
+        Ok(snapshot)
+    }
+
+    /// When this snapshot should be restored
+    pub fn restore_point(&self) -> RestorePoint {
+        self.restore_point
+    }
+
+    /// Number of times the working directory has been restored
+    pub fn restores(&self) -> usize {
+        self.restores
+    }
+
+    /// Restore the working directory to the template.
+    ///
+    /// Everything in the working directory is removed and the template is copied into it.
+    /// The working directory itself is kept, as the target might hold on to it.
+    pub fn restore(&mut self) -> Result<(), Error> {
+        let workdir = self.tmpfs_dir.as_ref().unwrap_or(&self.workdir);
+        clear_dir(workdir)?;
+        copy_dir(&self.template, workdir)?;
+        self.restores += 1;
+        log::debug!("Restored working directory {:?}", self.workdir);
+        Ok(())
+    }
+}
+
+impl Drop for WorkdirSnapshot {
+    fn drop(&mut self) {
+        // don't leave our copies behind in memory
+        if let Some(dir) = self.tmpfs_dir.take() {
+            if let Err(e) = fs::remove_dir_all(&dir) {
+                log::warn!("Could not remove working directory {dir:?} from the tmpfs: {e}");
+            }
+            let _ = fs::remove_file(&self.workdir);
+        }
+    }
+}
+
+/// Remove everything inside `dir`, but not `dir` itself.
+fn clear_dir(dir: &Path) -> Result<(), Error> {
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Recursively copy the contents of `from` into `to`. Symlinks are recreated, not followed.
+fn copy_dir(from: &Path, to: &Path) -> Result<(), Error> {
+    for entry in from.read_dir()? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        if file_type.is_dir() {
+            fs::create_dir(&target)?;
+            fs::set_permissions(&target, entry.metadata()?.permissions())?;
+            copy_dir(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}